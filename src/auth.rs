@@ -1,21 +1,42 @@
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
 use rocket::http::Status;
 use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Json;
+use rocket::State;
 use serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::Surreal;
+use chrono::Utc;
 use log::{error, info, warn}; // Importamos para agregar logs
+use std::net::IpAddr;
+use crate::crud::{get_user_by_username, get_user_record};
+use crate::sessions::{check_refresh_token, create_session, is_session_active, revoke_session, rotate_refresh_token, RefreshCheck};
+use surrealdb::sql::Thing;
+use crate::config::AppConfig;
+use crate::roles::Role;
+use crate::permissions::roles_have_permission;
+
+// Los access tokens son de corta duración; se renuevan con el refresh token de la sesión
+const ACCESS_TOKEN_TTL_SECONDS: i64 = 15 * 60;
+
+// Fallos de PIN o contraseña de un supervisor, dentro de una ventana de 15 minutos, tras los
+// que sus credenciales quedan bloqueadas otros 15 minutos
+const MAX_SUPERVISOR_FAILURES: u32 = 5;
 
 // Estructura que representa los claims del JWT
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,   // Usuario
-    pub role: String,  // Rol
+    pub roles: Vec<Role>, // Roles
     pub exp: usize,    // Expiración
+    pub sid: String,   // Sesión en la tabla `sessions`
 }
 
 // Estructura para representar un usuario autenticado
 pub struct AuthenticatedUser {
     pub username: String,
-    pub role: String,
+    pub roles: Vec<Role>,
+    pub session_id: String,
 }
 
 #[rocket::async_trait]
@@ -30,16 +51,33 @@ impl<'r> FromRequest<'r> for AuthenticatedUser {
             if let Some(token) = header.strip_prefix("Bearer ") {
                 info!("Extracted token: {}", token);
 
+                let Outcome::Success(config) = request.guard::<&State<AppConfig>>().await else {
+                    error!("La configuración de la aplicación no está disponible");
+                    return Outcome::Error((Status::InternalServerError, "Configuration unavailable"));
+                };
+
                 // Decodificar y validar el JWT
-                match validate_jwt(token) {
+                match validate_jwt(token, &config.jwt_secret) {
                     Ok(claims) => {
                         info!(
-                            "Token valid. Username: {}, Role: {}, Exp: {}",
-                            claims.sub, claims.role, claims.exp
+                            "Token valid. Username: {}, Roles: {:?}, Exp: {}",
+                            claims.sub, claims.roles, claims.exp
                         );
+
+                        // Un token firmado no basta: la sesión no debe estar revocada
+                        let Outcome::Success(database) = request.guard::<&State<Surreal<Client>>>().await else {
+                            error!("La base de datos no está disponible para validar la sesión");
+                            return Outcome::Error((Status::InternalServerError, "Database unavailable"));
+                        };
+                        if !is_session_active(database, &claims.sid).await {
+                            warn!("Sesión {} revocada o expirada para {}", claims.sid, claims.sub);
+                            return Outcome::Error((Status::Unauthorized, "Session revoked or expired"));
+                        }
+
                         Outcome::Success(AuthenticatedUser {
                             username: claims.sub,
-                            role: claims.role,
+                            roles: claims.roles,
+                            session_id: claims.sid,
                         })
                     }
                     Err(err) => {
@@ -58,21 +96,255 @@ impl<'r> FromRequest<'r> for AuthenticatedUser {
     }
 }
 
+// Credenciales recibidas en /auth/login
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+// Credenciales de un supervisor presente en la caja; basta la contraseña o el PIN
+#[derive(Deserialize, Debug)]
+pub struct SupervisorCredentials {
+    pub username: String,
+    pub password: Option<String>,
+    pub pin: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct LoginResponse {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+}
+
 // Función para validar el JWT
-fn validate_jwt(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
-    let secret = "secret-key-for-jwt"; // Esta clave debe coincidir con la usada en FastAPI
+fn validate_jwt(token: &str, secret: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
     let validation = Validation::new(Algorithm::HS256);
     let decoding_key = DecodingKey::from_secret(secret.as_ref());
     decode::<Claims>(token, &decoding_key, &validation).map(|data| data.claims)
 }
 
+// Función para firmar un JWT con los mismos claims que valida `validate_jwt`
+pub fn create_jwt(
+    secret: &str,
+    username: &str,
+    roles: &[Role],
+    session_id: &str,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let claims = Claims {
+        sub: username.to_string(),
+        roles: roles.to_vec(),
+        exp: (Utc::now().timestamp() + ACCESS_TOKEN_TTL_SECONDS) as usize,
+        sid: session_id.to_string(),
+    };
+    let encoding_key = EncodingKey::from_secret(secret.as_ref());
+    encode(&Header::new(Algorithm::HS256), &claims, &encoding_key)
+}
+
+fn token_response(
+    config: &AppConfig,
+    username: &str,
+    roles: &[Role],
+    session_id: &str,
+    refresh_token: String,
+) -> Result<Json<LoginResponse>, Status> {
+    let access_token = create_jwt(&config.jwt_secret, username, roles, session_id).map_err(|err| {
+        error!("Error al firmar el JWT: {:?}", err);
+        Status::InternalServerError
+    })?;
+
+    Ok(Json(LoginResponse {
+        access_token,
+        refresh_token,
+        token_type: "Bearer".to_string(),
+        expires_in: ACCESS_TOKEN_TTL_SECONDS,
+    }))
+}
+
+pub async fn login(
+    database: &State<Surreal<Client>>,
+    config: &State<AppConfig>,
+    credentials: Json<LoginRequest>,
+    ip: Option<IpAddr>,
+) -> Result<Json<LoginResponse>, Status> {
+    let credentials = credentials.into_inner();
+
+    let Some(user) = get_user_by_username(database, &credentials.username).await? else {
+        warn!("Intento de inicio de sesión con usuario inexistente: {}", credentials.username);
+        return Err(Status::Unauthorized);
+    };
+
+    match bcrypt::verify(&credentials.password, &user.password) {
+        Ok(true) => {}
+        Ok(false) => {
+            warn!("Contraseña incorrecta para el usuario: {}", credentials.username);
+            return Err(Status::Unauthorized);
+        }
+        Err(err) => {
+            error!("Error al verificar la contraseña de {}: {:?}", credentials.username, err);
+            return Err(Status::InternalServerError);
+        }
+    }
+
+    let (session_id, refresh_token) =
+        create_session(database, user.id.clone(), &user.username, ip.map(|ip| ip.to_string())).await?;
+
+    info!("Inicio de sesión exitoso para el usuario: {}", user.username);
+    token_response(config, &user.username, &user.roles, &session_id, refresh_token)
+}
+
+pub async fn refresh(
+    database: &State<Surreal<Client>>,
+    config: &State<AppConfig>,
+    request: Json<RefreshRequest>,
+) -> Result<Json<LoginResponse>, Status> {
+    let session = match check_refresh_token(database, &request.refresh_token).await? {
+        RefreshCheck::Valid(session) => session,
+        RefreshCheck::Invalid => {
+            warn!("Refresh token inválido o expirado");
+            return Err(Status::Unauthorized);
+        }
+        RefreshCheck::Reused(session_id) => {
+            // Un refresh token ya rotado volvió a usarse: se asume robado y se cierra la sesión
+            warn!("Reutilización de refresh token detectada en {}; revocando la sesión", session_id);
+            revoke_session(database, session_id, "system:token-reuse").await?;
+            return Err(Status::Unauthorized);
+        }
+    };
+
+    // Se vuelve a leer el usuario para reflejar cambios de rol o eliminaciones
+    let Some(user) = get_user_record(database, session.user.clone()).await? else {
+        warn!("El usuario {} de la sesión {} ya no existe", session.username, session.id);
+        revoke_session(database, session.id, "system:user-deleted").await?;
+        return Err(Status::Unauthorized);
+    };
+
+    let session_key = session.id.id.to_raw();
+    let Some(refresh_token) = rotate_refresh_token(database, session.id.clone(), &request.refresh_token).await? else {
+        // Otra renovación con el mismo token ganó la carrera: se trata como reutilización
+        warn!("Reutilización de refresh token detectada en {}; revocando la sesión", session.id);
+        revoke_session(database, session.id, "system:token-reuse").await?;
+        return Err(Status::Unauthorized);
+    };
+
+    info!("Tokens renovados para el usuario: {}", user.username);
+    token_response(config, &user.username, &user.roles, &session_key, refresh_token)
+}
+
+// Verifica las credenciales y que el usuario tenga `permission`; devuelve su nombre de usuario
+pub async fn verify_supervisor(
+    database: &State<Surreal<Client>>,
+    credentials: &SupervisorCredentials,
+    permission: &str,
+) -> Result<String, Status> {
+    let Some(user) = get_user_by_username(database, &credentials.username).await? else {
+        warn!("Supervisor inexistente: {}", credentials.username);
+        return Err(Status::Forbidden);
+    };
+    if supervisor_locked(database, &user.username).await? {
+        warn!("Credenciales de supervisor bloqueadas temporalmente para {}", user.username);
+        return Err(Status::TooManyRequests);
+    }
+
+    let verified = match (&credentials.pin, &credentials.password, &user.pin) {
+        (Some(pin), _, Some(pin_hash)) => bcrypt::verify(pin, pin_hash),
+        (None, Some(password), _) => bcrypt::verify(password, &user.password),
+        _ => Ok(false),
+    };
+    match verified {
+        Ok(true) => clear_supervisor_failures(database, &user.username).await,
+        Ok(false) => {
+            warn!("Credenciales de supervisor incorrectas para {}", credentials.username);
+            record_supervisor_failure(database, &user.username).await?;
+            return Err(Status::Forbidden);
+        }
+        Err(err) => {
+            error!("Error al verificar las credenciales de {}: {:?}", credentials.username, err);
+            return Err(Status::InternalServerError);
+        }
+    }
+
+    if !roles_have_permission(database, &user.roles, permission).await? {
+        warn!("{} no tiene el permiso {} para autorizar", user.username, permission);
+        return Err(Status::Forbidden);
+    }
+    Ok(user.username)
+}
+
+async fn supervisor_locked(database: &State<Surreal<Client>>, username: &str) -> Result<bool, Status> {
+    let query = "SELECT VALUE locked_until > time::now() FROM ONLY type::thing('supervisor_attempts', $username);";
+    match database.query(query).bind(("username", username.to_string())).await {
+        Ok(mut results) => results.take::<Option<bool>>(0).map(|locked| locked.unwrap_or(false)).map_err(|err| {
+            error!("Error al deserializar los intentos de {}: {:?}", username, err);
+            Status::InternalServerError
+        }),
+        Err(err) => {
+            error!("Error al consultar los intentos de {}: {:?}", username, err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// Cuenta el fallo en la ventana vigente (o abre una nueva) y bloquea al llegar al máximo
+async fn record_supervisor_failure(database: &State<Surreal<Client>>, username: &str) -> Result<(), Status> {
+    let query = "UPSERT type::thing('supervisor_attempts', $username) SET
+            failures = IF window_start > time::now() - 15m { failures + 1 } ELSE { 1 },
+            window_start = IF window_start > time::now() - 15m { window_start } ELSE { time::now() },
+            locked_until = IF failures >= $max { time::now() + 15m } ELSE { locked_until }
+        RETURN VALUE failures;";
+
+    let failures = match database
+        .query(query)
+        .bind(("username", username.to_string()))
+        .bind(("max", MAX_SUPERVISOR_FAILURES))
+        .await
+    {
+        Ok(mut results) => results.take::<Option<u32>>(0).map_err(|err| {
+            error!("Error al deserializar el intento fallido de {}: {:?}", username, err);
+            Status::InternalServerError
+        })?,
+        Err(err) => {
+            error!("Error al registrar el intento fallido de {}: {:?}", username, err);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    if let Some(failures) = failures.filter(|failures| *failures >= MAX_SUPERVISOR_FAILURES) {
+        warn!("{} fallos de credenciales de supervisor para {}; bloqueadas 15 minutos", failures, username);
+    }
+    Ok(())
+}
+
+async fn clear_supervisor_failures(database: &State<Surreal<Client>>, username: &str) {
+    let query = "DELETE type::thing('supervisor_attempts', $username);";
+    match database.query(query).bind(("username", username.to_string())).await.map(|response| response.check().err()) {
+        Ok(None) => {}
+        Ok(Some(err)) | Err(err) => error!("Error al limpiar los intentos fallidos de {}: {:?}", username, err),
+    }
+}
+
+pub async fn logout(
+    database: &State<Surreal<Client>>,
+    user: AuthenticatedUser,
+) -> Result<Status, Status> {
+    let session_id = Thing::from(("sessions", user.session_id.as_str()));
+    revoke_session(database, session_id, &user.username).await
+}
+
 impl AuthenticatedUser {
-    pub fn has_role(&self, role: &str) -> bool {
-        self.role == role
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role)
     }
 
     pub fn is_admin(&self) -> bool {
-        self.role == "admin"
+        self.has_role(Role::Admin)
     }
 }
 
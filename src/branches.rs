@@ -0,0 +1,322 @@
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::Surreal;
+use log::{info, warn, error};
+use chrono_tz::Tz;
+use crate::config::AppConfig;
+
+// Datos de una sucursal que salen en sus recibos. `code` es el ID del registro y el mismo
+// código que usan `branch` en Rocket.toml y el campo `branch` de los usuarios.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Branch {
+    pub code: String,
+    pub name: String,
+    pub business_name: String,
+    // Número RUC con que factura la sucursal
+    #[serde(default)]
+    pub ruc: String,
+    #[serde(default)]
+    pub address: String,
+    #[serde(default)]
+    pub phone: String,
+    // Ruta local o URL de la imagen del logo
+    #[serde(default)]
+    pub logo: Option<String>,
+    #[serde(default)]
+    pub footer_messages: Vec<String>,
+    #[serde(default)]
+    pub return_policy: String,
+}
+
+impl Branch {
+    // Sucursal sin datos guardados: los textos que llevaban los recibos antes de esta tabla
+    pub fn fallback(code: &str) -> Branch {
+        Branch {
+            code: code.to_string(),
+            name: "Sucursal Reparto Serrano".to_string(),
+            business_name: "Choi Taekwondo".to_string(),
+            ruc: String::new(),
+            address: String::new(),
+            phone: String::new(),
+            logo: None,
+            footer_messages: Vec::new(),
+            return_policy: String::new(),
+        }
+    }
+}
+
+// Plantilla de los recibos, una sola para todas las sucursales. Las líneas aceptan los campos
+// {business_name}, {branch_name}, {ruc}, {address}, {phone}, {cashier} e {invoice_number};
+// una línea cuyos campos quedan todos vacíos no se imprime.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReceiptTemplate {
+    pub header_lines: Vec<String>,
+    #[serde(default)]
+    pub footer_lines: Vec<String>,
+    #[serde(default = "default_true")]
+    pub show_return_policy: bool,
+    #[serde(default = "default_true")]
+    pub show_qr: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for ReceiptTemplate {
+    fn default() -> Self {
+        ReceiptTemplate {
+            header_lines: vec![
+                "{branch_name}".to_string(),
+                "RUC {ruc}".to_string(),
+                "{address}".to_string(),
+                "Tel. {phone}".to_string(),
+            ],
+            footer_lines: Vec::new(),
+            show_return_policy: true,
+            show_qr: true,
+        }
+    }
+}
+
+// Reemplaza los campos `{nombre}` de la línea. Los nombres desconocidos se dejan tal cual.
+pub fn render_line(line: &str, fields: &[(&str, &str)]) -> Option<String> {
+    let mut rendered = String::with_capacity(line.len());
+    let mut rest = line;
+    let mut has_fields = false;
+    let mut has_values = false;
+
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let field = after.find('}').and_then(|end| {
+            fields.iter().find(|(name, _)| *name == &after[..end]).map(|(_, value)| (end, *value))
+        });
+        match field {
+            Some((end, value)) => {
+                has_fields = true;
+                has_values |= !value.trim().is_empty();
+                rendered.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = after;
+            }
+        }
+    }
+    rendered.push_str(rest);
+
+    if has_fields && !has_values {
+        None
+    } else {
+        Some(rendered)
+    }
+}
+
+// Sucursal y plantilla con que se arma un recibo
+#[derive(Debug, Clone)]
+pub struct ReceiptBranding {
+    pub branch: Branch,
+    pub template: ReceiptTemplate,
+    // Zona horaria del negocio, en la que se imprime la fecha del recibo
+    pub timezone: Tz,
+}
+
+impl ReceiptBranding {
+    fn render_lines(&self, lines: &[String], cashier: &str, invoice_number: &str) -> Vec<String> {
+        let branch = &self.branch;
+        let fields = [
+            ("business_name", branch.business_name.as_str()),
+            ("branch_name", branch.name.as_str()),
+            ("ruc", branch.ruc.as_str()),
+            ("address", branch.address.as_str()),
+            ("phone", branch.phone.as_str()),
+            ("cashier", cashier),
+            ("invoice_number", invoice_number),
+        ];
+        lines.iter().filter_map(|line| render_line(line, &fields)).collect()
+    }
+
+    pub fn header_lines(&self, cashier: &str, invoice_number: &str) -> Vec<String> {
+        self.render_lines(&self.template.header_lines, cashier, invoice_number)
+    }
+
+    // Líneas de la plantilla seguidas de los mensajes propios de la sucursal
+    pub fn footer_lines(&self, cashier: &str, invoice_number: &str) -> Vec<String> {
+        let mut lines = self.render_lines(&self.template.footer_lines, cashier, invoice_number);
+        lines.extend(self.branch.footer_messages.iter().filter(|message| !message.trim().is_empty()).cloned());
+        lines
+    }
+
+    pub fn return_policy(&self) -> Option<String> {
+        Some(self.branch.return_policy.clone()).filter(|policy| self.template.show_return_policy && !policy.trim().is_empty())
+    }
+}
+
+const BRANCH_FIELDS: &str = "code, name, business_name, ruc, address, phone, logo, footer_messages, return_policy";
+
+// Sucursal `code`; si no está registrada se usa la de este servidor y, si tampoco, los textos de siempre
+pub async fn branch_branding(
+    database: &Surreal<Client>,
+    config: &AppConfig,
+    code: &str,
+) -> Result<ReceiptBranding, Status> {
+    let query = format!(
+        "SELECT {fields} FROM ONLY type::thing('branches', $code);
+        SELECT {fields} FROM ONLY type::thing('branches', $default_code);
+        SELECT header_lines, footer_lines, show_return_policy, show_qr FROM ONLY settings:receipt_template;",
+        fields = BRANCH_FIELDS
+    );
+
+    let mut results = database
+        .query(query)
+        .bind(("code", code.to_string()))
+        .bind(("default_code", config.branch.clone()))
+        .await
+        .map_err(|err| {
+            error!("Error al consultar los datos de la sucursal {}: {:?}", code, err);
+            Status::InternalServerError
+        })?;
+
+    let branch = results.take::<Option<Branch>>(0);
+    let default_branch = results.take::<Option<Branch>>(1);
+    let template = results.take::<Option<ReceiptTemplate>>(2);
+    match (branch, default_branch, template) {
+        (Ok(branch), Ok(default_branch), Ok(template)) => Ok(ReceiptBranding {
+            branch: branch.or(default_branch).unwrap_or_else(|| Branch::fallback(&config.branch)),
+            template: template.unwrap_or_default(),
+            timezone: config.timezone,
+        }),
+        (Err(err), _, _) | (_, Err(err), _) | (_, _, Err(err)) => {
+            error!("Error al deserializar los datos de la sucursal {}: {:?}", code, err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// Datos de la sucursal del cajero; los usuarios sin sucursal usan la de este servidor
+pub async fn receipt_branding(
+    database: &State<Surreal<Client>>,
+    config: &AppConfig,
+    cashier: &str,
+) -> Result<ReceiptBranding, Status> {
+    let code = match database
+        .query("SELECT VALUE branch FROM users WHERE username = $username LIMIT 1;")
+        .bind(("username", cashier.to_string()))
+        .await
+    {
+        Ok(mut results) => results.take::<Option<String>>(0).unwrap_or_else(|err| {
+            warn!("No se pudo leer la sucursal del usuario {}: {:?}", cashier, err);
+            None
+        }),
+        Err(err) => {
+            error!("Error al consultar la sucursal del usuario {}: {:?}", cashier, err);
+            return Err(Status::InternalServerError);
+        }
+    };
+    let code = code.map(|code| code.trim().to_string()).filter(|code| !code.is_empty()).unwrap_or_else(|| config.branch.clone());
+    branch_branding(database, config, &code).await
+}
+
+pub async fn get_branches(database: &State<Surreal<Client>>) -> Result<Json<Vec<Branch>>, Status> {
+    let query = format!("SELECT {} FROM branches ORDER BY code;", BRANCH_FIELDS);
+    match database.query(query).await {
+        Ok(mut results) => results.take::<Vec<Branch>>(0).map(Json).map_err(|err| {
+            error!("Error al deserializar las sucursales: {:?}", err);
+            Status::InternalServerError
+        }),
+        Err(err) => {
+            error!("Error al consultar las sucursales: {:?}", err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// Crea o corrige una sucursal; el código es el ID del registro
+pub async fn set_branch(
+    database: &State<Surreal<Client>>,
+    new_branch: Json<Branch>,
+) -> Result<Status, Status> {
+    let mut branch = new_branch.into_inner();
+    branch.code = branch.code.trim().to_string();
+
+    if branch.code.is_empty() || branch.name.trim().is_empty() || branch.business_name.trim().is_empty() {
+        warn!("Sucursal sin código, nombre o razón social");
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let result = database
+        .query("UPSERT type::thing('branches', $code) CONTENT $branch;")
+        .bind(("code", branch.code.clone()))
+        .bind(("branch", branch.clone()))
+        .await;
+
+    match result.map(|response| response.check().err()) {
+        Ok(None) => {
+            info!("Sucursal '{}' guardada", branch.code);
+            Ok(Status::Ok)
+        }
+        Ok(Some(err)) | Err(err) => {
+            error!("Error al guardar la sucursal: {:?}", err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+pub async fn get_receipt_template(database: &State<Surreal<Client>>) -> Result<Json<ReceiptTemplate>, Status> {
+    let query = "SELECT header_lines, footer_lines, show_return_policy, show_qr FROM ONLY settings:receipt_template;";
+    match database.query(query).await {
+        Ok(mut results) => results
+            .take::<Option<ReceiptTemplate>>(0)
+            .map(|template| Json(template.unwrap_or_default()))
+            .map_err(|err| {
+                error!("Error al deserializar la plantilla del recibo: {:?}", err);
+                Status::InternalServerError
+            }),
+        Err(err) => {
+            error!("Error al consultar la plantilla del recibo: {:?}", err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+pub async fn set_receipt_template(
+    database: &State<Surreal<Client>>,
+    template: Json<ReceiptTemplate>,
+) -> Result<Status, Status> {
+    let template = template.into_inner();
+
+    let result = database
+        .query("UPSERT settings:receipt_template CONTENT $template;")
+        .bind(("template", template))
+        .await;
+
+    match result.map(|response| response.check().err()) {
+        Ok(None) => {
+            info!("Plantilla del recibo actualizada");
+            Ok(Status::Ok)
+        }
+        Ok(Some(err)) | Err(err) => {
+            error!("Error al guardar la plantilla del recibo: {:?}", err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_line_fills_fields_and_drops_empty_lines() {
+        let fields = [("ruc", "J0310000000001"), ("phone", "")];
+        assert_eq!(render_line("RUC {ruc}", &fields).as_deref(), Some("RUC J0310000000001"));
+        assert_eq!(render_line("Tel. {phone}", &fields), None);
+        assert_eq!(render_line("Gracias {cliente}", &fields).as_deref(), Some("Gracias {cliente}"));
+        assert_eq!(render_line("Sin campos", &fields).as_deref(), Some("Sin campos"));
+    }
+}
@@ -0,0 +1,63 @@
+use rocket::http::Status;
+use log::warn;
+use chrono::{DateTime, Days, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use surrealdb::sql::Datetime;
+
+// En la base las fechas se guardan como `datetime` en UTC. Los días y horas que ve el
+// negocio se calculan con la zona horaria configurada (`timezone` en Rocket.toml).
+
+// Formato con que se muestran las fechas de las ventas, el mismo que se guardaba como texto
+pub const DISPLAY_FORMAT: &str = "%d-%m-%y %H:%M";
+
+// Fecha `dd-mm-YYYY` recibida en un filtro o reporte
+pub fn parse_day(value: &str) -> Result<NaiveDate, Status> {
+    NaiveDate::parse_from_str(value.trim(), "%d-%m-%Y").map_err(|_| {
+        warn!("Fecha inválida: '{}'", value);
+        Status::BadRequest
+    })
+}
+
+pub fn today(timezone: Tz) -> NaiveDate {
+    Utc::now().with_timezone(&timezone).date_naive()
+}
+
+pub fn format_local(timezone: Tz, datetime: &DateTime<Utc>, format: &str) -> String {
+    datetime.with_timezone(&timezone).format(format).to_string()
+}
+
+// Primer instante del día en la zona del negocio; con horario de verano se toma el más temprano
+fn start_of_day(timezone: Tz, day: NaiveDate) -> Result<DateTime<Utc>, Status> {
+    let midnight = day.and_hms_opt(0, 0, 0).ok_or(Status::BadRequest)?;
+    timezone
+        .from_local_datetime(&midnight)
+        .earliest()
+        .map(|datetime| datetime.with_timezone(&Utc))
+        .ok_or(Status::BadRequest)
+}
+
+// Intervalo `[desde, hasta)` que cubre los días `start..=end` completos. Se devuelve como
+// `Datetime` de SurrealDB: un `DateTime` de chrono viajaría como texto y no se compararía bien.
+pub fn day_range(timezone: Tz, start: NaiveDate, end: NaiveDate) -> Result<(Datetime, Datetime), Status> {
+    if end < start {
+        warn!("Rango de fechas invertido: {} a {}", start, end);
+        return Err(Status::BadRequest);
+    }
+    let next_day = end.checked_add_days(Days::new(1)).ok_or(Status::BadRequest)?;
+    Ok((start_of_day(timezone, start)?.into(), start_of_day(timezone, next_day)?.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn day_range_covers_whole_local_days() {
+        let managua: Tz = "America/Managua".parse().unwrap();
+        let day = NaiveDate::from_ymd_opt(2024, 12, 31).unwrap();
+        let (from, to) = day_range(managua, day, day).unwrap();
+        assert_eq!(from.0.to_rfc3339(), "2024-12-31T06:00:00+00:00");
+        assert_eq!(to.0.to_rfc3339(), "2025-01-01T06:00:00+00:00");
+        assert!(day_range(managua, day, day.pred_opt().unwrap()).is_err());
+    }
+}
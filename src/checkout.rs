@@ -0,0 +1,218 @@
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::{RecordId, Surreal};
+use log::{info, warn, error};
+use chrono::Utc;
+use crate::crud_sales::{is_insufficient_stock, run_sale_transaction, NewSale, NumberedSale, SaleStatus, ProductWithQuantity, SaleItem, CREATE_NUMBERED_SALE, DECREMENT_STOCK};
+use crate::pricing::{cart_quantities, check_client_amount, compute_totals, find_discount, price_lines, rate_tenders, sale_items, tenders_summary, Tender};
+use crate::exchange_rates::{current_rates, normalize_currency};
+use crate::money::Money;
+use crate::config::AppConfig;
+use crate::branches::receipt_branding;
+use crate::business_time;
+use crate::mail::queue_receipt_mail;
+use crate::promos::{is_discount_exhausted, DiscountCart, Redemption, REDEEM_DISCOUNT};
+use crate::repository::parse_record_id;
+use crate::receipts::{build_receipt, receipt_items, PaymentInfo, ReceiptJson, ReceiptTotals};
+
+// Carrito completo enviado por la caja en POST /cashier/checkout
+#[derive(Deserialize, Debug)]
+pub struct CheckoutRequest {
+    pub products: Vec<ProductWithQuantity>,
+    pub customer: Option<String>,
+    #[serde(default)]
+    pub promocode: String,
+    // Moneda elegida por el cliente; en ella se cobran los precios y se da el vuelto
+    pub currency: String,
+    // Una o varias formas de pago; el vuelto sale solo del efectivo
+    pub tenders: Vec<Tender>,
+    // Montos calculados por la caja; si vienen, deben coincidir con los del servidor
+    pub total_paid: Option<Money>,
+    pub change: Option<Money>,
+    // Enviar el recibo por correo: a `receipt_email` o, sin ella, al correo del cliente de `customer`
+    #[serde(default)]
+    pub email_receipt: bool,
+    #[serde(default)]
+    pub receipt_email: Option<String>,
+}
+
+// Parámetros de la transacción del checkout
+#[derive(Serialize, Clone)]
+struct CheckoutBindings {
+    items: Vec<SaleItem>,
+    sale: NewSale,
+    redemption: Option<Redemption>,
+}
+
+// Registra la venta, descuenta el inventario y cuenta el uso del código promocional en una
+// sola transacción, y devuelve su recibo
+pub async fn checkout(
+    database: &State<Surreal<Client>>,
+    config: &AppConfig,
+    cashier: &str,
+    shift: Option<RecordId>,
+    request: Json<CheckoutRequest>,
+) -> Result<Json<ReceiptJson>, Status> {
+    let mut request = request.into_inner();
+    request.currency = normalize_currency(&request.currency);
+    let quantities = cart_quantities(&request.products)?;
+
+    let mut currencies: Vec<String> = request.tenders.iter().map(|tender| tender.currency.clone()).collect();
+    currencies.push(request.currency.clone());
+    let rates = current_rates(database, config, currencies).await?;
+    let tenders = rate_tenders(request.tenders, &rates)?;
+    let lines = price_lines(database, quantities, &rates, &request.currency).await?;
+
+    // Primera validación de stock; la transacción vuelve a comprobarlo de forma atómica
+    for line in &lines {
+        if let Some(available) = line.available {
+            if available < line.quantity {
+                warn!(
+                    "Stock insuficiente para el producto {}: disponible {}, requerido {}",
+                    line.product, available, line.quantity
+                );
+                return Err(Status::BadRequest);
+            }
+        }
+    }
+
+    let cart = DiscountCart {
+        lines: &lines,
+        customer: request.customer.as_deref(),
+        today: business_time::today(config.timezone),
+        rates: &rates,
+        currency: &request.currency,
+    };
+    let discount = find_discount(database, &request.promocode, &cart).await?;
+    let totals = compute_totals(&lines, discount.as_ref(), &tenders, &rates, &request.currency)?;
+    check_client_amount("total", request.total_paid, totals.total)?;
+    check_client_amount("vuelto", request.change, totals.change)?;
+
+    let items: Vec<SaleItem> = sale_items(&lines, discount.as_ref(), &totals);
+    let redemption = Redemption::new(discount.as_ref(), request.customer.as_deref());
+    // Antes de la transacción, para no dejar una venta registrada sin recibo
+    let branding = receipt_branding(database, config, cashier).await?;
+    let receipt_recipient = if request.email_receipt {
+        receipt_recipient(database, request.receipt_email.take(), request.customer.as_deref()).await
+    } else {
+        None
+    };
+
+    let (type_, payment_ref) = tenders_summary(&tenders);
+    let sale = NewSale {
+        status: SaleStatus::Completed,
+        products: items.iter().map(|item| item.product.clone()).collect(),
+        items: items.clone(),
+        subtotal: totals.subtotal,
+        discount: totals.discount,
+        tax: totals.tax,
+        total_paid: totals.total,
+        amount_tendered: totals.amount_tendered,
+        change: totals.change,
+        tenders,
+        customer: request.customer.unwrap_or_default(),
+        cashier: cashier.to_string(),
+        promocode: request.promocode,
+        payment_ref,
+        date: Utc::now().into(),
+        type_,
+        exchange_rate: rates.rate(&request.currency)?,
+        base_currency: rates.base_currency,
+        currency: request.currency,
+        branch: config.branch.clone(),
+        shift,
+    };
+
+    let query = format!(
+        "BEGIN TRANSACTION;
+        {}
+        {}
+        {}
+        COMMIT TRANSACTION;",
+        DECREMENT_STOCK, CREATE_NUMBERED_SALE, REDEEM_DISCOUNT
+    );
+
+    let bindings = CheckoutBindings { items, sale: sale.clone(), redemption };
+    let (mut response, errors) = run_sale_transaction(database, &query, bindings).await?;
+    if let Some(err) = errors.values().find(|err| is_insufficient_stock(err)) {
+        warn!("Checkout cancelado: {}", err);
+        return Err(Status::BadRequest);
+    }
+    if let Some(err) = errors.values().find(|err| is_discount_exhausted(err)) {
+        warn!("Checkout cancelado: {}", err);
+        return Err(Status::UnprocessableEntity);
+    }
+    if !errors.is_empty() {
+        error!("Error en la transacción del checkout: {:?}", errors);
+        return Err(Status::InternalServerError);
+    }
+
+    // `DECREMENT_STOCK` es la sentencia 0; `CREATE_NUMBERED_SALE` devuelve la venta en la 4
+    let created = match response.take::<Option<NumberedSale>>(4) {
+        Ok(Some(created)) => created,
+        Ok(None) => {
+            error!("El checkout no devolvió el ID de la venta");
+            return Err(Status::InternalServerError);
+        }
+        Err(err) => {
+            error!("Error al deserializar el ID de la venta: {:?}", err);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    info!("Venta {} ({}) registrada por {} con {} líneas", created.id, created.invoice_number, cashier, lines.len());
+
+    let receipt = build_receipt(
+        &branding,
+        sale.cashier,
+        PaymentInfo {
+            method: sale.type_,
+            payment_ref: sale.payment_ref,
+            promocode: sale.promocode,
+            tenders: sale.tenders,
+        },
+        receipt_items(&sale.items),
+        ReceiptTotals {
+            subtotal: totals.subtotal,
+            discount: totals.discount,
+            tax: totals.tax,
+            total: totals.total,
+            change: totals.change,
+            currency: sale.currency,
+        },
+        Some(created.id),
+        Some(created.invoice_number),
+    );
+    if let Some(recipient) = receipt_recipient {
+        queue_receipt_mail(database, config, &receipt, &recipient).await;
+    }
+    Ok(Json(receipt))
+}
+
+// Correo al que se envía el recibo; un cliente sin correo deja la venta sin envío
+async fn receipt_recipient(
+    database: &State<Surreal<Client>>,
+    receipt_email: Option<String>,
+    customer: Option<&str>,
+) -> Option<String> {
+    if let Some(email) = receipt_email.filter(|email| !email.trim().is_empty()) {
+        return Some(email);
+    }
+    let client = parse_record_id("clients", customer?).ok()?;
+    match database.query("SELECT VALUE email FROM ONLY $client;").bind(("client", client.clone())).await {
+        Ok(mut result) => {
+            let email = result.take::<Option<String>>(0).ok().flatten().filter(|email| !email.trim().is_empty());
+            if email.is_none() {
+                warn!("El cliente {} no tiene correo; el recibo no se envía", client);
+            }
+            email
+        }
+        Err(err) => {
+            error!("Error al consultar el correo del cliente {}: {:?}", client, err);
+            None
+        }
+    }
+}
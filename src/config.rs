@@ -0,0 +1,111 @@
+use rocket::figment::Figment;
+use serde::Deserialize;
+use chrono_tz::Tz;
+use std::collections::HashMap;
+use crate::printer::{PrinterConfig, PrinterConnection};
+use crate::mail::{parse_mailbox, MailConfig};
+
+// Configuración de la aplicación, leída del figment de Rocket (Rocket.toml y variables `ROCKET_*`)
+#[derive(Deserialize, Clone)]
+pub struct AppConfig {
+    pub jwt_secret: String,
+    #[serde(default = "default_db_url")]
+    pub db_url: String,
+    #[serde(default = "default_db_username")]
+    pub db_username: String,
+    pub db_password: String,
+    #[serde(default = "default_db_name")]
+    pub db_namespace: String,
+    #[serde(default = "default_db_name")]
+    pub db_database: String,
+    // Moneda en que están los precios del inventario y en la que se consolidan los reportes
+    #[serde(default = "default_base_currency")]
+    pub base_currency: String,
+    // Código de la sucursal que atiende este servidor; queda en cada venta y en sus reportes
+    #[serde(default = "default_branch")]
+    pub branch: String,
+    // Zona horaria del negocio (nombre IANA); define qué es "hoy" en ventas, tasas y reportes
+    #[serde(default = "default_timezone")]
+    pub timezone: Tz,
+    // Impresora térmica de cada sucursal, por código de sucursal
+    #[serde(default)]
+    pub printers: HashMap<String, PrinterConfig>,
+    // Servidor SMTP para recibos y estados de cuenta; sin él no se envían correos
+    #[serde(default)]
+    pub mail: Option<MailConfig>,
+    #[serde(default)]
+    pub mail_password: Option<String>,
+}
+
+fn default_db_url() -> String {
+    "ws://127.0.0.1:8080".to_string()
+}
+
+fn default_db_username() -> String {
+    "root".to_string()
+}
+
+fn default_db_name() -> String {
+    "central-choi".to_string()
+}
+
+fn default_base_currency() -> String {
+    "NIO".to_string()
+}
+
+fn default_branch() -> String {
+    "RS".to_string()
+}
+
+fn default_timezone() -> Tz {
+    chrono_tz::America::Managua
+}
+
+impl AppConfig {
+    pub fn from_figment(figment: &Figment) -> Result<AppConfig, String> {
+        let config: AppConfig = figment
+            .extract()
+            .map_err(|err| format!("Configuración inválida: {}", err))?;
+
+        // Los secretos no pueden quedar vacíos aunque la llave exista
+        if config.jwt_secret.trim().is_empty() {
+            return Err("`jwt_secret` no puede estar vacío (ROCKET_JWT_SECRET)".to_string());
+        }
+        if config.db_password.is_empty() {
+            return Err("`db_password` no puede estar vacío (ROCKET_DB_PASSWORD)".to_string());
+        }
+
+        if config.base_currency.trim().is_empty() {
+            return Err("`base_currency` no puede estar vacío (ROCKET_BASE_CURRENCY)".to_string());
+        }
+        if config.branch.trim().is_empty() {
+            return Err("`branch` no puede estar vacío (ROCKET_BRANCH)".to_string());
+        }
+
+        for (branch, printer) in &config.printers {
+            let missing = match &printer.connection {
+                PrinterConnection::Network { host, .. } => host.trim().is_empty(),
+                PrinterConnection::File { path } => path.trim().is_empty(),
+                PrinterConnection::Usb { .. } => false,
+            };
+            if missing || printer.columns == 0 {
+                return Err(format!("La impresora de la sucursal `{}` está incompleta (printers.{})", branch, branch));
+            }
+        }
+
+        if let Some(mail) = &config.mail {
+            if mail.host.trim().is_empty() || mail.max_attempts == 0 {
+                return Err("La configuración de correo está incompleta (mail.host, mail.max_attempts)".to_string());
+            }
+            if let Err(err) = parse_mailbox(&mail.from) {
+                return Err(format!("`mail.from` inválido: {}", err));
+            }
+            let valid_day = |day: Option<u32>| day.is_none_or(|day| (1..=28).contains(&day));
+            if !valid_day(mail.statement_day) || !valid_day(mail.reminder_day) {
+                return Err("`mail.statement_day` y `mail.reminder_day` deben estar entre 1 y 28".to_string());
+            }
+        }
+
+        Ok(config)
+    }
+}
@@ -4,38 +4,48 @@ use rocket::serde::json::Json;
 use rocket::State;
 use serde::Deserialize;
 use serde::Serialize;
-use std::collections::HashMap;
 use surrealdb::engine::remote::ws::Client;
 use surrealdb::Surreal; // Asegúrate de que estás importando el cliente correcto
 use log::{info, error}; 
 use serde::Deserializer;
 use surrealdb::sql::Thing;
+use crate::roles::{deserialize_requested_roles, deserialize_roles, Role};
+use crate::repository::{create_record, delete_record, find_one_by, merge_record, parse_record_id};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct User {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub id: Option<Thing>,         // Convertido a String
     pub fullname: String,
-    pub roles: String,
+    #[serde(deserialize_with = "deserialize_requested_roles")]
+    pub roles: Vec<Role>,
     pub username: String,
     pub password: String,
     pub branch: String,
+    // PIN numérico para autorizar anulaciones desde la caja; se guarda hasheado
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pin: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct UserAsRecord {
     pub id: Thing,          // Se obtiene como Thing desde la base de datos
     pub fullname: String,
-    pub roles: String,
+    #[serde(deserialize_with = "deserialize_roles")]
+    pub roles: Vec<Role>,
     pub username: String,
     pub password: String,
     pub branch: String,
+    #[serde(default)]
+    pub pin: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct UserForQuery {
     pub id: Thing,
     pub fullname: String,
-    pub roles: String,
+    #[serde(deserialize_with = "deserialize_roles")]
+    pub roles: Vec<Role>,
     pub username: String,
     pub branch: String,
 }
@@ -44,7 +54,7 @@ pub struct UserForQuery {
 pub struct UserAsString {
     pub id: String,
     pub fullname: String,
-    pub roles: String,
+    pub roles: Vec<Role>,
     pub username: String,
     pub branch: String,
 }
@@ -85,77 +95,69 @@ where
     }
 }
 
+// Solo se serializan los campos presentes para que `MERGE` no borre los demás
 #[derive(Serialize, Deserialize)]
 pub struct UpdateUser {
+    #[serde(skip_serializing_if = "Option::is_none")]
     fullname: Option<String>,
-    roles: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    roles: Option<Vec<Role>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     branch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pin: Option<String>,
+}
+
+// El PIN de supervisor es solo numérico, de 4 a 8 dígitos
+fn is_valid_pin(pin: &str) -> bool {
+    (4..=8).contains(&pin.len()) && pin.chars().all(|c| c.is_ascii_digit())
 }
 
 pub async fn create_user(database: &State<Surreal<Client>>, new_user: Json<User>) -> Result<Status, Status> {
     let mut user = new_user.into_inner();
 
+    if user.roles.is_empty() {
+        error!("El usuario '{}' debe tener al menos un rol.", user.username);
+        return Err(Status::UnprocessableEntity);
+    }
+
     // Verificar si el usuario ya existe
-    let query_check = format!(
-        "SELECT * FROM users WHERE username = '{}';",
-        user.username
-    );
-    let result_check = database.query(&query_check).await;
+    let result_check =
+        find_one_by::<_, _, UserAsRecord>(database.inner(), "users", "username", user.username.clone()).await;
 
-    if let Ok(mut results) = result_check {
-        if let Ok(Some(_)) = results.take::<Option<User>>(0) {
-            error!("El nombre de usuario '{}' ya existe.", user.username);
-            return Err(Status::Conflict);
-        }
+    if let Ok(Some(_)) = result_check {
+        error!("El nombre de usuario '{}' ya existe.", user.username);
+        return Err(Status::Conflict);
     }
 
     // Hashear la contraseña
     let hashed_password = hash(&user.password, DEFAULT_COST).expect("Failed to hash password");
     user.password = hashed_password;
+    if let Some(pin) = &user.pin {
+        if !is_valid_pin(pin) {
+            error!("El PIN del usuario '{}' debe tener de 4 a 8 dígitos.", user.username);
+            return Err(Status::UnprocessableEntity);
+        }
+        user.pin = Some(hash(pin, DEFAULT_COST).map_err(|_| Status::InternalServerError)?);
+    }
+    user.id = None;
 
-    // Crear el usuario con `RETURN *`
-    let query = format!(
-        "CREATE users CONTENT {{
-            fullname: '{}',
-            roles: '{}',
-            username: '{}',
-            password: '{}',
-            branch: '{}'
-        }} RETURN *;",
-        user.fullname, user.roles, user.username, user.password, user.branch
-    );
-
-    info!("Ejecutando el query: {}", query);
-
-    let Ok(mut results) = database.query(&query).await else {
-        error!("Petición a la base de datos falló.");
-        return Err(Status::InternalServerError);
-    };
-
-    // Log de los resultados completos
-    debug!("Resultados del query: {:?}", results);
+    info!("Creando el usuario: {}", user.username);
 
     // Procesar los resultados del query
-    match results.take::<Vec<User>>(0) {
-        Ok(vec) if !vec.is_empty() => {
-            let user = vec[0].clone();
-            let user_id = user
-                .id
-                .as_ref()
-                .map_or_else(|| "Sin ID".to_string(), |id| id.to_string());
-            info!("Usuario creado correctamente: {:?}", vec[0]);
+    match create_record::<_, _, User>(database.inner(), "users", user).await {
+        Ok(Some(user)) => {
+            info!("Usuario creado correctamente: {:?}", user.id);
             Ok(Status::Created)
         }
-        Ok(vec) if vec.is_empty() => {
+        Ok(None) => {
             error!("El resultado del query está vacío.");
             Err(Status::InternalServerError)
         }
-        Ok(_) => {
-            error!("El resultado del query no coincide con el formato esperado");
-            Err(Status::InternalServerError)
-        }
         Err(e) => {
             error!("Error al procesar el resultado del query: {:?}", e);
             Err(Status::InternalServerError)
@@ -163,51 +165,77 @@ pub async fn create_user(database: &State<Surreal<Client>>, new_user: Json<User>
     }
 }
 
+pub async fn get_user_by_username(
+    database: &State<Surreal<Client>>,
+    username: &str,
+) -> Result<Option<UserAsRecord>, Status> {
+    let query = "SELECT * FROM users WHERE username = $username LIMIT 1;";
+
+    match database.query(query).bind(("username", username.to_string())).await {
+        Ok(mut results) => results.take::<Option<UserAsRecord>>(0).map_err(|err| {
+            error!("Error al deserializar el usuario '{}': {:?}", username, err);
+            Status::InternalServerError
+        }),
+        Err(err) => {
+            error!("Error al consultar el usuario '{}': {:?}", username, err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+pub async fn get_user_record(
+    database: &State<Surreal<Client>>,
+    user_id: Thing,
+) -> Result<Option<UserAsRecord>, Status> {
+    match database.query("SELECT * FROM ONLY $user;").bind(("user", user_id.clone())).await {
+        Ok(mut results) => results.take::<Option<UserAsRecord>>(0).map_err(|err| {
+            error!("Error al deserializar el usuario {}: {:?}", user_id, err);
+            Status::InternalServerError
+        }),
+        Err(err) => {
+            error!("Error al consultar el usuario {}: {:?}", user_id, err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
 pub async fn update_user(
     database: &State<Surreal<Client>>,
     user_id: String,
     update_data: Json<UpdateUser>,
 ) -> Result<Status, Status> {
-    let mut updates = HashMap::new();
-    
-    if let Some(fullname) = &update_data.fullname {
-        updates.insert("fullname", fullname.clone());
-    }
-    if let Some(username) = &update_data.username {
-        updates.insert("username", username.clone());
+    let record_id = parse_record_id("users", &user_id)?;
+    let mut patch = update_data.into_inner();
+
+    if patch.fullname.is_none()
+        && patch.roles.is_none()
+        && patch.username.is_none()
+        && patch.password.is_none()
+        && patch.branch.is_none()
+        && patch.pin.is_none()
+    {
+        return Err(Status::BadRequest); // Si no hay campos a actualizar
     }
-    if let Some(branch) = &update_data.branch {
-        updates.insert("branch", branch.clone());
+    if patch.roles.as_ref().is_some_and(|roles| roles.is_empty()) {
+        return Err(Status::UnprocessableEntity);
     }
-    if let Some(password) = &update_data.password {
+    if let Some(password) = &patch.password {
         // Hashear la contraseña
         let hashed_password = hash(password, DEFAULT_COST)
             .map_err(|_| Status::InternalServerError)?; // Manejar error al hashear
-        updates.insert("password", hashed_password); // Insertar contraseña hasheada
+        patch.password = Some(hashed_password); // Guardar contraseña hasheada
     }
-
-    if updates.is_empty() {
-        return Err(Status::BadRequest); // Si no hay campos a actualizar
+    if let Some(pin) = &patch.pin {
+        if !is_valid_pin(pin) {
+            return Err(Status::UnprocessableEntity);
+        }
+        patch.pin = Some(hash(pin, DEFAULT_COST).map_err(|_| Status::InternalServerError)?);
     }
 
-    // Construir la consulta SQL de actualización
-    let update_statements: Vec<String> = updates
-        .iter()
-        .map(|(key, value)| format!("{} = '{}'", key, value))
-        .collect();
-
-    let query = format!(
-        "UPDATE users:{} SET {};",
-        user_id,
-        update_statements.join(", ")
-    );
-
-    info!("Ejecutando query de actualización: {}", query);
+    info!("Actualizando el usuario: {}", record_id);
 
     // Ejecutar la consulta en la base de datos
-    let result = database.query(&query).await;
-
-    match result {
+    match merge_record(database.inner(), record_id, patch).await {
         Ok(_) => Ok(Status::Ok),
         Err(err) => {
             error!("Error al actualizar el usuario: {:?}", err);
@@ -257,9 +285,9 @@ pub async fn delete_user(
     database: &State<Surreal<Client>>,
     user_id: String,
 ) -> Result<Status, Status> {
-    let query = format!("DELETE users:{};", user_id);
+    let record_id = parse_record_id("users", &user_id)?;
 
-    match database.query(&query).await {
+    match delete_record(database.inner(), record_id).await {
         Ok(_) => Ok(Status::Ok),
         Err(err) => {
             error!("Error al eliminar el producto: {:?}", err);
@@ -6,6 +6,10 @@ use surrealdb::engine::remote::ws::Client;
 use surrealdb::Surreal;
 use crate::crud_sales::ProductWithQuantity; // Usa la estructura definida en el inventario
 use log::{info, error};
+use crate::money::Money;
+use crate::repository::{delete_record, merge_record, parse_record_id};
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
 
 
 
@@ -14,7 +18,7 @@ use log::{info, error};
 pub struct Bundle {
     pub name: String,
     pub products: Vec<ProductWithQuantity>, // Productos y cantidades
-    pub discount: Option<f64>,             // Descuento opcional
+    pub discount: Option<f64>,             // Descuento opcional, en porcentaje
 }
 
 pub async fn create_bundle(
@@ -23,6 +27,15 @@ pub async fn create_bundle(
 ) -> Result<Status, Status> {
     let bundle = new_bundle.into_inner();
 
+    let discount = match bundle.discount.map(Decimal::from_f64) {
+        None => Decimal::ZERO,
+        Some(Some(discount)) if discount >= Decimal::ZERO && discount <= Decimal::ONE_HUNDRED => discount,
+        Some(_) => {
+            log::warn!("Descuento inválido para el Bundle '{}': {:?}", bundle.name, bundle.discount);
+            return Err(Status::UnprocessableEntity);
+        }
+    };
+
     // Validar que los productos existan en el inventario
     for product in &bundle.products {
         let product_id = format!("{}", product.id);
@@ -33,43 +46,42 @@ pub async fn create_bundle(
     }
 
     // Calcular el precio total del Bundle (sin descuento)
-    let mut total_price = 0.0;
+    let mut total_price = Money::ZERO;
     for product in &bundle.products {
         let product_data = crate::crud_inventory::get_product_by_id(database, product.id.clone())
             .await
             .map_err(|_| Status::InternalServerError)?;
         if let Some(price) = product_data.price {
-            total_price += price * product.qnt as f64;
+            total_price += price.times(product.qnt);
         }
     }
 
     // Aplicar descuento si es necesario
-    let final_price = if let Some(discount) = bundle.discount {
-        total_price * (1.0 - discount / 100.0)
-    } else {
-        total_price
-    };
+    let final_price = total_price - total_price.percent(discount);
 
     // Guardar el Bundle en la base de datos
-    let query = format!(
-        "CREATE bundles CONTENT {{
-            name: '{}',
-            products: {},
-            discount: {},
-            final_price: {}
-        }};",
-        bundle.name,
-        serde_json::to_string(&bundle.products).unwrap(),
-        bundle.discount.unwrap_or(0.0),
-        final_price
-    );
-
-    match database.query(&query).await {
-        Ok(_) => {
-            log::info!("Bundle '{}' creado correctamente.", bundle.name);
+    let query = "CREATE bundles CONTENT {
+            name: $name,
+            products: $products,
+            discount: $discount,
+            final_price: $final_price
+        };";
+
+    let name = bundle.name.clone();
+    let result = database
+        .query(query)
+        .bind(("name", bundle.name))
+        .bind(("products", bundle.products))
+        .bind(("discount", bundle.discount.unwrap_or(0.0)))
+        .bind(("final_price", final_price))
+        .await;
+
+    match result.map(|response| response.check().err()) {
+        Ok(None) => {
+            log::info!("Bundle '{}' creado correctamente.", name);
             Ok(Status::Created)
         }
-        Err(err) => {
+        Ok(Some(err)) | Err(err) => {
             log::error!("Error al crear el Bundle: {:?}", err);
             Err(Status::InternalServerError)
         }
@@ -98,9 +110,9 @@ pub async fn get_bundle_by_id(
     database: &State<Surreal<Client>>,
     bundle_id: String,
 ) -> Result<Json<Bundle>, Status> {
-    let query = format!("SELECT * FROM bundles WHERE id = '{}';", bundle_id);
+    let record_id = parse_record_id("bundles", &bundle_id)?;
 
-    match database.query(&query).await {
+    match database.query("SELECT * FROM $id;").bind(("id", record_id)).await {
         Ok(mut results) => {
             if let Some(bundle) = results.take::<Vec<Bundle>>(0).ok().and_then(|mut b| b.pop()) {
                 Ok(Json(bundle))
@@ -120,17 +132,10 @@ pub async fn update_bundle(
     bundle_id: String,
     update_data: Json<Bundle>,
 ) -> Result<Status, Status> {
+    let record_id = parse_record_id("bundles", &bundle_id)?;
     let bundle = update_data.into_inner();
 
-    // Serializar los datos actualizados
-    let update_content = serde_json::to_string(&bundle).map_err(|_| Status::BadRequest)?;
-
-    let query = format!(
-        "UPDATE bundles:{} MERGE {};",
-        bundle_id, update_content
-    );
-
-    match database.query(&query).await {
+    match merge_record(database.inner(), record_id, bundle).await {
         Ok(_) => {
             log::info!("Bundle '{}' actualizado correctamente.", bundle_id);
             Ok(Status::Ok)
@@ -146,9 +151,9 @@ pub async fn delete_bundle(
     database: &State<Surreal<Client>>,
     bundle_id: String,
 ) -> Result<Status, Status> {
-    let query = format!("DELETE bundles:{};", bundle_id);
+    let record_id = parse_record_id("bundles", &bundle_id)?;
 
-    match database.query(&query).await {
+    match delete_record(database.inner(), record_id).await {
         Ok(_) => {
             log::info!("Bundle '{}' eliminado correctamente.", bundle_id);
             Ok(Status::Ok)
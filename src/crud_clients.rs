@@ -7,6 +7,7 @@ use surrealdb::Surreal;
 use surrealdb::sql::Thing;
 use log::{info, error};
 use serde_json::Value;
+use crate::repository::{create_record, delete_record, parse_record_id, replace_record};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct NewCliente {
@@ -92,42 +93,14 @@ pub async fn create_client(
     }
 
     // Crear el cliente
-    let query = format!(
-        "CREATE clients CONTENT {{
-            fullname: '{}',
-            is_minor: {},
-            phone: '{}',
-            email: '{}',
-            monthly_pay_ref: '{}',
-            is_preferred: {},
-            schedule: '{}',
-            is_active: {},
-            times: '{}'
-        }} RETURN *;",
-        client.fullname,
-        client.is_minor,
-        client.phone.unwrap_or_default(),
-        client.email.unwrap_or_default(),
-        client.monthly_pay_ref.unwrap_or_default(),
-        client.is_preferred,
-        client.schedule.clone().unwrap_or_default(),
-        client.is_active,
-        client.times.unwrap_or_default()
-    );
-
-    match database.query(&query).await {
-        Ok(mut results) => {
-            match results.take::<Vec<Cliente>>(0) {
-                Ok(vec) if !vec.is_empty() => {
-                    let cliente = vec[0].clone();
-
+    match create_record::<_, _, Cliente>(database.inner(), "clients", client).await {
+        Ok(created) => {
+            match created {
+                Some(cliente) => {
                     // Consultar el id del schedule basado en el nombre proporcionado
                     let schedule_id: Option<Thing> = if let Some(ref schedule_name) = cliente.schedule {
-                        let schedule_query = format!(
-                            "SELECT VALUE id FROM schedules WHERE name = '{}';",
-                            schedule_name
-                        );
-                        match database.query(&schedule_query).await {
+                        let schedule_query = "SELECT VALUE id FROM schedules WHERE name = $name;";
+                        match database.query(schedule_query).bind(("name", schedule_name.clone())).await {
                             Ok(mut res) => {
                                 let result: Option<Thing> = res.take(0).unwrap_or(None);
                                 result
@@ -145,10 +118,9 @@ pub async fn create_client(
                     };
 
                     // Crear el registro en payments
-                    let payment_query = format!(
-                        "CREATE payments CONTENT {{
-                            client_id: {},
-                            months: [{{
+                    let payment_query = "CREATE payments CONTENT {
+                            client_id: $client,
+                            months: [{
                                 Enero: false,
                                 Febrero: false,
                                 Marzo: false,
@@ -161,19 +133,22 @@ pub async fn create_client(
                                 Octubre: false,
                                 Noviembre: false,
                                 Diciembre: false
-                            }}],
-                            schedule: {},
+                            }],
+                            schedule: $schedule,
                             year: 2025
-                        }};",
-                        cliente.id,
-                        schedule_id.map(|id| id.to_string()).unwrap_or_else(|| "null".to_string())
-                    );
+                        };";
+
+                    let result = database
+                        .query(payment_query)
+                        .bind(("client", cliente.id.clone()))
+                        .bind(("schedule", schedule_id))
+                        .await;
 
-                    match database.query(&payment_query).await {
-                        Ok(_) => {
+                    match result.map(|response| response.check().err()) {
+                        Ok(None) => {
                             info!("Registro en payments creado para el cliente: {:?}", cliente.id);
                         }
-                        Err(err) => {
+                        Ok(Some(err)) | Err(err) => {
                             error!(
                                 "Error al crear registro en payments para el cliente: {:?}, error: {:?}",
                                 cliente.id, err
@@ -185,14 +160,10 @@ pub async fn create_client(
                     info!("Cliente creado correctamente: {:?}", cliente);
                     Ok(Status::Created)
                 }
-                Ok(_) => {
+                None => {
                     error!("El resultado del query está vacío.");
                     Err(Status::InternalServerError)
                 }
-                Err(e) => {
-                    error!("Error al procesar el resultado del query: {:?}", e);
-                    Err(Status::InternalServerError)
-                }
             }
         }
         Err(err) => {
@@ -241,33 +212,23 @@ pub async fn update_client(
     client_id: String,
     updated_data: Json<UpdateCliente>,
 ) -> Result<Status, Status> {
+    let record_id = parse_record_id("clients", &client_id)?;
     let client = updated_data.into_inner();
 
-    let query = format!(
-        "UPDATE clients:{} CONTENT {{
-            fullname: '{}',
-            is_minor: {},
-            phone: '{}',
-            email: '{}',
-            monthly_pay_ref: '{}',
-            is_preferred: {},
-            schedule: '{}',
-            is_active: {},
-            times: '{}'
-        }};",
-        client_id,
-        client.fullname.unwrap_or_default(),
-        client.is_minor.unwrap_or_default(),
-        client.phone.unwrap_or_default(),
-        client.email.unwrap_or_default(),
-        client.monthly_pay_ref.unwrap_or_default(),
-        client.is_preferred.unwrap_or_default(),
-        client.schedule.unwrap_or_default(),
-        client.is_active,
-        client.times.unwrap_or_default(),
-    );
+    // La actualización reemplaza el registro completo, igual que la creación
+    let content = NewCliente {
+        fullname: client.fullname.unwrap_or_default(),
+        is_minor: client.is_minor.unwrap_or_default(),
+        phone: client.phone,
+        email: client.email,
+        monthly_pay_ref: client.monthly_pay_ref,
+        is_preferred: client.is_preferred.unwrap_or_default(),
+        schedule: client.schedule,
+        is_active: client.is_active,
+        times: client.times,
+    };
 
-    match database.query(&query).await {
+    match replace_record(database.inner(), record_id, content).await {
         Ok(_) => {
             info!("Cliente actualizado correctamente.");
             Ok(Status::Ok)
@@ -283,9 +244,9 @@ pub async fn delete_client(
     database: &State<Surreal<Client>>, 
     client_id: String,
 ) -> Result<Status, Status> {
-    let query = format!("DELETE {};", client_id);
+    let record_id = parse_record_id("clients", &client_id)?;
 
-    match database.query(&query).await {
+    match delete_record(database.inner(), record_id).await {
         Ok(_) => {
             info!("Cliente eliminado correctamente.");
             Ok(Status::Ok)
@@ -9,25 +9,33 @@ use surrealdb::Surreal;
 use log::{info, error};
 use surrealdb::sql::Thing;
 use std::collections::HashSet;
+use crate::money::Money;
+use crate::taxes::TaxTreatment;
+use crate::repository::{create_record, delete_record, delete_where, find_one_by, merge_record, parse_record_id};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ProductAsRecord {
     id: Thing,
     name: Option<String>,
-    price: Option<f64>,
+    price: Option<Money>,
     bar_code: Option<String>,
     quantity: Option<u32>,
     category: Option<String>,
+    #[serde(default)]
+    tax_treatment: TaxTreatment,
+    tax_rate: Option<String>,
 }
 
 #[derive(Debug, Serialize, Clone)]
 pub struct ProductAsString {
     pub id: String,
     pub name: Option<String>,
-    pub price: Option<f64>,
+    pub price: Option<Money>,
     pub bar_code: Option<String>,
     pub quantity: Option<u32>,
     pub category: Option<String>,
+    pub tax_treatment: TaxTreatment,
+    pub tax_rate: Option<String>,
 }
 
 impl From<ProductAsRecord> for ProductAsString {
@@ -39,6 +47,8 @@ impl From<ProductAsRecord> for ProductAsString {
             bar_code: record.bar_code,
             quantity: record.quantity,
             category: record.category,
+            tax_treatment: record.tax_treatment,
+            tax_rate: record.tax_rate,
         }
     }
 }
@@ -51,19 +61,33 @@ pub struct Category {
 #[derive(Serialize, Deserialize)]
 pub struct Product {
     name: String,
-    price: f64,
+    price: Money,
     bar_code: String,
     quantity: u32,
     category: String,
+    // Sin indicarlo el producto queda exento; `tax_rate` por omisión es el IVA general
+    #[serde(default)]
+    tax_treatment: TaxTreatment,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tax_rate: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct UpdateProduct {
+    #[serde(skip_serializing_if = "Option::is_none")]
     name: Option<String>,
-    price: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    price: Option<Money>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     bar_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     quantity: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     category: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tax_treatment: Option<TaxTreatment>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tax_rate: Option<String>,
 }
 
 pub async fn get_product(database: &State<Surreal<Client>>) -> Result<Json<Vec<ProductAsString>>, Status> {
@@ -104,8 +128,13 @@ pub async fn get_product_by_id(
         id_base: &str,
     ) -> Result<Option<ProductAsRecord>, Status> {
         let thing_id = Thing::from((table, id_base));
-        let query = format!("SELECT * FROM {} WHERE id = $id;", table);
-        match database.query(&query).bind(("id", thing_id)).await {
+        let query = "SELECT * FROM type::table($table) WHERE id = $id;";
+        match database
+            .query(query)
+            .bind(("table", table.to_string()))
+            .bind(("id", thing_id))
+            .await
+        {
             Ok(mut results) => {
                 if let Ok(Some(record)) = results.take::<Option<ProductAsRecord>>(0) {
                     return Ok(Some(record));
@@ -150,11 +179,12 @@ pub async fn create_product(
     new_product: Json<Product>,
 ) -> Result<Status, Status> {
     let product = new_product.into_inner();
-    let query_check = format!(
-        "SELECT * FROM products WHERE name = ' ' category = '{}';",
-        product.category
-    );
-    let result_check = database.query(&query_check).await;
+    let query_check = "SELECT * FROM products WHERE name = $name AND category = $category LIMIT 1;";
+    let result_check = database
+        .query(query_check)
+        .bind(("name", product.name.clone()))
+        .bind(("category", product.category.clone()))
+        .await;
 
     if let Ok(mut results) = result_check {
         if let Ok(Some(_)) = results.take::<Option<Product>>(0) {
@@ -162,29 +192,22 @@ pub async fn create_product(
             return Err(Status::Conflict);
         }
     }
-    let query = format!(
-        "CREATE products CONTENT {{
-            name: '{}',
-            price: {},
-            bar_code: '{}',
-            quantity: {},
-            category: '{}'
-        }};",
-        product.name, product.price, product.bar_code, product.quantity, product.category
-    );
 
-    log::info!("Ejecutando el query: {}", query);
-
-    let Ok(mut results) = database.query(&query).await else {
-        log::error!("Peticion a la base de datos ha fallado.");
-        return Err(Status::InternalServerError);
-    };
-    if let Ok(Some(_)) = results.take::<Option<Product>>(0) {
-        log::info!("Producto creado correctamente creado");
-        Ok(Status::Created)
-    } else {
-        log::error!("Creacion del producto fallida: Sin resultados por retornar");
-        Err(Status::InternalServerError)
+    log::info!("Creando el producto: {}", product.name);
+
+    match create_record::<_, _, Product>(database.inner(), "products", product).await {
+        Ok(Some(_)) => {
+            log::info!("Producto creado correctamente creado");
+            Ok(Status::Created)
+        }
+        Ok(None) => {
+            log::error!("Creacion del producto fallida: Sin resultados por retornar");
+            Err(Status::InternalServerError)
+        }
+        Err(err) => {
+            log::error!("Peticion a la base de datos ha fallado: {:?}", err);
+            Err(Status::InternalServerError)
+        }
     }
 }
 
@@ -193,37 +216,23 @@ pub async fn update_product(
     product_id: String,
     update_data: Json<UpdateProduct>,
 ) -> Result<Status, Status> {
-    let mut updates = Vec::new();
-
-    if let Some(name) = &update_data.name {
-        updates.push(format!("name = '{}'", name));
-    }
-    if let Some(price) = &update_data.price {
-        updates.push(format!("price = {}", price));
-    }
-    if let Some(bar_code) = &update_data.bar_code {
-        updates.push(format!("bar_code = '{}'", bar_code));
-    }
-    if let Some(quantity) = &update_data.quantity {
-        updates.push(format!("quantity = {}", quantity));
-    }
-    if let Some(category) = &update_data.category {
-        updates.push(format!("category = '{}'", category));
-    }
-
-    if updates.is_empty() {
+    let record_id = parse_record_id("products", &product_id)?;
+    let patch = update_data.into_inner();
+
+    if patch.name.is_none()
+        && patch.price.is_none()
+        && patch.bar_code.is_none()
+        && patch.quantity.is_none()
+        && patch.category.is_none()
+        && patch.tax_treatment.is_none()
+        && patch.tax_rate.is_none()
+    {
         return Err(Status::BadRequest);
     }
 
-    let query = format!(
-        "UPDATE {} SET {};",
-        product_id,
-        updates.join(", ")
-    );
-
-    log::info!("Ejecutando query: {}", query);
+    log::info!("Actualizando el producto: {}", record_id);
 
-    match database.query(&query).await {
+    match merge_record(database.inner(), record_id, patch).await {
         Ok(_) => Ok(Status::Ok),
         Err(err) => {
             log::error!("Error al actualizar el producto: {:?}", err);
@@ -236,9 +245,9 @@ pub async fn delete_product(
     database: &State<Surreal<Client>>,
     product_id: String,
 ) -> Result<Status, Status> {
-    let query = format!("DELETE {};", product_id);
+    let record_id = parse_record_id("products", &product_id)?;
 
-    match database.query(&query).await {
+    match delete_record(database.inner(), record_id).await {
         Ok(_) => Ok(Status::Ok),
         Err(err) => {
             error!("Error al eliminar el producto: {:?}", err);
@@ -252,35 +261,25 @@ pub async fn create_category(
     new_category: Json<Category>,
 ) -> Result<Status, Status> {
     let category = new_category.into_inner();
-    let query_check = format!(
-        "SELECT * FROM categories WHERE name = '{}';", category.name
-    );
-    let result_check = database.query(&query_check).await;
+    let result_check =
+        find_one_by::<_, _, Category>(database.inner(), "categories", "name", category.name.clone()).await;
 
-    if let Ok(mut results) = result_check {
-        if let Ok(Some(_)) = results.take::<Option<Product>>(0) {
-            log::error!("La categoria '{}' ya existe, utilice otra categoria porfavor.", category.name);
-            return Err(Status::Conflict);
-        }
+    if let Ok(Some(_)) = result_check {
+        log::error!("La categoria '{}' ya existe, utilice otra categoria porfavor.", category.name);
+        return Err(Status::Conflict);
     }
-    let query = format!(
-        "CREATE categories CONTENT {{
-            name: '{}'
-        }};",
-        category.name
-    );
 
-    log::info!("Ejecutando el query: {}", query);
-
-    let Ok(mut results) = database.query(&query).await else {
-        log::error!("Peticion a la base de datos ha fallado.");
-        return Err(Status::InternalServerError);
-    };
-    if let Ok(Some(_)) = results.take::<Option<Product>>(0) {
-        log::info!("Categoria creada correctamente.");
-        Ok(Status::Created)
-    } else {
-        Ok(Status::Created)
+    log::info!("Creando la categoria: {}", category.name);
+
+    match create_record::<_, _, Category>(database.inner(), "categories", category).await {
+        Ok(_) => {
+            log::info!("Categoria creada correctamente.");
+            Ok(Status::Created)
+        }
+        Err(err) => {
+            log::error!("Peticion a la base de datos ha fallado: {:?}", err);
+            Err(Status::InternalServerError)
+        }
     }
 }
 
@@ -311,9 +310,7 @@ pub async fn delete_category(
     database: &State<Surreal<Client>>,
     category: String,
 ) -> Result<Status, Status> {
-    let query = format!("DELETE FROM categories WHERE name = '{}';", category);
-
-    match database.query(&query).await {
+    match delete_where(database.inner(), "categories", "name", category).await {
         Ok(_) => Ok(Status::Ok),
         Err(err) => {
             error!("Error al eliminar el producto: {:?}", err);
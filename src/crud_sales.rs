@@ -5,18 +5,131 @@ use serde::Deserialize;
 use serde::Serialize;
 use serde::Deserializer;
 use serde::de::{self, Visitor, SeqAccess};
-use std::collections::HashMap;
 use surrealdb::engine::remote::ws::Client;
-use surrealdb::Surreal; 
+use surrealdb::{Connection, Response, Surreal};
 use log::{info, error}; 
 use surrealdb::sql::Thing;
 use std::collections::HashSet;
+use std::collections::HashMap;
+use std::time::Duration;
+use rand::Rng;
 use chrono::{Utc, FixedOffset, DateTime};
 use serde_json::Value as JsonValue;
 use surrealdb::sql::{Value as SurrealValue, Object};
 use crate::crud_inventory::get_product_by_id;
 use std::fmt;
-use chrono::NaiveDate;
+use crate::repository::{parse_record_id, parse_record_id_in};
+use chrono::NaiveDateTime;
+use surrealdb::RecordId;
+use crate::auth::{verify_supervisor, SupervisorCredentials};
+use crate::permissions::{Permission, SalesVoid};
+use crate::pricing::{check_client_amount, compute_totals, find_discount, price_lines, rate_tenders, sale_items, tenders_summary, PaymentMethod, Tender};
+use crate::promos::{is_discount_exhausted, DiscountCart, Redemption, REDEEM_DISCOUNT};
+use crate::exchange_rates::{current_rates, normalize_currency};
+use crate::money::Money;
+use crate::taxes::TaxTreatment;
+use crate::config::AppConfig;
+use crate::business_time::{self, DISPLAY_FORMAT};
+use chrono_tz::Tz;
+use surrealdb::sql::Datetime;
+
+// Tablas cuyos registros se pueden cobrar en una venta
+pub const SELLABLE_TABLES: &[&str] = &["products", "exams", "monthly"];
+
+// Descuenta el inventario de cada línea de `$items`. Exámenes y mensualidades no tienen
+// `quantity` y se omiten; si una línea no alcanza, el THROW cancela toda la transacción.
+pub const DECREMENT_STOCK: &str = "FOR $line IN $items {
+        LET $available = (SELECT VALUE quantity FROM ONLY $line.product);
+        IF $available != NONE AND $available < $line.quantity {
+            THROW 'Stock insuficiente: ' + <string> $line.product;
+        };
+        IF $available != NONE {
+            UPDATE $line.product SET quantity -= $line.quantity;
+        };
+    };";
+
+// Repone el inventario de cada línea de `$items` devuelta; exámenes y mensualidades se omiten
+pub const RESTORE_STOCK: &str = "FOR $line IN $items {
+        UPDATE $line.product SET quantity += $line.quantity WHERE quantity != NONE;
+    };";
+
+// Registra `$sale` con el siguiente número de factura de su sucursal (`RS-000123`). El contador
+// avanza en la misma transacción que crea la venta: si algo falla el número no se consume, y las
+// ventas anuladas lo conservan. Son cuatro sentencias; la última devuelve un `NumberedSale`.
+pub const CREATE_NUMBERED_SALE: &str = "LET $invoice_sequence = (UPSERT type::thing('counters', ['invoice', $sale.branch])
+            SET value = (value ?? 0) + 1 RETURN VALUE value)[0];
+        LET $invoice_digits = <string> $invoice_sequence;
+        LET $created = CREATE ONLY sales CONTENT $sale RETURN id;
+        UPDATE ONLY $created.id SET
+            invoice_sequence = $invoice_sequence,
+            invoice_number = $sale.branch + '-' + string::repeat('0', math::max([0, 6 - string::len($invoice_digits)])) + $invoice_digits
+            RETURN <string> id AS id, invoice_number;";
+
+// Mensaje de SurrealDB cuando dos transacciones chocan, p. ej. dos cajas que piden a la vez el
+// siguiente número de factura de la sucursal
+const TRANSACTION_CONFLICT: &str = "This transaction can be retried";
+
+// Intentos de una transacción de venta antes de responder con error
+pub const SALE_ATTEMPTS: u64 = 8;
+
+pub fn is_transaction_conflict(err: &surrealdb::Error) -> bool {
+    err.to_string().contains(TRANSACTION_CONFLICT)
+}
+
+// Ejecuta la transacción de una venta numerada y la repite, tras una espera aleatoria que crece
+// con cada intento, mientras pierda el contador de facturas frente a otra caja. Devuelve la
+// respuesta con sus errores ya extraídos para que cada llamador reconozca sus propios THROW.
+pub async fn run_sale_transaction<C, B>(
+    database: &Surreal<C>,
+    query: &str,
+    bindings: B,
+) -> Result<(Response, HashMap<usize, surrealdb::Error>), Status>
+where
+    C: Connection,
+    B: Serialize + Clone + 'static,
+{
+    let mut attempt = 1;
+    loop {
+        let mut response = match database.query(query).bind(bindings.clone()).await {
+            Ok(response) => response,
+            Err(err) => {
+                error!("Error al ejecutar la transacción de la venta: {:?}", err);
+                return Err(Status::InternalServerError);
+            }
+        };
+
+        let errors = response.take_errors();
+        if attempt < SALE_ATTEMPTS && errors.values().any(is_transaction_conflict) {
+            log::warn!("Conflicto al numerar la venta; reintento {} de {}", attempt, SALE_ATTEMPTS - 1);
+            let pause = rand::thread_rng().gen_range(5..25) * attempt;
+            rocket::tokio::time::sleep(Duration::from_millis(pause)).await;
+            attempt += 1;
+            continue;
+        }
+        return Ok((response, errors));
+    }
+}
+
+// Venta recién registrada y su número de factura
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NumberedSale {
+    pub id: String,
+    pub invoice_number: String,
+}
+
+// Línea de `$items` para `DECREMENT_STOCK`; `SaleItem` también sirve
+#[derive(Serialize, Debug)]
+pub struct StockLine {
+    pub product: RecordId,
+    pub quantity: u32,
+}
+
+// Debe coincidir con el mensaje del THROW de `DECREMENT_STOCK`
+const INSUFFICIENT_STOCK: &str = "Stock insuficiente";
+
+pub fn is_insufficient_stock(err: &surrealdb::Error) -> bool {
+    err.to_string().contains(INSUFFICIENT_STOCK)
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ProductWithQuantity {
@@ -33,33 +146,36 @@ pub struct EnrichedProduct {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SimplifiedSales {
     pub cashier: Option<String>,
-    pub change: Option<f64>,
+    pub change: Option<Money>,
     pub currency: Option<String>,
     pub customer: Option<String>,
+    // `datetime` es el momento de la venta en UTC; `date`, el mismo en la hora del negocio
+    #[serde(default)]
     pub date: Option<String>,
+    pub datetime: Option<DateTime<Utc>>,
     pub id: Thing, // Mantén `Thing` si prefieres usar el tipo original
+    // Las ventas anteriores a la numeración no lo tienen
+    pub invoice_number: Option<String>,
     pub payment_ref: Option<String>,
     pub products_names: Option<Vec<String>>, // Solo los nombres de los productos
+    #[serde(default)]
+    pub items: Vec<SaleItemAsString>,
     pub promocode: Option<String>,
-    pub total_paid: Option<f64>,
+    pub total_paid: Option<Money>,
+    pub tax: Option<Money>,
+    #[serde(default)]
+    pub tenders: Vec<Tender>,
+    // Devoluciones acumuladas y lo que queda de la venta después de restarlas
+    pub status: Option<SaleStatus>,
+    pub refunded_total: Option<Money>,
+    pub net_total: Option<Money>,
+    // Tasa de la venta a la moneda base y el neto consolidado con ella
+    pub base_currency: Option<String>,
+    pub exchange_rate: Option<f64>,
+    pub net_total_base: Option<Money>,
     pub type_: Option<String>, // Usamos `type_` para evitar conflictos con palabras reservadas
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct SalesAsRecord {
-    pub id: Thing,
-    products: Option<Vec<Thing>>,
-    total_paid: Option<f64>,
-    customer: Option<String>,
-    cashier: Option<String>,
-    promocode: Option<String>,
-    payment_ref: Option<String>,
-    date: Option<String>,
-    change: Option<f64>,
-    type_: Option<String>, // Usamos `type_` porque `type` es una palabra reservada
-    currency: Option<String>,
-}
-
 fn thing_to_string<'de, D>(deserializer: D) -> Result<String, D::Error>
 where
     D: Deserializer<'de>,
@@ -118,13 +234,13 @@ pub struct SalesAsString {
     pub id: String,
     #[serde(deserialize_with = "things_to_strings")] // Aplica el deserializador aquí
     pub products: Option<Vec<String>>,
-    pub total_paid: Option<f64>,
+    pub total_paid: Option<Money>,
     pub customer: Option<String>,
     pub cashier: Option<String>,
     pub promocode: Option<String>,
     pub payment_ref: Option<String>,
     pub date: Option<String>,
-    pub change: Option<f64>,
+    pub change: Option<Money>,
     #[serde(rename = "type")]
     pub type_: Option<String>,
     pub currency: Option<String>,
@@ -133,44 +249,116 @@ pub struct SalesAsString {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Sales {
     #[serde(deserialize_with = "deserialize_products")]
-    pub products: Vec<Thing>,
-    pub total_paid: f64,
+    pub products: Vec<RecordId>,
+    pub total_paid: Money,
     pub customer: Option<String>,
-    pub cashier: String,
     pub promocode: String,
     pub payment_ref: String,
-    pub date: Option<String>,
-    pub change: f64, // Campo obligatorio
+    pub change: Money, // Campo obligatorio
+    // Monto entregado por el cliente; si falta se asume `total_paid + change`
+    #[serde(default)]
+    pub amount_tendered: Option<Money>,
+    // Pago dividido; si falta, todo se pagó con `type_`
+    #[serde(default)]
+    pub tenders: Vec<Tender>,
     pub type_: String, // Campo obligatorio
     pub currency: String, // Campo obligatorio
 }
 
-impl From<SalesAsRecord> for SalesAsString {
-    fn from(record: SalesAsRecord) -> Self {
-        SalesAsString {
-            id: record.id.to_string(), // Convierte Thing a String
-            products: Some(record.products
-                .map(|things| {
-                    things
-                        .into_iter()
-                        .map(|thing| thing.to_string()) // Convierte Thing a String
-                        .collect::<Vec<String>>()
-                })
-                .unwrap_or_default()),
-            total_paid: record.total_paid,
-            customer: record.customer,
-            cashier: record.cashier,
-            promocode: record.promocode,
-            payment_ref: record.payment_ref,
-            date: record.date,
-            change: record.change,
-            type_: record.type_,
-            currency: record.currency,
-        }
-    }
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SaleStatus {
+    #[default]
+    #[serde(rename = "completed")]
+    Completed,
+    #[serde(rename = "voided")]
+    Voided,
+    #[serde(rename = "refunded")]
+    Refunded,
 }
 
-fn deserialize_products<'de, D>(deserializer: D) -> Result<Vec<Thing>, D::Error>
+// Línea vendida con el precio vigente al cobrarla; editar o borrar el producto después no la cambia.
+// `discount` es la parte del descuento de la venta asignada a la línea; `line_total` ya lo resta
+// y suma el impuesto cuando no venía incluido en el precio.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SaleItem {
+    pub product: RecordId,
+    pub name: String,
+    pub unit_price: Money,
+    pub quantity: u32,
+    pub discount: Money,
+    pub line_total: Money,
+    // Las ventas anteriores al IVA no traen estos campos y se leen como exentas
+    #[serde(default)]
+    pub tax_treatment: TaxTreatment,
+    #[serde(default)]
+    pub tax_rate: String,
+    #[serde(default)]
+    pub tax_percentage: f64,
+    #[serde(default)]
+    pub taxable_base: Money,
+    #[serde(default)]
+    pub tax: Money,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SaleItemAsString {
+    pub product: String,
+    pub name: String,
+    pub unit_price: Money,
+    pub quantity: u32,
+    pub discount: Money,
+    pub line_total: Money,
+    #[serde(default)]
+    pub tax_treatment: TaxTreatment,
+    #[serde(default)]
+    pub tax_rate: String,
+    #[serde(default)]
+    pub tax_percentage: f64,
+    #[serde(default)]
+    pub taxable_base: Money,
+    #[serde(default)]
+    pub tax: Money,
+}
+
+// Parámetros de la transacción de `create_sales`
+#[derive(Serialize, Clone)]
+struct SaleBindings {
+    sale: NewSale,
+    redemption: Option<Redemption>,
+}
+
+// Contenido de un registro en `sales`; lo comparten `create_sales` y el checkout
+#[derive(Serialize, Debug, Clone)]
+pub struct NewSale {
+    pub status: SaleStatus,
+    pub products: Vec<RecordId>,
+    pub items: Vec<SaleItem>,
+    pub subtotal: Money,
+    pub discount: Money,
+    pub tax: Money,
+    pub total_paid: Money,
+    pub amount_tendered: Money,
+    pub change: Money,
+    pub tenders: Vec<Tender>,
+    pub customer: String,
+    pub cashier: String,
+    pub promocode: String,
+    pub payment_ref: String,
+    // Momento de la venta; se guarda como `datetime` de SurrealDB, en UTC
+    pub date: Datetime,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub currency: String,
+    // Unidades de la moneda base por unidad de `currency` al momento de la venta
+    pub base_currency: String,
+    pub exchange_rate: f64,
+    pub branch: String,
+    // Turno abierto del cajero al registrar la venta; sin turno la venta no entra en ningún arqueo
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shift: Option<RecordId>,
+}
+
+fn deserialize_products<'de, D>(deserializer: D) -> Result<Vec<RecordId>, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
@@ -180,7 +368,7 @@ where
             if let Some(id) = value.get("id").and_then(|v| v.as_str()) {
                 let parts: Vec<&str> = id.split(":").collect();
                 if parts.len() == 2 {
-                    Ok(Thing::from((parts[0], parts[1])))
+                    Ok(RecordId::from((parts[0], parts[1])))
                 } else {
                     Err(serde::de::Error::custom(
                         "Invalid format for Thing; expected `table:id`",
@@ -195,27 +383,58 @@ where
         .collect()
 }
 
+// Campos de `SimplifiedSales`. Las ventas sin `items` (anteriores a las líneas de venta)
+// siguen resolviendo los nombres desde `products`. Una fecha que la migración no pudo
+// convertir se lee como vacía.
+const SIMPLIFIED_SALES_FIELDS: &str = "
+    cashier,
+    change,
+    currency,
+    customer,
+    IF type::is::datetime(date) THEN date END AS datetime,
+    id,
+    invoice_number,
+    payment_ref,
+    items.name ?? products.map(|$product| (
+        SELECT name FROM products WHERE id = $product.id
+    )[0].name) AS products_names,
+    (SELECT <string> product AS product, name, unit_price, quantity, discount, line_total,
+        tax_treatment, tax_rate, tax_percentage, taxable_base, tax
+        FROM $parent.items) AS items,
+    tenders ?? [] AS tenders,
+    promocode,
+    total_paid,
+    tax ?? 0 AS tax,
+    status ?? 'completed' AS status,
+    refunded_total ?? 0 AS refunded_total,
+    total_paid - (refunded_total ?? 0) AS net_total,
+    base_currency ?? currency AS base_currency,
+    exchange_rate ?? 1 AS exchange_rate,
+    (total_paid - (refunded_total ?? 0)) * <decimal> (exchange_rate ?? 1) AS net_total_base,
+    type AS type_";
+
+// Rellena `date` con la hora del negocio a partir de `datetime`
+fn with_local_dates(mut sales: Vec<SimplifiedSales>, timezone: Tz) -> Vec<SimplifiedSales> {
+    for sale in &mut sales {
+        sale.date = sale.datetime.map(|datetime| business_time::format_local(timezone, &datetime, DISPLAY_FORMAT));
+    }
+    sales
+}
+
+// Las ventas anuladas quedan fuera de listados y reportes salvo que se pidan
 pub async fn get_sales(
     database: &State<Surreal<Client>>,
+    timezone: Tz,
+    include_voided: bool,
 ) -> Result<Json<Vec<SimplifiedSales>>, Status> {
+    let query = format!(
+        "SELECT {} FROM sales WHERE $include_voided OR status != 'voided';",
+        SIMPLIFIED_SALES_FIELDS
+    );
+
     match database
-        .query(
-            "SELECT 
-                cashier,
-                change,
-                currency,
-                customer,
-                date,
-                id,
-                payment_ref,
-                products.map(|$product| (
-                    SELECT name FROM products WHERE id = $product.id
-                )[0].name) AS products_names,
-                promocode,
-                total_paid,
-                type AS type_
-             FROM sales",
-        )
+        .query(query)
+        .bind(("include_voided", include_voided))
         .await
     {
         Ok(mut results) => {
@@ -228,7 +447,7 @@ pub async fn get_sales(
             };
 
             log::info!("Ventas obtenidas: {:?}", sales);
-            Ok(Json(sales))
+            Ok(Json(with_local_dates(sales, timezone)))
         }
         Err(err) => {
             log::error!("Error al consultar la base de datos: {:?}", err);
@@ -237,59 +456,116 @@ pub async fn get_sales(
     }
 }
 
+// La venta queda a nombre del usuario de la sesión, el mismo cuyo turno la recibe
 pub async fn create_sales(
     database: &State<Surreal<Client>>, 
+    config: &AppConfig,
+    cashier: &str,
+    shift: Option<RecordId>,
     new_sale: Json<Sales>,
-) -> Result<Status, Status> {
-    let sale = new_sale.into_inner();
-
-    // Registrar venta
-    let products_str = sale
-        .products
-        .iter()
-        .map(|thing| thing.to_string())
-        .collect::<Vec<String>>()
-        .join(", ");
-
-    let query = format!(
-        "CREATE sales CONTENT {{
-            products: [{}],
-            total_paid: {},
-            customer: '{}',
-            cashier: '{}',
-            promocode: '{}',
-            payment_ref: '{}',
-            date: '{}',
-            change: {},
-            type: '{}',
-            currency: '{}'
-        }} RETURN *;",
-        products_str,
-        sale.total_paid,
-        sale.customer.clone().unwrap_or_default(),
-        sale.cashier,
-        sale.promocode,
-        sale.payment_ref,
-        sale.date.clone().unwrap_or_default(),
-        sale.change,
-        sale.type_,
-        sale.currency,
-    );
-
-    log::info!("Ejecutando el query: {}", query);
+) -> Result<Json<NumberedSale>, Status> {
+    let mut sale = new_sale.into_inner();
+    sale.currency = normalize_currency(&sale.currency);
+
+    // Cada aparición de un producto en la lista cuenta como una unidad
+    let mut quantities: Vec<(RecordId, u32)> = Vec::new();
+    for product in sale.products {
+        match quantities.iter_mut().find(|(id, _)| *id == product) {
+            Some((_, quantity)) => *quantity += 1,
+            None => quantities.push((product, 1)),
+        }
+    }
+    if quantities.is_empty() {
+        log::warn!("Venta recibida sin productos");
+        return Err(Status::BadRequest);
+    }
 
-    match database.query(&query).await {
-        Ok(mut results) => {
-            if let Ok(Some((sale_id, _))) = results.take::<Vec<(Thing, Sales)>>(0).map(|mut v| v.pop()) {
-                log::info!("Venta creada correctamente con ID: {}", sale_id.to_string());
-            } else {
-                log::warn!("Venta creada, pero no se pudo procesar el ID de la venta.");
-            }            
+    // Los montos se recalculan con los precios actuales; los de la caja solo se verifican
+    let tenders = if sale.tenders.is_empty() {
+        let method = sale.type_.parse::<PaymentMethod>().map_err(|err| {
+            log::warn!("{}", err);
+            Status::UnprocessableEntity
+        })?;
+        vec![Tender {
+            method,
+            amount: sale.amount_tendered.unwrap_or(sale.total_paid + sale.change),
+            currency: sale.currency.clone(),
+            reference: Some(sale.payment_ref.clone()).filter(|reference| !reference.is_empty()),
+            exchange_rate: None,
+        }]
+    } else {
+        sale.tenders
+    };
+    let mut currencies: Vec<String> = tenders.iter().map(|tender| tender.currency.clone()).collect();
+    currencies.push(sale.currency.clone());
+    let rates = current_rates(database, config, currencies).await?;
+    let tenders = rate_tenders(tenders, &rates)?;
+
+    let lines = price_lines(database, quantities, &rates, &sale.currency).await?;
+    let cart = DiscountCart {
+        lines: &lines,
+        customer: sale.customer.as_deref(),
+        today: business_time::today(config.timezone),
+        rates: &rates,
+        currency: &sale.currency,
+    };
+    let discount = find_discount(database, &sale.promocode, &cart).await?;
+    let totals = compute_totals(&lines, discount.as_ref(), &tenders, &rates, &sale.currency)?;
+    let redemption = Redemption::new(discount.as_ref(), sale.customer.as_deref());
+    check_client_amount("total", Some(sale.total_paid), totals.total)?;
+    check_client_amount("vuelto", Some(sale.change), totals.change)?;
+
+    let (type_, payment_ref) = tenders_summary(&tenders);
+    let sale = NewSale {
+        status: SaleStatus::Completed,
+        products: lines.iter().map(|line| line.product.clone()).collect(),
+        items: sale_items(&lines, discount.as_ref(), &totals),
+        subtotal: totals.subtotal,
+        discount: totals.discount,
+        tax: totals.tax,
+        total_paid: totals.total,
+        amount_tendered: totals.amount_tendered,
+        change: totals.change,
+        tenders,
+        customer: sale.customer.unwrap_or_default(),
+        cashier: cashier.to_string(),
+        promocode: sale.promocode,
+        payment_ref,
+        date: Utc::now().into(),
+        type_,
+        exchange_rate: rates.rate(&sale.currency)?,
+        base_currency: rates.base_currency,
+        currency: sale.currency,
+        branch: config.branch.clone(),
+        shift,
+    };
+
+    // Registrar venta con su número de factura y el uso del código promocional
+    let query = format!("BEGIN TRANSACTION; {} {} COMMIT TRANSACTION;", CREATE_NUMBERED_SALE, REDEEM_DISCOUNT);
+
+    log::info!("Registrando venta del cajero {}", sale.cashier);
+
+    let (mut response, errors) = run_sale_transaction(database, &query, SaleBindings { sale, redemption }).await?;
+    if let Some(err) = errors.values().find(|err| is_discount_exhausted(err)) {
+        log::warn!("Venta cancelada: {}", err);
+        return Err(Status::UnprocessableEntity);
+    }
+    if !errors.is_empty() {
+        log::error!("Error en la transacción de la venta: {:?}", errors);
+        return Err(Status::InternalServerError);
+    }
 
-            Ok(Status::Created)
+    match response.take::<Option<NumberedSale>>(3) {
+        Ok(Some(created)) => {
+            log::info!("Venta creada correctamente con ID: {} ({})", created.id, created.invoice_number);
+            Ok(Json(created))
+        }
+        Ok(None) => {
+            log::error!("La venta no devolvió su número de factura");
+            Err(Status::InternalServerError)
         }
         Err(err) => {
-            log::error!("Error al ejecutar el query: {:?}", err);
+            log::error!("Error al deserializar el número de factura: {:?}", err);
             Err(Status::InternalServerError)
         }
     }
@@ -304,116 +580,203 @@ pub async fn update_products_for_new_quantities(
     log::info!("Iniciando actualización de inventario.");
     log::info!("Datos recibidos: {:?}", product_updates);
 
-    for product in product_updates {
-        let product_id = format!("{}", product.id);
-
-        log::info!("Procesando producto: {}", product_id);
-
-        let query = format!("SELECT quantity FROM {}", product_id);
-        log::info!("Ejecutando query: {}", query);
-
-        match database.query(&query).await {
-            Ok(mut results) => {
-                log::info!("Resultados del query: {:?}", results);
-
-                if let Some(quantity_obj) = results
-                    .take::<Vec<HashMap<String, JsonValue>>>(0)
-                    .ok()
-                    .and_then(|mut res| res.pop())
-                {
-                    if let Some(current_quantity) = quantity_obj.get("quantity").and_then(|v| v.as_u64()) {
-                        log::info!(
-                            "Cantidad actual para {}: {}. Requerida: {}",
-                            product_id, current_quantity, product.qnt
-                        );
-
-                        if current_quantity < product.qnt as u64 {
-                            error!(
-                                "Stock insuficiente para el producto {}: disponible {}, requerido {}",
-                                product_id, current_quantity, product.qnt
-                            );
-                            return Err(Status::BadRequest);
-                        }
-
-                        let new_quantity = current_quantity - product.qnt as u64;
-                        log::info!(
-                            "Nueva cantidad calculada para {}: {}",
-                            product_id, new_quantity
-                        );
-
-                        let update_query = format!(
-                            "UPDATE {} SET quantity = {}",
-                            product_id, new_quantity
-                        );
-                        log::info!("Ejecutando query de actualización: {}", update_query);
-
-                        if let Err(err) = database.query(&update_query).await {
-                            error!(
-                                "Error al actualizar el inventario para el producto {}: {:?}",
-                                product_id, err
-                            );
-                            return Err(Status::InternalServerError);
-                        }
-                    }
-                }
-            }
-            Err(err) => {
-                log::error!(
-                    "Error al consultar el inventario para el producto {}: {:?}",
-                    product_id, err
-                );
-                return Err(Status::InternalServerError);
-            }
+    let items = product_updates
+        .iter()
+        .map(|product| {
+            Ok(StockLine {
+                product: parse_record_id_in(SELLABLE_TABLES, &product.id)?,
+                quantity: product.qnt,
+            })
+        })
+        .collect::<Result<Vec<StockLine>, Status>>()?;
+
+    // Todas las líneas se descuentan juntas o ninguna
+    let query = format!("BEGIN TRANSACTION; {} COMMIT TRANSACTION;", DECREMENT_STOCK);
+
+    match database.query(query).bind(("items", items)).await.map(|response| response.check().err()) {
+        Ok(None) => Ok(Status::Ok),
+        Ok(Some(err)) | Err(err) if is_insufficient_stock(&err) => {
+            log::warn!("Inventario sin cambios: {}", err);
+            Err(Status::BadRequest)
+        }
+        Ok(Some(err)) | Err(err) => {
+            log::error!("Error al actualizar el inventario: {:?}", err);
+            Err(Status::InternalServerError)
         }
     }
+}
 
-    // Agregar `Ok(Status::Ok)` al final de la función
-    Ok(Status::Ok)
+// Anulación autorizada por un supervisor; la venta se conserva con el motivo y quién la anuló
+#[derive(Deserialize, Debug)]
+pub struct VoidRequest {
+    pub reason: String,
+    pub supervisor: SupervisorCredentials,
+}
+
+#[derive(Deserialize, Debug)]
+struct VoidableSale {
+    items: Option<Vec<SaleItem>>,
+    status: Option<SaleStatus>,
+    refunded_total: Option<Money>,
 }
 
-pub async fn delete_sales(
+// Debe coincidir con el mensaje del THROW de `void_sale`
+const NOT_VOIDABLE: &str = "Venta no anulable";
+
+pub async fn void_sale(
     database: &State<Surreal<Client>>,
     sales_id: String,
-    ) -> Result<Status, Status> {
-    let query = format!("DELETE sales:{}", sales_id);
+    requested_by: &str,
+    request: Json<VoidRequest>,
+) -> Result<Status, Status> {
+    let request = request.into_inner();
+    if request.reason.trim().is_empty() {
+        log::warn!("Anulación sin motivo para la venta {}", sales_id);
+        return Err(Status::UnprocessableEntity);
+    }
 
-    match database.query(&query).await {
-        Ok(_) => Ok(Status::Ok),
+    let authorized_by = verify_supervisor(database, &request.supervisor, SalesVoid::NAME).await?;
+    let record_id = parse_record_id("sales", &sales_id)?;
+
+    let sale = match database
+        .query("SELECT items, status, refunded_total FROM ONLY $id;")
+        .bind(("id", record_id.clone()))
+        .await
+    {
+        Ok(mut results) => results.take::<Option<VoidableSale>>(0).map_err(|err| {
+            error!("Error al deserializar la venta {}: {:?}", record_id, err);
+            Status::InternalServerError
+        })?,
         Err(err) => {
-            error!("Error al eliminar el producto");
-            Err(Status::InternalServerError)
+            error!("Error al consultar la venta {}: {:?}", record_id, err);
+            return Err(Status::InternalServerError);
         }
+    };
+
+    let Some(sale) = sale else {
+        log::warn!("La venta {} no existe", record_id);
+        return Err(Status::NotFound);
+    };
+    // Una venta con devoluciones ya repuso parte del inventario; se termina de devolver en su lugar
+    if sale.status.unwrap_or_default() != SaleStatus::Completed || !sale.refunded_total.unwrap_or_default().is_zero() {
+        log::warn!("La venta {} no se puede anular en su estado actual", record_id);
+        return Err(Status::Conflict);
+    }
+
+    // Marcar la venta y reponer su inventario juntos; el WHERE evita anular dos veces
+    let query = format!(
+        "BEGIN TRANSACTION;
+        LET $voided = UPDATE $id SET
+                status = 'voided',
+                void_reason = $reason,
+                voided_by = $authorized_by,
+                void_requested_by = $requested_by,
+                voided_at = time::now()
+            WHERE (status ?? 'completed') = 'completed' AND (refunded_total ?? 0) = 0
+            RETURN id;
+        IF array::len($voided) = 0 {{
+            THROW '{}';
+        }};
+        {}
+        COMMIT TRANSACTION;",
+        NOT_VOIDABLE, RESTORE_STOCK
+    );
+
+    let mut response = database
+        .query(query)
+        .bind(("id", record_id.clone()))
+        .bind(("reason", request.reason))
+        .bind(("authorized_by", authorized_by.clone()))
+        .bind(("requested_by", requested_by.to_string()))
+        .bind(("items", sale.items.unwrap_or_default()))
+        .await
+        .map_err(|err| {
+            error!("Error al anular la venta {}: {:?}", record_id, err);
+            Status::InternalServerError
+        })?;
+
+    // El THROW no es la primera sentencia; se busca entre todos los errores de la transacción
+    let errors = response.take_errors();
+    if errors.values().any(|err| err.to_string().contains(NOT_VOIDABLE)) {
+        log::warn!("La venta {} cambió de estado antes de anularse", record_id);
+        return Err(Status::Conflict);
+    }
+    if !errors.is_empty() {
+        error!("Error en la transacción de anulación de {}: {:?}", record_id, errors);
+        return Err(Status::InternalServerError);
     }
+
+    log::info!("Venta {} anulada por {} a pedido de {}", record_id, authorized_by, requested_by);
+    Ok(Status::Ok)
 }
 
+// Tamaño de página del listado por rango cuando no se indica otro, y el máximo aceptado
+pub const DEFAULT_PAGE_SIZE: u32 = 50;
+pub const MAX_PAGE_SIZE: u32 = 200;
+
+// Una página de ventas; `total` cuenta todas las del rango
+#[derive(Serialize, Debug)]
+pub struct SalesPage {
+    pub sales: Vec<SimplifiedSales>,
+    pub page: u32,
+    pub page_size: u32,
+    pub total: u64,
+}
+
+// Ventas entre dos fechas `dd-mm-YYYY` del negocio (ambas incluidas), de la más reciente a la más antigua
 pub async fn get_sales_by_date_range(
     database: &State<Surreal<Client>>,
+    timezone: Tz,
     start_date: String,
     end_date: String,
-) -> Result<Json<Vec<SimplifiedSales>>, Status> {
-    // Validar formato de fechas
-    let parsed_start_date = NaiveDate::parse_from_str(&start_date, "%d-%m-%Y")
-        .map_err(|_| Status::BadRequest)?;
-    let parsed_end_date = NaiveDate::parse_from_str(&end_date, "%d-%m-%Y")
-        .map_err(|_| Status::BadRequest)?;
-
-    // Ajustar el formato para incluir horas y minutos
-    let start_datetime = format!("{} 00:00", parsed_start_date.format("%d-%m-%y"));
-    let end_datetime = format!("{} 23:59", parsed_end_date.format("%d-%m-%y"));
+    include_voided: bool,
+    page: u32,
+    page_size: u32,
+) -> Result<Json<SalesPage>, Status> {
+    let (from, to) = business_time::day_range(
+        timezone,
+        business_time::parse_day(&start_date)?,
+        business_time::parse_day(&end_date)?,
+    )?;
+    if page == 0 || page_size == 0 || page_size > MAX_PAGE_SIZE {
+        log::warn!("Paginación inválida: página {}, tamaño {}", page, page_size);
+        return Err(Status::BadRequest);
+    }
 
-    // Query para buscar ventas en el rango de fechas
     let query = format!(
-        "SELECT id, products, payment_method, payment_ref, promocode, date
-         FROM sales
-         WHERE date >= '{}' AND date <= '{}';",
-        start_datetime,
-        end_datetime
+        "SELECT {} FROM sales
+            WHERE date >= $from AND date < $to AND ($include_voided OR status != 'voided')
+            ORDER BY datetime DESC LIMIT $limit START $start;
+        SELECT count() FROM sales
+            WHERE date >= $from AND date < $to AND ($include_voided OR status != 'voided')
+            GROUP ALL;",
+        SIMPLIFIED_SALES_FIELDS
     );
 
-    match database.query(&query).await {
+    match database
+        .query(query)
+        .bind(("from", from))
+        .bind(("to", to))
+        .bind(("include_voided", include_voided))
+        .bind(("limit", page_size))
+        .bind(("start", u64::from(page - 1) * u64::from(page_size)))
+        .await
+    {
         Ok(mut results) => {
-            let sales: Vec<SimplifiedSales> = results.take(0).unwrap_or_default();
-            Ok(Json(sales))
+            let sales = results.take::<Vec<SimplifiedSales>>(0);
+            let total = results.take::<Option<u64>>((1, "count"));
+            match (sales, total) {
+                (Ok(sales), Ok(total)) => Ok(Json(SalesPage {
+                    sales: with_local_dates(sales, timezone),
+                    page,
+                    page_size,
+                    total: total.unwrap_or_default(),
+                })),
+                (Err(err), _) | (_, Err(err)) => {
+                    error!("Error al deserializar las ventas del rango: {:?}", err);
+                    Err(Status::InternalServerError)
+                }
+            }
         }
         Err(err) => {
             error!("Error al buscar ventas por rango de fechas: {:?}", err);
@@ -422,3 +785,157 @@ pub async fn get_sales_by_date_range(
     }
 }
 
+// ID de la venta a partir de lo que lee el escáner: el contenido del QR del recibo
+// (`sales:<id>`), el ID sin tabla o el número de factura impreso (`RS-000123`)
+pub async fn resolve_sale_id(
+    database: &State<Surreal<Client>>,
+    code: &str,
+) -> Result<RecordId, Status> {
+    let code = code.trim();
+    if !code.contains(':') {
+        // El prefijo es el código de sucursal tal como se configuró; se compara sin distinguir mayúsculas
+        let result = database
+            .query("SELECT VALUE id FROM sales WHERE string::uppercase(invoice_number ?? '') = $invoice_number LIMIT 1;")
+            .bind(("invoice_number", code.to_uppercase()))
+            .await;
+        match result {
+            Ok(mut results) => match results.take::<Option<RecordId>>(0) {
+                Ok(Some(record_id)) => return Ok(record_id),
+                Ok(None) => {}
+                Err(err) => {
+                    error!("Error al deserializar la venta de la factura {}: {:?}", code, err);
+                    return Err(Status::InternalServerError);
+                }
+            },
+            Err(err) => {
+                error!("Error al buscar la factura {}: {:?}", code, err);
+                return Err(Status::InternalServerError);
+            }
+        }
+    }
+    parse_record_id("sales", code)
+}
+
+// Una venta buscada por el código escaneado en la caja
+pub async fn find_sale(
+    database: &State<Surreal<Client>>,
+    timezone: Tz,
+    code: &str,
+) -> Result<Json<SimplifiedSales>, Status> {
+    let record_id = resolve_sale_id(database, code).await?;
+    let query = format!("SELECT {} FROM ONLY $id;", SIMPLIFIED_SALES_FIELDS);
+
+    match database.query(query).bind(("id", record_id.clone())).await {
+        Ok(mut results) => match results.take::<Option<SimplifiedSales>>(0) {
+            Ok(Some(sale)) => Ok(Json(with_local_dates(vec![sale], timezone).remove(0))),
+            Ok(None) => {
+                log::warn!("La venta {} no existe", record_id);
+                Err(Status::NotFound)
+            }
+            Err(err) => {
+                error!("Error al deserializar la venta {}: {:?}", record_id, err);
+                Err(Status::InternalServerError)
+            }
+        },
+        Err(err) => {
+            error!("Error al consultar la venta {}: {:?}", record_id, err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// Formato y desfase con que se guardaban las fechas como texto antes de usar `datetime`
+const LEGACY_DATE_FORMAT: &str = "%d-%m-%y %H:%M";
+const LEGACY_UTC_OFFSET_SECONDS: i32 = -6 * 3600;
+
+#[derive(Deserialize, Debug)]
+struct LegacySaleDate {
+    id: RecordId,
+    date: String,
+}
+
+#[derive(Serialize, Debug)]
+struct MigratedSaleDate {
+    id: RecordId,
+    date: Datetime,
+}
+
+// Convierte las fechas de texto de las ventas anteriores en `datetime`. El texto original
+// queda en `legacy_date`; las que no se pueden leer se dejan como están y se avisan.
+pub async fn migrate_sale_dates(database: &Surreal<Client>) -> Result<(), surrealdb::Error> {
+    let legacy: Vec<LegacySaleDate> = database
+        .query("SELECT id, date FROM sales WHERE type::is::string(date);")
+        .await?
+        .take(0)?;
+    if legacy.is_empty() {
+        return Ok(());
+    }
+
+    let offset = FixedOffset::east_opt(LEGACY_UTC_OFFSET_SECONDS).expect("desfase válido");
+    let mut rows: Vec<MigratedSaleDate> = Vec::new();
+    for sale in legacy {
+        let parsed = NaiveDateTime::parse_from_str(sale.date.trim(), LEGACY_DATE_FORMAT)
+            .ok()
+            .and_then(|date| date.and_local_timezone(offset).single());
+        match parsed {
+            Some(date) => rows.push(MigratedSaleDate { id: sale.id, date: date.with_timezone(&Utc).into() }),
+            None => log::warn!("La venta {} tiene una fecha ilegible: '{}'", sale.id, sale.date),
+        }
+    }
+
+    let migrated = rows.len();
+    database
+        .query("FOR $row IN $rows {
+            UPDATE $row.id SET legacy_date = date, date = $row.date;
+        };")
+        .bind(("rows", rows))
+        .await?
+        .check()?;
+
+    log::info!("Fechas de {} ventas convertidas a datetime", migrated);
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use surrealdb::engine::local::{Db, Mem};
+
+    #[derive(Serialize, Clone)]
+    struct TestSale {
+        branch: String,
+    }
+
+    #[derive(Serialize, Clone)]
+    struct TestBindings {
+        sale: TestSale,
+    }
+
+    async fn numbered_sale(database: Surreal<Db>) -> NumberedSale {
+        let query = format!("BEGIN TRANSACTION; {} COMMIT TRANSACTION;", CREATE_NUMBERED_SALE);
+        let bindings = TestBindings { sale: TestSale { branch: "RS".to_string() } };
+        let (mut response, errors) = run_sale_transaction(&database, &query, bindings).await.unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        response.take::<Option<NumberedSale>>(3).unwrap().expect("venta numerada")
+    }
+
+    // Varios hilos para que las transacciones choquen de verdad en el contador. El motor en
+    // memoria puede repetir un número en una carrera propia de su commit, así que la prueba
+    // comprueba que ninguna caja se queda sin venta, no la unicidad de la numeración.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_sales_retry_counter_conflicts() {
+        let database = Surreal::new::<Mem>(()).await.expect("base de datos en memoria");
+        database.use_ns("test").use_db("test").await.expect("namespace de pruebas");
+
+        let tasks: Vec<_> = (0..16).map(|_| tokio::spawn(numbered_sale(database.clone()))).collect();
+        for task in tasks {
+            let created = task.await.unwrap();
+            let sequence: u32 = created.invoice_number.strip_prefix("RS-").unwrap().parse().unwrap();
+            assert!((1..=16).contains(&sequence), "{}", created.invoice_number);
+        }
+
+        let total: Option<usize> = database.query("RETURN count(SELECT id FROM sales);").await.unwrap().take(0).unwrap();
+        assert_eq!(total, Some(16));
+    }
+}
@@ -1,17 +1,23 @@
 use surrealdb::engine::remote::ws::Client;
-use surrealdb::engine::remote::ws::Ws;
+use surrealdb::engine::remote::ws::{Ws, Wss};
 use surrealdb::opt::auth::Root;
 use surrealdb::Surreal;
+use crate::config::AppConfig;
 use crate::Paint;
 
-pub async fn connect_db() -> Result<Surreal<Client>, surrealdb::Error> {
-    let db = Surreal::new::<Ws>("127.0.0.1:8080").await?;
+pub async fn connect_db(config: &AppConfig) -> Result<Surreal<Client>, surrealdb::Error> {
+    let db = if let Some(address) = config.db_url.strip_prefix("wss://") {
+        Surreal::new::<Wss>(address).await?
+    } else {
+        let address = config.db_url.strip_prefix("ws://").unwrap_or(&config.db_url);
+        Surreal::new::<Ws>(address).await?
+    };
     db.signin(Root {
-        username: "root",
-        password: "root",
+        username: &config.db_username,
+        password: &config.db_password,
     })
     .await?;
-    db.use_ns("central-choi").use_db("central-choi").await?;
+    db.use_ns(&config.db_namespace).use_db(&config.db_database).await?;
 
     println!("{}", Paint::green("Conexión a SurrealDB establecida correctamente."));
 
@@ -7,25 +7,41 @@ use surrealdb::engine::remote::ws::Client;
 use surrealdb::Surreal;
 use log::{info, error};
 use surrealdb::sql::Thing;
+use crate::money::Money;
+use crate::taxes::TaxTreatment;
+use crate::repository::{create_record, delete_record, find_one_by, merge_record, parse_record_id};
 
 #[derive(Serialize, Deserialize)]
 pub struct Exam {
+    #[serde(skip_serializing_if = "Option::is_none")]
     id: Option<Thing>,
     name: String,
-    price: f64,
+    price: Money,
+    #[serde(default)]
+    tax_treatment: TaxTreatment,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tax_rate: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct UpdateExam {
+    #[serde(skip_serializing_if = "Option::is_none")]
     name: Option<String>,
-    price: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    price: Option<Money>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tax_treatment: Option<TaxTreatment>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tax_rate: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ExamAsString {
     pub id: String,
     pub name: String,
-    pub price: f64,
+    pub price: Money,
+    pub tax_treatment: TaxTreatment,
+    pub tax_rate: Option<String>,
 }
 
 impl From<Exam> for ExamAsString {
@@ -34,6 +50,8 @@ impl From<Exam> for ExamAsString {
             id: exam.id.map(|thing| thing.to_string()).unwrap_or_default(),
             name: exam.name,
             price: exam.price,
+            tax_treatment: exam.tax_treatment,
+            tax_rate: exam.tax_rate,
         }
     }
 }
@@ -42,35 +60,22 @@ pub async fn create_exam(
     database: &State<Surreal<Client>>,
     new_exam: Json<Exam>,
 ) -> Result<Status, Status> {
-    let exam = new_exam.into_inner();
-    let query_check = format!(
-        "SELECT * FROM exams WHERE name = '{}';",
-        exam.name
-    );
-    if let Ok(mut results) = database.query(&query_check).await {
-        if let Ok(Some(_)) = results.take::<Option<Exam>>(0) {
-            error!("El examen '{}' ya existe", exam.name);
-            return Err(Status::Conflict);
-        }
+    let mut exam = new_exam.into_inner();
+    if let Ok(Some(_)) = find_one_by::<_, _, Exam>(database.inner(), "exams", "name", exam.name.clone()).await {
+        error!("El examen '{}' ya existe", exam.name);
+        return Err(Status::Conflict);
     }
 
-    let query = format!(
-        "CREATE exams CONTENT {{
-            name: '{}',
-            price: {}
-        }} RETURN *;",
-        exam.name, exam.price
-    );
-
     info!("Ejecutando el query para crear el examen: {}", exam.name);
 
-    match database.query(&query).await {
-        Ok(mut results) => {
-            if let Ok(Some(_)) = results.take::<Option<Exam>>(0) {
-                info!("Examen '{}' creado correctamente", exam.name);
-                return Ok(Status::Created);
-            }
+    let name = exam.name.clone();
+    exam.id = None;
+    match create_record::<_, _, Exam>(database.inner(), "exams", exam).await {
+        Ok(Some(_)) => {
+            info!("Examen '{}' creado correctamente", name);
+            return Ok(Status::Created);
         }
+        Ok(None) => {}
         Err(err) => {
             error!("Error al crear el examen: {:?}", err);
         }
@@ -106,28 +111,16 @@ pub async fn update_exam(
     exam_id: String,
     update_data: Json<UpdateExam>,
 ) -> Result<Status, Status> {
-    let mut updates = Vec::new();
+    let record_id = parse_record_id("exams", &exam_id)?;
+    let patch = update_data.into_inner();
 
-    if let Some(name) = &update_data.name {
-        updates.push(format!("name = '{}'", name));
-    }
-    if let Some(price) = &update_data.price {
-        updates.push(format!("price = {}", price));
-    }
-
-    if updates.is_empty() {
+    if patch.name.is_none() && patch.price.is_none() && patch.tax_treatment.is_none() && patch.tax_rate.is_none() {
         return Err(Status::BadRequest);
     }
 
-    let query = format!(
-        "UPDATE {} SET {};",
-        exam_id,
-        updates.join(", ")
-    );
-
-    info!("Ejecutando query: {}", query);
+    info!("Actualizando el examen: {}", record_id);
 
-    match database.query(&query).await {
+    match merge_record(database.inner(), record_id, patch).await {
         Ok(_) => Ok(Status::Ok),
         Err(err) => {
             error!("Error al actualizar el examen: {:?}", err);
@@ -140,9 +133,9 @@ pub async fn delete_exam(
     database: &State<Surreal<Client>>,
     exam_id: String,
 ) -> Result<Status, Status> {
-    let query = format!("DELETE {};", exam_id);
+    let record_id = parse_record_id("exams", &exam_id)?;
 
-    match database.query(&query).await {
+    match delete_record(database.inner(), record_id).await {
         Ok(_) => Ok(Status::Ok),
         Err(err) => {
             error!("Error al eliminar el examen: {:?}", err);
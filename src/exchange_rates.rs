@@ -0,0 +1,194 @@
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::Surreal;
+use log::{info, warn, error};
+use chrono::NaiveDate;
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use crate::money::Money;
+use crate::business_time;
+use crate::config::AppConfig;
+
+// Tasa del día: cuántas unidades de la moneda base vale una unidad de `currency`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExchangeRate {
+    pub currency: String,
+    pub rate: f64,
+    // Día en que rige la tasa, `YYYY-MM-DD`
+    pub date: String,
+}
+
+// Tasas vigentes al momento de una venta, incluida la base (siempre 1)
+#[derive(Debug, Clone)]
+pub struct Rates {
+    pub base_currency: String,
+    rates: Vec<ExchangeRate>,
+}
+
+impl Rates {
+    pub fn new(base_currency: String, rates: Vec<ExchangeRate>) -> Rates {
+        Rates { base_currency, rates }
+    }
+
+    pub fn rate(&self, currency: &str) -> Result<f64, Status> {
+        if currency == self.base_currency {
+            return Ok(1.0);
+        }
+        match self.rates.iter().find(|rate| rate.currency == currency) {
+            Some(rate) => Ok(rate.rate),
+            None => {
+                warn!("No hay tasa de cambio vigente para {}", currency);
+                Err(Status::UnprocessableEntity)
+            }
+        }
+    }
+
+    pub fn convert(&self, amount: Money, from: &str, to: &str) -> Result<Money, Status> {
+        if from == to {
+            return Ok(amount);
+        }
+        match (Decimal::from_f64(self.rate(from)?), Decimal::from_f64(self.rate(to)?)) {
+            (Some(from_rate), Some(to_rate)) => Ok(amount.scale(from_rate / to_rate)),
+            _ => {
+                warn!("Tasa de cambio inválida entre {} y {}", from, to);
+                Err(Status::UnprocessableEntity)
+            }
+        }
+    }
+}
+
+// Los códigos se guardan en mayúsculas: "usd" y "USD" son la misma moneda
+pub fn normalize_currency(currency: &str) -> String {
+    currency.trim().to_uppercase()
+}
+
+// La última tasa publicada de cada moneda que ya esté en vigor
+pub async fn current_rates(
+    database: &State<Surreal<Client>>,
+    config: &AppConfig,
+    currencies: Vec<String>,
+) -> Result<Rates, Status> {
+    let base_currency = normalize_currency(&config.base_currency);
+    let currencies: Vec<String> = currencies
+        .iter()
+        .map(|currency| normalize_currency(currency))
+        .filter(|currency| *currency != base_currency)
+        .collect();
+
+    let query = "SELECT currency, rate, date FROM exchange_rates
+        WHERE currency IN $currencies AND date <= $today
+        ORDER BY date DESC;";
+
+    let published: Vec<ExchangeRate> = match database
+        .query(query)
+        .bind(("currencies", currencies))
+        .bind(("today", business_time::today(config.timezone).format("%Y-%m-%d").to_string()))
+        .await
+    {
+        Ok(mut results) => results.take(0).map_err(|err| {
+            error!("Error al deserializar las tasas de cambio: {:?}", err);
+            Status::InternalServerError
+        })?,
+        Err(err) => {
+            error!("Error al consultar las tasas de cambio: {:?}", err);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    // Vienen de la más reciente a la más antigua; se conserva la primera de cada moneda
+    let mut rates: Vec<ExchangeRate> = Vec::new();
+    for rate in published {
+        if !rates.iter().any(|current| current.currency == rate.currency) {
+            rates.push(rate);
+        }
+    }
+
+    Ok(Rates::new(base_currency, rates))
+}
+
+pub async fn get_exchange_rates(
+    database: &State<Surreal<Client>>,
+) -> Result<Json<Vec<ExchangeRate>>, Status> {
+    let query = "SELECT currency, rate, date FROM exchange_rates ORDER BY date DESC, currency;";
+
+    match database.query(query).await {
+        Ok(mut results) => results.take::<Vec<ExchangeRate>>(0).map(Json).map_err(|err| {
+            error!("Error al deserializar las tasas de cambio: {:?}", err);
+            Status::InternalServerError
+        }),
+        Err(err) => {
+            error!("Error al consultar las tasas de cambio: {:?}", err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// Tasas vigentes hoy, para que la caja muestre los montos en cada moneda
+pub async fn get_current_rates(
+    database: &State<Surreal<Client>>,
+    config: &AppConfig,
+) -> Result<Json<Vec<ExchangeRate>>, Status> {
+    let currencies: Vec<String> = match database.query("RETURN array::distinct(SELECT VALUE currency FROM exchange_rates);").await {
+        Ok(mut results) => results.take(0).map_err(|err| {
+            error!("Error al deserializar las monedas: {:?}", err);
+            Status::InternalServerError
+        })?,
+        Err(err) => {
+            error!("Error al consultar las monedas: {:?}", err);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    Ok(Json(current_rates(database, config, currencies).await?.rates))
+}
+
+// Registra o corrige la tasa de una moneda para un día
+pub async fn set_exchange_rate(
+    database: &State<Surreal<Client>>,
+    base_currency: &str,
+    new_rate: Json<ExchangeRate>,
+) -> Result<Status, Status> {
+    let mut rate = new_rate.into_inner();
+    rate.currency = normalize_currency(&rate.currency);
+
+    if rate.currency.is_empty() || rate.currency == normalize_currency(base_currency) {
+        warn!("Moneda inválida para una tasa de cambio: '{}'", rate.currency);
+        return Err(Status::UnprocessableEntity);
+    }
+    if !rate.rate.is_finite() || rate.rate <= 0.0 {
+        warn!("Tasa inválida para {}: {}", rate.currency, rate.rate);
+        return Err(Status::UnprocessableEntity);
+    }
+    // Se guarda la fecha normalizada: `current_rates` la compara como texto
+    rate.date = match NaiveDate::parse_from_str(&rate.date, "%Y-%m-%d") {
+        Ok(date) => date.format("%Y-%m-%d").to_string(),
+        Err(_) => {
+            warn!("Fecha inválida para la tasa de {}: '{}'", rate.currency, rate.date);
+            return Err(Status::UnprocessableEntity);
+        }
+    };
+
+    // Una sola tasa por moneda y día: el ID es `[moneda, fecha]`
+    let query = "UPSERT type::thing('exchange_rates', [$currency, $date]) CONTENT $rate;";
+
+    let result = database
+        .query(query)
+        .bind(("currency", rate.currency.clone()))
+        .bind(("date", rate.date.clone()))
+        .bind(("rate", rate.clone()))
+        .await;
+
+    match result.map(|response| response.check().err()) {
+        Ok(None) => {
+            info!("Tasa de {} para el {} fijada en {}", rate.currency, rate.date, rate.rate);
+            Ok(Status::Ok)
+        }
+        Ok(Some(err)) | Err(err) => {
+            error!("Error al guardar la tasa de cambio: {:?}", err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
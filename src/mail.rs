@@ -0,0 +1,542 @@
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use lettre::message::header::ContentType;
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use log::{info, warn, error};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::sql::Datetime;
+use surrealdb::{RecordId, Surreal};
+use crate::config::AppConfig;
+use crate::receipt_documents::{render_html, render_text};
+use crate::receipts::ReceiptJson;
+use crate::repository::parse_record_id;
+use crate::statements::MonthlySchedule;
+
+// Cifrado de la conexión SMTP. MailHog y otros buzones de prueba usan `none`.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MailSecurity {
+    None,
+    #[default]
+    Starttls,
+    Tls,
+}
+
+// Llave `mail` de Rocket.toml; la contraseña va aparte en `mail_password` (ROCKET_MAIL_PASSWORD)
+#[derive(Deserialize, Clone, Debug)]
+pub struct MailConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub security: MailSecurity,
+    #[serde(default)]
+    pub username: Option<String>,
+    // Remitente, p. ej. "Choi Taekwondo <caja@choitkd.com>"
+    pub from: String,
+    // Intentos antes de dar un correo por fallido; la espera se duplica en cada reintento
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_retry_seconds")]
+    pub retry_seconds: u64,
+    // Cada cuánto se revisa la cola
+    #[serde(default = "default_poll_seconds")]
+    pub poll_seconds: u64,
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+    // Día del mes en que se encolan solos los estados de cuenta y los recordatorios de pago
+    #[serde(default)]
+    pub statement_day: Option<u32>,
+    #[serde(default)]
+    pub reminder_day: Option<u32>,
+}
+
+fn default_port() -> u16 {
+    587
+}
+
+fn default_max_attempts() -> u32 {
+    6
+}
+
+fn default_retry_seconds() -> u64 {
+    60
+}
+
+fn default_poll_seconds() -> u64 {
+    15
+}
+
+fn default_timeout_seconds() -> u64 {
+    10
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MailKind {
+    Receipt,
+    Statement,
+    Reminder,
+    Test,
+}
+
+impl MailKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MailKind::Receipt => "receipt",
+            MailKind::Statement => "statement",
+            MailKind::Reminder => "reminder",
+            MailKind::Test => "test",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, FromFormField, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MailStatus {
+    Pending,
+    Sent,
+    Failed,
+}
+
+// Resultado de cada intento, tal como queda en la bitácora del correo
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryOutcome {
+    Sent,
+    Retry,
+    Failed,
+}
+
+// Correo por encolar
+#[derive(Serialize, Debug, Clone)]
+pub struct OutgoingMail {
+    pub kind: MailKind,
+    pub recipient: String,
+    pub subject: String,
+    pub text_body: String,
+    pub html_body: Option<String>,
+    // Registro que originó el correo: la venta o el cliente
+    pub reference: Option<String>,
+}
+
+// Contenido de un registro en `mail_messages`
+#[derive(Serialize, Debug)]
+struct NewMailRecord {
+    #[serde(flatten)]
+    mail: OutgoingMail,
+    status: MailStatus,
+    attempts: u32,
+    created_at: Datetime,
+    next_attempt_at: Datetime,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DeliveryLogEntry {
+    pub at: DateTime<Utc>,
+    pub attempt: u32,
+    pub outcome: DeliveryOutcome,
+    pub detail: String,
+}
+
+// Correo de la cola con su bitácora de entrega, para GET /admin/mail
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MailMessage {
+    pub id: String,
+    pub kind: MailKind,
+    pub recipient: String,
+    pub subject: String,
+    pub reference: Option<String>,
+    pub status: MailStatus,
+    pub attempts: u32,
+    pub created_at: Option<DateTime<Utc>>,
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub sent_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub log: Vec<DeliveryLogEntry>,
+}
+
+// Lo que el remitente necesita de un correo pendiente
+#[derive(Deserialize, Debug)]
+struct QueuedMail {
+    id: String,
+    recipient: String,
+    subject: String,
+    text_body: String,
+    html_body: Option<String>,
+    attempts: u32,
+}
+
+pub fn parse_mailbox(address: &str) -> Result<Mailbox, String> {
+    address
+        .trim()
+        .parse::<Mailbox>()
+        .map_err(|err| format!("Dirección inválida '{}': {}", address, err))
+}
+
+fn build_message(from: &str, mail: &QueuedMail) -> Result<Message, String> {
+    let builder = Message::builder()
+        .from(parse_mailbox(from)?)
+        .to(parse_mailbox(&mail.recipient)?)
+        .subject(mail.subject.clone());
+    let message = match &mail.html_body {
+        Some(html_body) => builder.multipart(MultiPart::alternative_plain_html(mail.text_body.clone(), html_body.clone())),
+        None => builder.header(ContentType::TEXT_PLAIN).body(mail.text_body.clone()),
+    };
+    message.map_err(|err| format!("No se pudo armar el correo: {}", err))
+}
+
+// Espera antes del intento `attempt + 1`: `retry_seconds`, el doble, el cuádruple... hasta 6 horas
+pub fn retry_delay(retry_seconds: u64, attempt: u32) -> Duration {
+    let factor = 1u64 << attempt.saturating_sub(1).min(16);
+    Duration::from_secs(retry_seconds.saturating_mul(factor).min(6 * 3600))
+}
+
+fn transport(settings: &MailConfig, password: Option<&str>) -> Result<AsyncSmtpTransport<Tokio1Executor>, String> {
+    let tls_parameters = || TlsParameters::new(settings.host.clone()).map_err(|err| format!("TLS inválido: {}", err));
+    let tls = match settings.security {
+        MailSecurity::None => Tls::None,
+        MailSecurity::Starttls => Tls::Required(tls_parameters()?),
+        MailSecurity::Tls => Tls::Wrapper(tls_parameters()?),
+    };
+    let mut builder = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(settings.host.as_str())
+        .port(settings.port)
+        .tls(tls)
+        .timeout(Some(Duration::from_secs(settings.timeout_seconds)));
+    if let (Some(username), Some(password)) = (&settings.username, password) {
+        builder = builder.credentials(Credentials::new(username.clone(), password.to_string()));
+    }
+    Ok(builder.build())
+}
+
+// Encola un correo. Con `key` el correo se encola una sola vez (p. ej. un estado de cuenta por
+// cliente y mes); devuelve `false` si ya estaba en la cola.
+pub async fn enqueue_mail(
+    database: &Surreal<Client>,
+    config: &AppConfig,
+    key: Option<String>,
+    mail: OutgoingMail,
+) -> Result<bool, Status> {
+    if config.mail.is_none() {
+        warn!("Correo no configurado; no se encola '{}'", mail.subject);
+        return Err(Status::ServiceUnavailable);
+    }
+    if let Err(err) = parse_mailbox(&mail.recipient) {
+        warn!("{}", err);
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let key = key.unwrap_or_else(|| {
+        rand::thread_rng().sample_iter(&Alphanumeric).take(20).map(char::from).collect()
+    });
+    let record_id = RecordId::from(("mail_messages", key));
+    let now: Datetime = Utc::now().into();
+    let record = NewMailRecord {
+        mail,
+        status: MailStatus::Pending,
+        attempts: 0,
+        created_at: now.clone(),
+        next_attempt_at: now,
+    };
+
+    let query = "LET $exists = record::exists($id);
+        IF !$exists { CREATE $id CONTENT $mail RETURN NONE };
+        RETURN !$exists;";
+    let mut response = database
+        .query(query)
+        .bind(("id", record_id.clone()))
+        .bind(("mail", record))
+        .await
+        .map_err(|err| {
+            error!("Error al encolar el correo {}: {:?}", record_id, err);
+            Status::InternalServerError
+        })?;
+
+    let errors = response.take_errors();
+    if !errors.is_empty() {
+        error!("Error al encolar el correo {}: {:?}", record_id, errors);
+        return Err(Status::InternalServerError);
+    }
+    match response.take::<Option<bool>>(2) {
+        Ok(queued) => {
+            let queued = queued.unwrap_or(false);
+            if queued {
+                info!("Correo {} encolado", record_id);
+            }
+            Ok(queued)
+        }
+        Err(err) => {
+            error!("Error al deserializar el resultado del correo {}: {:?}", record_id, err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// Encola el recibo para el cliente. Un error aquí no deshace la venta: solo se registra.
+pub async fn queue_receipt_mail(
+    database: &Surreal<Client>,
+    config: &AppConfig,
+    receipt: &ReceiptJson,
+    recipient: &str,
+) {
+    let subject = match &receipt.footer.invoice_number {
+        Some(invoice_number) => format!("Recibo {} - {}", invoice_number, receipt.header.title),
+        None => format!("Recibo - {}", receipt.header.title),
+    };
+    // Un recibo por venta: si la caja lo pide dos veces no se envía dos veces
+    let key = receipt
+        .footer
+        .sale_id
+        .as_deref()
+        .and_then(|sale_id| sale_id.split_once(':'))
+        .map(|(_, id)| format!("receipt_{}", id));
+    let mail = OutgoingMail {
+        kind: MailKind::Receipt,
+        recipient: recipient.to_string(),
+        subject,
+        text_body: render_text(receipt),
+        html_body: Some(render_html(receipt)),
+        reference: receipt.footer.sale_id.clone(),
+    };
+    if let Err(status) = enqueue_mail(database, config, key, mail).await {
+        warn!("No se encoló el recibo para {}: {}", recipient, status);
+    }
+}
+
+// Toma el correo para este proceso. Si el proceso muere a medio envío, el correo vuelve a la
+// cola cuando vence el plazo.
+async fn claim(database: &Surreal<Client>, record_id: &RecordId) -> Result<bool, surrealdb::Error> {
+    let mut response = database
+        .query("UPDATE $id SET next_attempt_at = time::now() + 5m
+            WHERE status = 'pending' AND next_attempt_at <= time::now() RETURN VALUE id;")
+        .bind(("id", record_id.clone()))
+        .await?;
+    Ok(!response.take::<Vec<RecordId>>(0)?.is_empty())
+}
+
+async fn record_attempt(
+    database: &Surreal<Client>,
+    settings: &MailConfig,
+    record_id: &RecordId,
+    attempt: u32,
+    result: Result<(), (bool, String)>,
+) -> Result<(), surrealdb::Error> {
+    let now = Utc::now();
+    let (status, outcome, detail, next_attempt_at) = match result {
+        Ok(()) => (MailStatus::Sent, DeliveryOutcome::Sent, String::new(), now),
+        // Los rechazos permanentes del servidor (5xx) no se reintentan
+        Err((permanent, detail)) if permanent || attempt >= settings.max_attempts => {
+            (MailStatus::Failed, DeliveryOutcome::Failed, detail, now)
+        }
+        Err((_, detail)) => {
+            let delay = retry_delay(settings.retry_seconds, attempt);
+            let next = now + chrono::Duration::from_std(delay).unwrap_or_else(|_| chrono::Duration::hours(6));
+            (MailStatus::Pending, DeliveryOutcome::Retry, detail, next)
+        }
+    };
+
+    match outcome {
+        DeliveryOutcome::Sent => info!("Correo {} enviado en el intento {}", record_id, attempt),
+        DeliveryOutcome::Retry => warn!("Correo {} no enviado (intento {}): {}", record_id, attempt, detail),
+        DeliveryOutcome::Failed => error!("Correo {} descartado tras {} intentos: {}", record_id, attempt, detail),
+    }
+
+    database
+        .query("UPDATE $id SET
+                attempts = $attempt,
+                status = $status,
+                next_attempt_at = $next_attempt_at,
+                sent_at = IF $status = 'sent' THEN time::now() ELSE sent_at END,
+                last_error = IF $detail = '' THEN NONE ELSE $detail END,
+                log = array::append(log ?? [], { at: time::now(), attempt: $attempt, outcome: $outcome, detail: $detail })
+            RETURN NONE;")
+        .bind(("id", record_id.clone()))
+        .bind(("attempt", attempt))
+        .bind(("status", status))
+        .bind(("next_attempt_at", Datetime::from(next_attempt_at)))
+        .bind(("outcome", outcome))
+        .bind(("detail", detail))
+        .await?
+        .check()?;
+    Ok(())
+}
+
+// Envía los correos vencidos de la cola, de a un lote por vuelta
+async fn send_pending(
+    database: &Surreal<Client>,
+    settings: &MailConfig,
+    transport: &AsyncSmtpTransport<Tokio1Executor>,
+) -> Result<(), surrealdb::Error> {
+    let pending = database
+        .query("SELECT <string> id AS id, recipient, subject, text_body, html_body, attempts, next_attempt_at FROM mail_messages
+            WHERE status = 'pending' AND next_attempt_at <= time::now()
+            ORDER BY next_attempt_at LIMIT 20;")
+        .await?
+        .take::<Vec<QueuedMail>>(0)?;
+
+    for mail in pending {
+        let Ok(record_id) = parse_record_id("mail_messages", &mail.id) else {
+            continue;
+        };
+        if !claim(database, &record_id).await? {
+            continue;
+        }
+
+        let attempt = mail.attempts + 1;
+        let result = match build_message(&settings.from, &mail) {
+            Ok(message) => transport
+                .send(message)
+                .await
+                .map(|_| ())
+                .map_err(|err| (err.is_permanent(), err.to_string())),
+            Err(err) => Err((true, err)),
+        };
+        record_attempt(database, settings, &record_id, attempt, result).await?;
+    }
+    Ok(())
+}
+
+// Tarea de fondo que vacía la cola y encola los correos mensuales; se lanza al iniciar Rocket
+pub async fn run_mail_sender(database: Surreal<Client>, config: AppConfig) {
+    let Some(settings) = config.mail.clone() else {
+        info!("Correo deshabilitado: no hay sección `mail` en la configuración");
+        return;
+    };
+    let transport = match transport(&settings, config.mail_password.as_deref()) {
+        Ok(transport) => transport,
+        Err(err) => {
+            error!("No se pudo preparar el envío de correos: {}", err);
+            return;
+        }
+    };
+    info!("Envío de correos por {}:{} activo", settings.host, settings.port);
+
+    let mut schedule = MonthlySchedule::default();
+    let mut interval = rocket::tokio::time::interval(Duration::from_secs(settings.poll_seconds.max(1)));
+    loop {
+        interval.tick().await;
+        schedule.run(&database, &config, &settings).await;
+        if let Err(err) = send_pending(&database, &settings, &transport).await {
+            error!("Error al procesar la cola de correos: {:?}", err);
+        }
+    }
+}
+
+// Últimos correos de la cola, con su bitácora; opcionalmente solo los de un estado
+pub async fn get_mail_messages(
+    database: &Surreal<Client>,
+    status: Option<MailStatus>,
+    limit: u32,
+) -> Result<Json<Vec<MailMessage>>, Status> {
+    let query = "SELECT <string> id AS id, kind, recipient, subject, reference, status, attempts,
+            created_at, next_attempt_at, sent_at, last_error, log ?? [] AS log
+        FROM mail_messages WHERE $status = NONE OR status = $status
+        ORDER BY created_at DESC LIMIT $limit;";
+
+    match database.query(query).bind(("status", status)).bind(("limit", limit)).await {
+        Ok(mut results) => results.take::<Vec<MailMessage>>(0).map(Json).map_err(|err| {
+            error!("Error al deserializar los correos: {:?}", err);
+            Status::InternalServerError
+        }),
+        Err(err) => {
+            error!("Error al consultar los correos: {:?}", err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// Devuelve a la cola un correo que no se pudo enviar, con los intentos en cero
+pub async fn retry_mail(database: &Surreal<Client>, mail_id: String) -> Result<Status, Status> {
+    let record_id = parse_record_id("mail_messages", &mail_id)?;
+    let query = "SELECT VALUE status FROM ONLY $id;
+        UPDATE $id SET status = 'pending', attempts = 0, next_attempt_at = time::now()
+            WHERE status = 'failed' RETURN VALUE id;";
+
+    match database.query(query).bind(("id", record_id.clone())).await {
+        Ok(mut results) => {
+            let status = results.take::<Option<MailStatus>>(0);
+            let updated = results.take::<Vec<RecordId>>(1);
+            match (status, updated) {
+                (Ok(None), _) => {
+                    warn!("El correo {} no existe", record_id);
+                    Err(Status::NotFound)
+                }
+                (Ok(Some(_)), Ok(updated)) if updated.is_empty() => {
+                    warn!("El correo {} no está fallido; no se reintenta", record_id);
+                    Err(Status::Conflict)
+                }
+                (Ok(Some(_)), Ok(_)) => {
+                    info!("Correo {} devuelto a la cola", record_id);
+                    Ok(Status::Ok)
+                }
+                (Err(err), _) | (_, Err(err)) => {
+                    error!("Error al deserializar el correo {}: {:?}", record_id, err);
+                    Err(Status::InternalServerError)
+                }
+            }
+        }
+        Err(err) => {
+            error!("Error al reintentar el correo {}: {:?}", record_id, err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TestMailRequest {
+    pub recipient: String,
+}
+
+// Correo de prueba para revisar la configuración, p. ej. contra MailHog
+pub async fn queue_test_mail(
+    database: &Surreal<Client>,
+    config: &AppConfig,
+    request: Json<TestMailRequest>,
+) -> Result<Status, Status> {
+    let mail = OutgoingMail {
+        kind: MailKind::Test,
+        recipient: request.into_inner().recipient,
+        subject: "Correo de prueba".to_string(),
+        text_body: "Si recibió este correo, el envío desde el sistema funciona.".to_string(),
+        html_body: None,
+        reference: None,
+    };
+    enqueue_mail(database, config, None, mail).await.map(|_| Status::Accepted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_delay_doubles_until_the_cap() {
+        assert_eq!(retry_delay(60, 1), Duration::from_secs(60));
+        assert_eq!(retry_delay(60, 3), Duration::from_secs(240));
+        assert_eq!(retry_delay(60, 30), Duration::from_secs(6 * 3600));
+    }
+
+    #[test]
+    fn build_message_sends_text_and_html() {
+        let mail = QueuedMail {
+            id: "mail_messages:abc".to_string(),
+            recipient: "Ana Pérez <ana@example.com>".to_string(),
+            subject: "Recibo RS-000123 - Choi Taekwondo".to_string(),
+            text_body: "Total 1500.00".to_string(),
+            html_body: Some("<p>Total 1500.00</p>".to_string()),
+            attempts: 0,
+        };
+        let message = build_message("Choi Taekwondo <caja@example.com>", &mail).unwrap();
+        let formatted = String::from_utf8(message.formatted()).unwrap();
+        assert!(formatted.contains("To: =?utf-8?b?"));
+        assert!(formatted.contains("multipart/alternative"));
+        assert!(build_message("sin arroba", &mail).is_err());
+    }
+}
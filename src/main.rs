@@ -1,5 +1,6 @@
 #[macro_use]
 extern crate rocket;
+mod config;
 mod crud;
 mod database;
 mod routers;
@@ -8,17 +9,36 @@ mod promos;
 mod crud_sales;
 mod crud_clients;
 mod auth;
+mod permissions;
 mod receipts;
+mod repository;
+mod roles;
 mod exams;
 mod crud_bundles;
 mod schedules;
-//mod android_printer;
+mod sessions;
+mod checkout;
+mod pricing;
+mod refunds;
+mod exchange_rates;
+mod money;
+mod taxes;
+mod shifts;
+mod reports;
+mod business_time;
+mod printer;
+mod receipt_documents;
+mod branches;
+mod mail;
+mod statements;
 
 use crate::routers::admin::routes;
 use surrealdb::Surreal;
 use surrealdb::engine::remote::ws::Client;
 use rocket::Request;
+use rocket::fairing::AdHoc;
 use crate::rocket::yansi::Paint;
+use crate::config::AppConfig;
 
 #[catch(500)]
 fn internal_error(req: &Request) -> String {
@@ -26,9 +46,19 @@ fn internal_error(req: &Request) -> String {
 }
 #[launch]
 async fn rocket() -> _ {
-    let db: Surreal<Client> = database::connect_db().await.expect("fallo de conexión a la DB");
-    rocket::build()
+    let figment = rocket::Config::figment();
+    let config = AppConfig::from_figment(&figment).unwrap_or_else(|err| panic!("{}", err));
+    let db: Surreal<Client> = database::connect_db(&config).await.expect("fallo de conexión a la DB");
+    crud_sales::migrate_sale_dates(&db).await.expect("fallo al migrar las fechas de las ventas");
+    rocket::custom(figment)
         .manage(db)
+        .manage(config)
+        .attach(AdHoc::on_liftoff("Cola de correos", |rocket| Box::pin(async move {
+            if let (Some(db), Some(config)) = (rocket.state::<Surreal<Client>>(), rocket.state::<AppConfig>()) {
+                rocket::tokio::spawn(mail::run_mail_sender(db.clone(), config.clone()));
+            }
+        })))
+        .mount("/auth", routers::auth::routes())
         .mount("/admin", routes())
         .mount("/cashier", routers::cashier::routes())
         .register("/", catchers![internal_error]) 
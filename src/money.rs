@@ -0,0 +1,286 @@
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use std::str::FromStr;
+
+// Monto en centavos. Toda la aritmética de precios, descuentos y totales se hace en
+// enteros; solo se redondea al leer un decimal o al aplicar un porcentaje o una tasa.
+//
+// Regla de redondeo: al centavo más cercano, y los medios centavos se alejan del cero
+// (2.345 -> 2.35, -2.345 -> -2.35), igual que en la caja registradora.
+//
+// En SurrealDB se guarda como `decimal` con dos posiciones; en el JSON de la API viaja
+// como número (`12.5`). Al leer se aceptan enteros, flotantes, decimales y cadenas,
+// así que los registros anteriores guardados como `float` siguen funcionando.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Money(i64);
+
+const ROUNDING: RoundingStrategy = RoundingStrategy::MidpointAwayFromZero;
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    pub const fn from_cents(cents: i64) -> Money {
+        Money(cents)
+    }
+
+    pub const fn cents(self) -> i64 {
+        self.0
+    }
+
+    pub fn from_decimal(amount: Decimal) -> Option<Money> {
+        (amount.round_dp_with_strategy(2, ROUNDING) * Decimal::ONE_HUNDRED)
+            .to_i64()
+            .map(Money)
+    }
+
+    pub fn to_decimal(self) -> Decimal {
+        Decimal::new(self.0, 2)
+    }
+
+    // Solo para valores que llegan como `f64` (tasas, porcentajes, datos anteriores)
+    pub fn from_f64(amount: f64) -> Option<Money> {
+        Decimal::from_f64(amount).and_then(Money::from_decimal)
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.to_decimal().to_f64().unwrap_or_default()
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn times(self, quantity: u32) -> Money {
+        Money(self.0 * quantity as i64)
+    }
+
+    // Multiplica por un factor decimal (porcentaje, tasa de cambio) y redondea
+    pub fn scale(self, factor: Decimal) -> Money {
+        Money::from_decimal(self.to_decimal() * factor).unwrap_or(self)
+    }
+
+    // `part / whole` de este monto, para repartir descuentos y devoluciones parciales
+    pub fn prorate(self, part: i64, whole: i64) -> Money {
+        if whole == 0 {
+            return Money::ZERO;
+        }
+        self.scale(Decimal::from(part) / Decimal::from(whole))
+    }
+
+    pub fn percent(self, percentage: Decimal) -> Money {
+        self.scale(percentage / Decimal::ONE_HUNDRED)
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.2}", self.to_decimal())
+    }
+}
+
+impl FromStr for Money {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Decimal::from_str(value.trim())
+            .ok()
+            .and_then(Money::from_decimal)
+            .ok_or_else(|| format!("Monto inválido: '{}'", value))
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+
+    fn add(self, other: Money) -> Money {
+        Money(self.0 + other.0)
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+
+    fn sub(self, other: Money) -> Money {
+        Money(self.0 - other.0)
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+
+    fn neg(self) -> Money {
+        Money(-self.0)
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, other: Money) {
+        self.0 += other.0;
+    }
+}
+
+impl SubAssign for Money {
+    fn sub_assign(&mut self, other: Money) {
+        self.0 -= other.0;
+    }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Money>>(iter: I) -> Money {
+        Money(iter.map(|money| money.0).sum())
+    }
+}
+
+impl<'a> Sum<&'a Money> for Money {
+    fn sum<I: Iterator<Item = &'a Money>>(iter: I) -> Money {
+        iter.copied().sum()
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // El serializador de SurrealDB no es "human readable"; el de serde_json sí
+        if serializer.is_human_readable() {
+            serializer.serialize_f64(self.to_f64())
+        } else {
+            surrealdb::sql::Number::Decimal(self.to_decimal()).serialize(serializer)
+        }
+    }
+}
+
+// Decimales que no son montos, como los porcentajes: se guardan y viajan igual que `Money`
+// pero sin redondear al centavo. Para usar con `#[serde(with = "decimal_number")]`.
+pub mod decimal_number {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &Decimal, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_f64(value.to_f64().unwrap_or_default())
+        } else {
+            surrealdb::sql::Number::Decimal(*value).serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Decimal, D::Error> {
+        deserializer.deserialize_any(DecimalVisitor)
+    }
+
+    // Lo mismo para campos opcionales
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(value: &Option<Decimal>, serializer: S) -> Result<S::Ok, S::Error> {
+            match value {
+                Some(value) => super::serialize(value, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Decimal>, D::Error> {
+            #[derive(Deserialize)]
+            struct Wrapper(#[serde(deserialize_with = "super::deserialize")] Decimal);
+            Option::<Wrapper>::deserialize(deserializer).map(|wrapper| wrapper.map(|Wrapper(value)| value))
+        }
+    }
+}
+
+// Acepta enteros, flotantes, decimales y cadenas, como `MoneyVisitor`
+struct DecimalVisitor;
+
+impl Visitor<'_> for DecimalVisitor {
+    type Value = Decimal;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("un número decimal")
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Decimal, E> {
+        Ok(Decimal::from(value))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Decimal, E> {
+        Ok(Decimal::from(value))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Decimal, E> {
+        Decimal::from_f64(value).ok_or_else(|| E::custom(format!("Número inválido: {}", value)))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Decimal, E> {
+        Decimal::from_str(value.trim()).map_err(|_| E::custom(format!("Número inválido: '{}'", value)))
+    }
+}
+
+struct MoneyVisitor;
+
+impl Visitor<'_> for MoneyVisitor {
+    type Value = Money;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("un monto numérico")
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Money, E> {
+        Money::from_decimal(Decimal::from(value)).ok_or_else(|| E::custom(format!("Monto fuera de rango: {}", value)))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Money, E> {
+        Money::from_decimal(Decimal::from(value)).ok_or_else(|| E::custom(format!("Monto fuera de rango: {}", value)))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Money, E> {
+        Money::from_f64(value).ok_or_else(|| E::custom(format!("Monto inválido: {}", value)))
+    }
+
+    // SurrealDB entrega los `decimal` como cadena
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Money, E> {
+        value.parse().map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(MoneyVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_cents_round_away_from_zero() {
+        assert_eq!(Money::from_str("2.345").unwrap(), Money::from_cents(235));
+        assert_eq!(Money::from_str("-2.345").unwrap(), Money::from_cents(-235));
+        assert_eq!(Money::from_str("2.344").unwrap(), Money::from_cents(234));
+    }
+
+    #[test]
+    fn float_inputs_do_not_drift() {
+        // 0.1 + 0.2 en f64 es 0.30000000000000004
+        let total: Money = [0.1, 0.2].iter().map(|amount| Money::from_f64(*amount).unwrap()).sum();
+        assert_eq!(total, Money::from_cents(30));
+        assert_eq!(Money::from_f64(19.99).unwrap().times(3), Money::from_cents(5997));
+    }
+
+    #[test]
+    fn percent_and_prorate_round_to_cents() {
+        let subtotal = Money::from_cents(1999);
+        assert_eq!(subtotal.percent(Decimal::from(15)), Money::from_cents(300));
+        assert_eq!(Money::from_cents(1000).prorate(1, 3), Money::from_cents(333));
+        assert_eq!(Money::from_cents(1000).prorate(1, 0), Money::ZERO);
+    }
+
+    #[test]
+    fn json_uses_plain_numbers() {
+        let money: Money = serde_json::from_str("12.5").unwrap();
+        assert_eq!(money, Money::from_cents(1250));
+        assert_eq!(serde_json::to_string(&money).unwrap(), "12.5");
+        assert_eq!(money.to_string(), "12.50");
+    }
+}
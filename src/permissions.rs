@@ -0,0 +1,315 @@
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::sql::Thing;
+use surrealdb::Surreal;
+use log::{info, warn, error};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use crate::auth::AuthenticatedUser;
+use crate::roles::Role;
+
+// Capacidad con nombre que una ruta puede exigir, p. ej. `sales.create`
+pub trait Permission {
+    const NAME: &'static str;
+}
+
+macro_rules! permissions {
+    ($($ty:ident => $name:literal),* $(,)?) => {
+        $(
+            pub struct $ty;
+            impl Permission for $ty {
+                const NAME: &'static str = $name;
+            }
+        )*
+
+        // Todas las capacidades conocidas; la matriz no acepta otras
+        pub const ALL_PERMISSIONS: &[&str] = &[$($name),*];
+    };
+}
+
+permissions! {
+    ClientsRead => "clients.read",
+    ClientsWrite => "clients.write",
+    ClientsDelete => "clients.delete",
+    SalesRead => "sales.read",
+    SalesCreate => "sales.create",
+    SalesRefund => "sales.refund",
+    SalesVoid => "sales.void",
+    PromosRead => "promos.read",
+    PromosWrite => "promos.write",
+    InventoryRead => "inventory.read",
+    InventoryWrite => "inventory.write",
+    InventoryManage => "inventory.manage",
+    ExamsRead => "exams.read",
+    ExamsWrite => "exams.write",
+    BundlesRead => "bundles.read",
+    BundlesWrite => "bundles.write",
+    BundlesManage => "bundles.manage",
+    PaymentsRead => "payments.read",
+    PaymentsUpdate => "payments.update",
+    ReceiptsPrint => "receipts.print",
+    ExchangeRatesRead => "exchange_rates.read",
+    ExchangeRatesWrite => "exchange_rates.write",
+    TaxesRead => "taxes.read",
+    TaxesWrite => "taxes.write",
+    ShiftsManage => "shifts.manage",
+    ReportsRead => "reports.read",
+    ReportsClose => "reports.close",
+    BranchesRead => "branches.read",
+    BranchesWrite => "branches.write",
+    MailRead => "mail.read",
+    MailSend => "mail.send",
+}
+
+// Matriz usada cuando un rol todavía no tiene registro en la tabla `permissions`
+fn default_permissions(role: Role) -> Vec<String> {
+    let capabilities: &[&str] = match role {
+        Role::Admin => ALL_PERMISSIONS,
+        Role::Cashier => &[
+            "clients.read",
+            "clients.write",
+            "sales.read",
+            "sales.create",
+            "promos.read",
+            "inventory.read",
+            "inventory.write",
+            "exams.read",
+            "bundles.read",
+            "bundles.write",
+            "payments.read",
+            "payments.update",
+            "receipts.print",
+            "exchange_rates.read",
+            "shifts.manage",
+        ],
+        Role::Instructor => &[
+            "clients.read",
+            "exams.read",
+            "payments.read",
+        ],
+        // Lo mismo que la caja, más autorizar devoluciones y anulaciones
+        Role::Supervisor => &[
+            "clients.read",
+            "clients.write",
+            "sales.read",
+            "sales.create",
+            "sales.refund",
+            "sales.void",
+            "reports.read",
+            "promos.read",
+            "inventory.read",
+            "inventory.write",
+            "exams.read",
+            "bundles.read",
+            "bundles.write",
+            "payments.read",
+            "payments.update",
+            "receipts.print",
+            "exchange_rates.read",
+            "shifts.manage",
+        ],
+    };
+    capabilities.iter().map(|c| c.to_string()).collect()
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RolePermissions {
+    pub role: Role,
+    pub capabilities: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UpdateRolePermissions {
+    pub capabilities: Vec<String>,
+}
+
+pub async fn get_role_permissions(
+    database: &State<Surreal<Client>>,
+    role: Role,
+) -> Result<Vec<String>, Status> {
+    let query = "SELECT VALUE capabilities FROM ONLY $record;";
+
+    match database
+        .query(query)
+        .bind(("record", Thing::from(("permissions", role.as_str()))))
+        .await
+    {
+        Ok(mut results) => match results.take::<Option<Vec<String>>>(0) {
+            Ok(Some(capabilities)) => Ok(capabilities),
+            Ok(None) => Ok(default_permissions(role)),
+            Err(err) => {
+                error!("Error al deserializar los permisos del rol {}: {:?}", role, err);
+                Err(Status::InternalServerError)
+            }
+        },
+        Err(err) => {
+            error!("Error al consultar los permisos del rol {}: {:?}", role, err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// Indica si alguno de los roles tiene la capacidad; el administrador las tiene todas
+pub async fn roles_have_permission(
+    database: &State<Surreal<Client>>,
+    roles: &[Role],
+    permission: &str,
+) -> Result<bool, Status> {
+    if roles.contains(&Role::Admin) {
+        return Ok(true);
+    }
+    for role in roles {
+        if get_role_permissions(database, *role).await?.iter().any(|c| c == permission) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+pub async fn get_permissions(
+    database: &State<Surreal<Client>>,
+) -> Result<Json<Vec<RolePermissions>>, Status> {
+    let query = "SELECT role, capabilities FROM permissions ORDER BY role;";
+
+    match database.query(query).await {
+        Ok(mut results) => {
+            let mut matrix: Vec<RolePermissions> = results.take(0).map_err(|err| {
+                error!("Error al deserializar la matriz de permisos: {:?}", err);
+                Status::InternalServerError
+            })?;
+
+            // Mostrar también los roles que siguen usando la matriz por defecto
+            for role in Role::ALL {
+                if !matrix.iter().any(|entry| entry.role == role) {
+                    matrix.push(RolePermissions {
+                        role,
+                        capabilities: default_permissions(role),
+                    });
+                }
+            }
+            Ok(Json(matrix))
+        }
+        Err(err) => {
+            error!("Error al obtener la matriz de permisos: {:?}", err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+pub async fn update_role_permissions(
+    database: &State<Surreal<Client>>,
+    role: Role,
+    update_data: Json<UpdateRolePermissions>,
+) -> Result<Status, Status> {
+    let mut capabilities = update_data.into_inner().capabilities;
+
+    if let Some(unknown) = capabilities.iter().find(|c| !ALL_PERMISSIONS.contains(&c.as_str())) {
+        warn!("Capacidad desconocida '{}' para el rol {}", unknown, role);
+        return Err(Status::UnprocessableEntity);
+    }
+    capabilities.sort();
+    capabilities.dedup();
+
+    let query = "UPSERT $record CONTENT { role: $role, capabilities: $capabilities };";
+
+    match database
+        .query(query)
+        .bind(("record", Thing::from(("permissions", role.as_str()))))
+        .bind(("role", role))
+        .bind(("capabilities", capabilities))
+        .await
+    {
+        Ok(_) => {
+            info!("Permisos del rol {} actualizados.", role);
+            Ok(Status::Ok)
+        }
+        Err(err) => {
+            error!("Error al actualizar los permisos del rol {}: {:?}", role, err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// Guardia que solo deja pasar a administradores
+pub struct RequireAdmin {
+    pub user: AuthenticatedUser,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequireAdmin {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user = match request.guard::<AuthenticatedUser>().await {
+            Outcome::Success(user) => user,
+            Outcome::Error(err) => return Outcome::Error(err),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+
+        if user.is_admin() {
+            Outcome::Success(RequireAdmin { user })
+        } else {
+            warn!("{} intentó acceder a una ruta de administrador", user.username);
+            Outcome::Error((Status::Forbidden, "Admin role required"))
+        }
+    }
+}
+
+impl Deref for RequireAdmin {
+    type Target = AuthenticatedUser;
+
+    fn deref(&self) -> &Self::Target {
+        &self.user
+    }
+}
+
+// Guardia que exige que alguno de los roles del usuario tenga la capacidad `P`
+pub struct RequirePermission<P: Permission> {
+    pub user: AuthenticatedUser,
+    permission: PhantomData<fn() -> P>,
+}
+
+#[rocket::async_trait]
+impl<'r, P: Permission> FromRequest<'r> for RequirePermission<P> {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user = match request.guard::<AuthenticatedUser>().await {
+            Outcome::Success(user) => user,
+            Outcome::Error(err) => return Outcome::Error(err),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+
+        // El administrador conserva todas las capacidades para no quedar bloqueado
+        if user.is_admin() {
+            return Outcome::Success(RequirePermission { user, permission: PhantomData });
+        }
+
+        let Outcome::Success(database) = request.guard::<&State<Surreal<Client>>>().await else {
+            error!("La base de datos no está disponible para validar permisos");
+            return Outcome::Error((Status::InternalServerError, "Database unavailable"));
+        };
+
+        // Un usuario con varios roles obtiene la unión de sus capacidades
+        match roles_have_permission(database, &user.roles, P::NAME).await {
+            Ok(true) => Outcome::Success(RequirePermission { user, permission: PhantomData }),
+            Ok(false) => {
+                warn!("{} (roles {:?}) no tiene el permiso {}", user.username, user.roles, P::NAME);
+                Outcome::Error((Status::Forbidden, "Missing permission"))
+            }
+            Err(status) => Outcome::Error((status, "Could not load permissions")),
+        }
+    }
+}
+
+impl<P: Permission> Deref for RequirePermission<P> {
+    type Target = AuthenticatedUser;
+
+    fn deref(&self) -> &Self::Target {
+        &self.user
+    }
+}
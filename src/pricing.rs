@@ -0,0 +1,521 @@
+use rocket::http::Status;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::{RecordId, Surreal};
+use log::{warn, error};
+use std::str::FromStr;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use crate::crud_sales::{ProductWithQuantity, SaleItem, SELLABLE_TABLES};
+use crate::exchange_rates::{normalize_currency, Rates};
+use crate::money::Money;
+use crate::taxes::{line_tax, load_tax_rates, tax_percentage, TaxTreatment, DEFAULT_TAX_RATE};
+use crate::promos::{check_discount, DiscountCart, DiscountCheck, DiscountCode};
+use crate::repository::parse_record_id_in;
+
+// Diferencia máxima aceptada entre los montos que calcula la caja y los del servidor
+pub const TOTALS_TOLERANCE: Money = Money::from_cents(1);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiscountKind {
+    #[default]
+    #[serde(rename = "percentage", alias = "porcentaje")]
+    Percentage,
+    #[serde(rename = "fixed", alias = "fijo")]
+    Fixed,
+}
+
+// Valor de un código de descuento: un porcentaje del subtotal o un monto fijo en la moneda base
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiscountValue {
+    Percentage(Decimal),
+    Fixed(Money),
+}
+
+impl DiscountValue {
+    // Porcentaje entre 0 y 100 o monto no negativo; `None` si el valor no sirve para el tipo
+    pub fn new(kind: DiscountKind, value: Decimal) -> Option<DiscountValue> {
+        match kind {
+            DiscountKind::Percentage if (Decimal::ZERO..=Decimal::ONE_HUNDRED).contains(&value) => {
+                Some(DiscountValue::Percentage(value))
+            }
+            DiscountKind::Fixed if value >= Decimal::ZERO => Money::from_decimal(value).map(DiscountValue::Fixed),
+            _ => None,
+        }
+    }
+
+    pub fn value(&self) -> Decimal {
+        match self {
+            DiscountValue::Percentage(percentage) => *percentage,
+            DiscountValue::Fixed(amount) => amount.to_decimal(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaymentMethod {
+    #[serde(rename = "efectivo", alias = "cash")]
+    Cash,
+    #[serde(rename = "tarjeta", alias = "card")]
+    Card,
+    #[serde(rename = "transferencia", alias = "transfer")]
+    Transfer,
+}
+
+impl PaymentMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PaymentMethod::Cash => "efectivo",
+            PaymentMethod::Card => "tarjeta",
+            PaymentMethod::Transfer => "transferencia",
+        }
+    }
+}
+
+// Acepta el `type_` libre de las ventas anteriores, p. ej. "Efectivo" o "card"
+impl FromStr for PaymentMethod {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "efectivo" | "cash" => Ok(PaymentMethod::Cash),
+            "tarjeta" | "card" => Ok(PaymentMethod::Card),
+            "transferencia" | "transfer" => Ok(PaymentMethod::Transfer),
+            _ => Err(format!("Forma de pago desconocida: '{}'", value)),
+        }
+    }
+}
+
+// Una de las formas de pago con que se cubre la venta
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Tender {
+    pub method: PaymentMethod,
+    pub amount: Money,
+    pub currency: String,
+    pub reference: Option<String>,
+    // Tasa a la moneda base al momento de la venta; la fija el servidor
+    #[serde(default)]
+    pub exchange_rate: Option<f64>,
+}
+
+// Línea del carrito con el nombre, el precio y el impuesto vigentes al momento del cobro;
+// `line_total` es el importe antes del descuento de la venta y del impuesto
+#[derive(Debug)]
+pub struct PricedLine {
+    pub product: RecordId,
+    pub name: String,
+    // Solo los productos tienen categoría
+    pub category: Option<String>,
+    pub unit_price: Money,
+    pub quantity: u32,
+    pub line_total: Money,
+    pub available: Option<u32>,
+    pub tax_treatment: TaxTreatment,
+    pub tax_rate: String,
+    pub tax_percentage: Decimal,
+}
+
+// `subtotal` es la suma de precios antes del descuento; `tax` incluye tanto el impuesto
+// sumado como el ya incluido en el precio, y `total` es lo que paga el cliente
+#[derive(Debug, Clone, Copy)]
+pub struct SaleTotals {
+    pub subtotal: Money,
+    pub discount: Money,
+    pub tax: Money,
+    pub total: Money,
+    pub amount_tendered: Money,
+    pub change: Money,
+}
+
+#[derive(Deserialize, Debug)]
+struct SellableRecord {
+    id: RecordId,
+    name: Option<String>,
+    category: Option<String>,
+    price: Option<Money>,
+    quantity: Option<u32>,
+    tax_treatment: Option<TaxTreatment>,
+    tax_rate: Option<String>,
+}
+
+// El mismo artículo escaneado varias veces se agrupa en una sola línea
+pub fn cart_quantities(products: &[ProductWithQuantity]) -> Result<Vec<(RecordId, u32)>, Status> {
+    let mut quantities: Vec<(RecordId, u32)> = Vec::new();
+    for product in products {
+        if product.qnt == 0 {
+            warn!("Cantidad inválida para {} en el carrito", product.id);
+            return Err(Status::UnprocessableEntity);
+        }
+        let record_id = parse_record_id_in(SELLABLE_TABLES, &product.id)?;
+        match quantities.iter_mut().find(|(id, _)| *id == record_id) {
+            Some((_, quantity)) => *quantity += product.qnt,
+            None => quantities.push((record_id, product.qnt)),
+        }
+    }
+
+    if quantities.is_empty() {
+        warn!("Carrito recibido sin productos");
+        return Err(Status::BadRequest);
+    }
+    Ok(quantities)
+}
+
+// Resuelve cada línea contra products, exams y monthly con su precio actual,
+// convertido de la moneda base a la moneda de la venta
+pub async fn price_lines(
+    database: &State<Surreal<Client>>,
+    quantities: Vec<(RecordId, u32)>,
+    rates: &Rates,
+    currency: &str,
+) -> Result<Vec<PricedLine>, Status> {
+    let ids: Vec<RecordId> = quantities.iter().map(|(id, _)| id.clone()).collect();
+    let records: Vec<SellableRecord> = match database
+        .query("SELECT id, name, category, price, quantity, tax_treatment, tax_rate FROM $ids;")
+        .bind(("ids", ids))
+        .await
+    {
+        Ok(mut results) => results.take(0).map_err(|err| {
+            error!("Error al deserializar los productos del carrito: {:?}", err);
+            Status::InternalServerError
+        })?,
+        Err(err) => {
+            error!("Error al consultar los productos del carrito: {:?}", err);
+            return Err(Status::InternalServerError);
+        }
+    };
+    let tax_rates = load_tax_rates(database).await?;
+
+    quantities
+        .into_iter()
+        .map(|(product, quantity)| {
+            let Some(record) = records.iter().find(|record| record.id == product) else {
+                warn!("El producto {} no existe", product);
+                return Err(Status::NotFound);
+            };
+            let Some(base_price) = record.price else {
+                warn!("El producto {} no tiene precio", product);
+                return Err(Status::UnprocessableEntity);
+            };
+            let unit_price = rates.convert(base_price, &rates.base_currency, currency)?;
+
+            let tax_treatment = record.tax_treatment.unwrap_or_default();
+            let tax_rate = record.tax_rate.clone().unwrap_or_else(|| DEFAULT_TAX_RATE.to_string());
+            let tax_percentage = match tax_treatment {
+                TaxTreatment::Exempt => Decimal::ZERO,
+                _ => tax_percentage(&tax_rates, &tax_rate)?,
+            };
+
+            Ok(PricedLine {
+                name: record.name.clone().unwrap_or_else(|| product.to_string()),
+                category: record.category.clone().filter(|category| !category.is_empty()),
+                product,
+                unit_price,
+                quantity,
+                line_total: unit_price.times(quantity),
+                available: record.quantity,
+                tax_treatment,
+                tax_rate: if tax_treatment == TaxTreatment::Exempt { String::new() } else { tax_rate },
+                tax_percentage,
+            })
+        })
+        .collect()
+}
+
+// Busca el código promocional de la venta y lo valida contra el carrito; un código vacío
+// significa sin descuento
+pub async fn find_discount(
+    database: &State<Surreal<Client>>,
+    code: &str,
+    cart: &DiscountCart<'_>,
+) -> Result<Option<DiscountCode>, Status> {
+    if code.trim().is_empty() {
+        return Ok(None);
+    }
+
+    match check_discount(database, code, cart).await? {
+        DiscountCheck::Applies(discount) => Ok(Some(discount)),
+        DiscountCheck::Rejected(rejection) => {
+            warn!("Código de descuento '{}' rechazado: {}", code, rejection.message());
+            Err(Status::UnprocessableEntity)
+        }
+    }
+}
+
+// Monto a descontar del subtotal; nunca lo supera. El monto fijo está en la moneda base y se
+// convierte a la de la venta.
+pub fn discount_amount(
+    discount: &DiscountCode,
+    subtotal: Money,
+    rates: &Rates,
+    currency: &str,
+) -> Result<Money, Status> {
+    let Some(value) = discount.discount() else {
+        warn!("Valor de descuento inválido en el código '{}': {}", discount.code, discount.discount_value);
+        return Err(Status::UnprocessableEntity);
+    };
+    let amount = match value {
+        DiscountValue::Percentage(percentage) => subtotal.percent(percentage),
+        DiscountValue::Fixed(amount) => rates.convert(amount, &rates.base_currency, currency)?,
+    };
+    Ok(amount.clamp(Money::ZERO, subtotal))
+}
+
+pub fn compute_totals(
+    lines: &[PricedLine],
+    code: Option<&DiscountCode>,
+    tenders: &[Tender],
+    rates: &Rates,
+    currency: &str,
+) -> Result<SaleTotals, Status> {
+    let subtotal: Money = lines.iter().map(|line| line.line_total).sum();
+    let total_discount = match code {
+        Some(code) => {
+            let eligible_subtotal: Money = lines
+                .iter()
+                .filter(|line| code.applies_to(line))
+                .map(|line| line.line_total)
+                .sum();
+            discount_amount(code, eligible_subtotal, rates, currency)?
+        }
+        None => Money::ZERO,
+    };
+    let items = allocate_lines(lines, code, total_discount);
+    let tax: Money = items.iter().map(|item| item.tax).sum();
+    let total: Money = items.iter().map(|item| item.line_total).sum();
+
+    if tenders.is_empty() {
+        warn!("Venta sin formas de pago");
+        return Err(Status::UnprocessableEntity);
+    }
+    if let Some(tender) = tenders.iter().find(|tender| tender.amount <= Money::ZERO) {
+        warn!("Forma de pago inválida: {:?}", tender);
+        return Err(Status::UnprocessableEntity);
+    }
+
+    // Tarjeta y transferencia se cobran exactas; el vuelto solo sale del efectivo y se
+    // entrega en la moneda de la venta
+    let (mut cash, mut non_cash) = (Money::ZERO, Money::ZERO);
+    for tender in tenders {
+        let amount = rates.convert(tender.amount, &tender.currency, currency)?;
+        if tender.method == PaymentMethod::Cash {
+            cash += amount;
+        } else {
+            non_cash += amount;
+        }
+    }
+    if non_cash > total + TOTALS_TOLERANCE {
+        warn!("Los pagos sin efectivo ({}) superan el total de {}", non_cash, total);
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let amount_tendered = cash + non_cash;
+    if amount_tendered + TOTALS_TOLERANCE < total {
+        warn!("Monto recibido {} insuficiente para un total de {}", amount_tendered, total);
+        return Err(Status::UnprocessableEntity);
+    }
+
+    Ok(SaleTotals {
+        subtotal,
+        discount: total_discount,
+        tax,
+        total,
+        amount_tendered,
+        change: (cash - (total - non_cash)).max(Money::ZERO),
+    })
+}
+
+// Normaliza la moneda de cada pago y guarda la tasa con que se convirtió
+pub fn rate_tenders(tenders: Vec<Tender>, rates: &Rates) -> Result<Vec<Tender>, Status> {
+    tenders
+        .into_iter()
+        .map(|tender| {
+            let currency = normalize_currency(&tender.currency);
+            Ok(Tender {
+                exchange_rate: Some(rates.rate(&currency)?),
+                currency,
+                ..tender
+            })
+        })
+        .collect()
+}
+
+// `type` y `payment_ref` de la venta se siguen llenando para los listados existentes
+pub fn tenders_summary(tenders: &[Tender]) -> (String, String) {
+    let mut methods: Vec<&str> = tenders.iter().map(|tender| tender.method.as_str()).collect();
+    methods.dedup();
+    let method = if methods.len() == 1 { methods[0].to_string() } else { "mixto".to_string() };
+    let references: Vec<&str> = tenders.iter().filter_map(|tender| tender.reference.as_deref()).collect();
+    (method, references.join(", "))
+}
+
+pub fn sale_items(lines: &[PricedLine], code: Option<&DiscountCode>, totals: &SaleTotals) -> Vec<SaleItem> {
+    allocate_lines(lines, code, totals.discount)
+}
+
+// Reparte el descuento de la venta entre las líneas del alcance del código en proporción a
+// su importe y calcula el impuesto de cada una sobre lo que queda; el residuo del redondeo
+// del descuento queda en la última de ellas para que la suma cuadre
+fn allocate_lines(lines: &[PricedLine], code: Option<&DiscountCode>, total_discount: Money) -> Vec<SaleItem> {
+    let eligible = |line: &PricedLine| code.is_some_and(|code| code.applies_to(line));
+    let eligible_subtotal: Money = lines.iter().filter(|line| eligible(line)).map(|line| line.line_total).sum();
+    let last_eligible = lines.iter().rposition(eligible);
+    let mut remaining = total_discount;
+
+    lines
+        .iter()
+        .enumerate()
+        .map(|(index, line)| {
+            let discount = if !eligible(line) {
+                Money::ZERO
+            } else if Some(index) == last_eligible {
+                remaining
+            } else {
+                total_discount.prorate(line.line_total.cents(), eligible_subtotal.cents())
+            };
+            remaining -= discount;
+            let tax = line_tax(line.tax_treatment, line.tax_percentage, line.line_total - discount);
+
+            SaleItem {
+                product: line.product.clone(),
+                name: line.name.clone(),
+                unit_price: line.unit_price,
+                quantity: line.quantity,
+                discount,
+                line_total: tax.line_total,
+                tax_treatment: line.tax_treatment,
+                tax_rate: line.tax_rate.clone(),
+                tax_percentage: line.tax_percentage.to_f64().unwrap_or_default(),
+                taxable_base: tax.taxable_base,
+                tax: tax.tax,
+            }
+        })
+        .collect()
+}
+
+// Rechaza la venta si el monto que calculó la caja no coincide con el del servidor
+pub fn check_client_amount(field: &str, client: Option<Money>, server: Money) -> Result<(), Status> {
+    match client {
+        Some(client) if (client - server).max(server - client) > TOTALS_TOLERANCE => {
+            warn!("El {} enviado ({}) no coincide con el calculado ({})", field, client, server);
+            Err(Status::UnprocessableEntity)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange_rates::ExchangeRate;
+
+    fn line(name: &str, category: Option<&str>, cents: i64, tax_treatment: TaxTreatment) -> PricedLine {
+        PricedLine {
+            product: RecordId::from(("products", name)),
+            name: name.to_string(),
+            category: category.map(str::to_string),
+            unit_price: Money::from_cents(cents),
+            quantity: 1,
+            line_total: Money::from_cents(cents),
+            available: None,
+            tax_treatment,
+            tax_rate: String::new(),
+            tax_percentage: Decimal::from(15),
+        }
+    }
+
+    fn code(discount_type: DiscountKind, discount_value: Decimal) -> DiscountCode {
+        DiscountCode {
+            code: "VERANO".to_string(),
+            discount_type,
+            discount_value,
+            active: true,
+            ..Default::default()
+        }
+    }
+
+    fn tender(method: PaymentMethod, cents: i64, currency: &str) -> Tender {
+        Tender {
+            method,
+            amount: Money::from_cents(cents),
+            currency: currency.to_string(),
+            reference: None,
+            exchange_rate: None,
+        }
+    }
+
+    // Córdobas como base y el dólar a 36.5
+    fn rates() -> Rates {
+        Rates::new(
+            "NIO".to_string(),
+            vec![ExchangeRate { currency: "USD".to_string(), rate: 36.5, date: "2026-06-15".to_string() }],
+        )
+    }
+
+    #[test]
+    fn discounts_are_capped_at_the_subtotal() {
+        let subtotal = Money::from_cents(1999);
+        let amount = |discount: DiscountCode, currency: &str| discount_amount(&discount, subtotal, &rates(), currency).unwrap();
+
+        assert_eq!(amount(code(DiscountKind::Percentage, Decimal::from(10)), "NIO"), Money::from_cents(200));
+        assert_eq!(amount(code(DiscountKind::Percentage, Decimal::ONE_HUNDRED), "NIO"), subtotal);
+        assert_eq!(amount(code(DiscountKind::Fixed, Decimal::from(5)), "NIO"), Money::from_cents(500));
+        assert_eq!(amount(code(DiscountKind::Fixed, Decimal::from(50)), "NIO"), subtotal);
+        // El monto fijo está en córdobas: 365 NIO son 10 USD
+        assert_eq!(amount(code(DiscountKind::Fixed, Decimal::from(365)), "USD"), Money::from_cents(1000));
+        assert!(discount_amount(&code(DiscountKind::Percentage, Decimal::from(150)), subtotal, &rates(), "NIO").is_err());
+    }
+
+    #[test]
+    fn change_comes_only_from_cash() {
+        let lines = vec![line("dobok", None, 90000, TaxTreatment::Exempt)];
+        let totals = |tenders: &[Tender]| compute_totals(&lines, None, tenders, &rates(), "NIO");
+
+        let mixed = totals(&[tender(PaymentMethod::Card, 50000, "NIO"), tender(PaymentMethod::Cash, 50000, "NIO")]).unwrap();
+        assert_eq!((mixed.amount_tendered, mixed.change), (Money::from_cents(100000), Money::from_cents(10000)));
+
+        // 20 USD son 730 NIO; el vuelto se da en córdobas
+        let dollars = totals(&[tender(PaymentMethod::Cash, 2000, "USD"), tender(PaymentMethod::Transfer, 20000, "NIO")]).unwrap();
+        assert_eq!(dollars.change, Money::from_cents(3000));
+
+        assert!(totals(&[tender(PaymentMethod::Card, 95000, "NIO")]).is_err());
+        assert!(totals(&[tender(PaymentMethod::Card, 40000, "NIO"), tender(PaymentMethod::Cash, 20000, "NIO")]).is_err());
+        assert!(totals(&[]).is_err());
+    }
+
+    #[test]
+    fn totals_tolerate_one_cent() {
+        let lines = vec![line("dobok", None, 90000, TaxTreatment::Exempt)];
+        let cash = |cents| compute_totals(&lines, None, &[tender(PaymentMethod::Cash, cents, "NIO")], &rates(), "NIO");
+        assert_eq!(cash(89999).unwrap().change, Money::ZERO);
+        assert!(cash(89998).is_err());
+
+        let server = Money::from_cents(90000);
+        assert!(check_client_amount("total", Some(Money::from_cents(90001)), server).is_ok());
+        assert!(check_client_amount("total", Some(Money::from_cents(89999)), server).is_ok());
+        assert!(check_client_amount("total", Some(Money::from_cents(90002)), server).is_err());
+        assert!(check_client_amount("total", None, server).is_ok());
+    }
+
+    #[test]
+    fn line_discounts_add_up_to_the_sale_discount() {
+        let lines = vec![
+            line("dobok", Some("Uniformes"), 10000, TaxTreatment::Exempt),
+            line("cinturon", Some("Uniformes"), 10000, TaxTreatment::Taxable),
+            line("guantes", Some("Protecciones"), 10000, TaxTreatment::Exempt),
+            line("pantalon", Some("Uniformes"), 10000, TaxTreatment::Included),
+        ];
+        let mut discount = code(DiscountKind::Fixed, Decimal::from(10));
+        discount.categories = vec!["Uniformes".to_string()];
+
+        let totals = compute_totals(&lines, Some(&discount), &[tender(PaymentMethod::Cash, 50000, "NIO")], &rates(), "NIO").unwrap();
+        let items = sale_items(&lines, Some(&discount), &totals);
+
+        let discounts: Vec<Money> = items.iter().map(|item| item.discount).collect();
+        assert_eq!(discounts, vec![Money::from_cents(333), Money::from_cents(333), Money::ZERO, Money::from_cents(334)]);
+        assert_eq!(discounts.iter().sum::<Money>(), totals.discount);
+        assert_eq!(items.iter().map(|item| item.line_total).sum::<Money>(), totals.total);
+        // El impuesto se calcula sobre la línea ya descontada
+        assert_eq!(items[1].tax, Money::from_cents(1450));
+    }
+}
@@ -0,0 +1,367 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+use escpos::driver::{Driver, FileDriver, NetworkDriver, UsbDriver};
+use escpos::errors::Result as PrinterResult;
+use escpos::printer::Printer;
+use escpos::printer_options::PrinterOptions;
+use escpos::utils::{JustifyMode, PageCode, Protocol, QRCodeCorrectionLevel, QRCodeModel, QRCodeOption};
+use log::{info, warn, error};
+use rocket::http::Status;
+use serde::Deserialize;
+use crate::config::AppConfig;
+use crate::receipts::ReceiptJson;
+
+// Cómo se llega a la impresora térmica de una sucursal. En red, `host` debe ser una IP.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum PrinterConnection {
+    Usb { vendor_id: u16, product_id: u16 },
+    Network {
+        host: String,
+        #[serde(default = "default_port")]
+        port: u16,
+    },
+    // Dispositivo o archivo ya existente, p. ej. `/dev/usb/lp0`
+    File { path: String },
+}
+
+// Llave `printers.<sucursal>` de Rocket.toml
+#[derive(Deserialize, Clone, Debug)]
+pub struct PrinterConfig {
+    #[serde(flatten)]
+    pub connection: PrinterConnection,
+    // Caracteres por línea: 48 en papel de 80 mm, 32 en el de 58 mm
+    #[serde(default = "default_columns")]
+    pub columns: u8,
+    // Imagen que se imprime arriba del ticket; requiere compilar con la feature `graphics`
+    #[serde(default)]
+    pub logo: Option<String>,
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+}
+
+fn default_port() -> u16 {
+    9100
+}
+
+fn default_columns() -> u8 {
+    48
+}
+
+fn default_timeout_seconds() -> u64 {
+    5
+}
+
+// Guarda en memoria lo que se enviaría a la impresora. El ticket se arma aquí completo antes de
+// abrir la conexión, así un error a medio camino no deja un ticket cortado.
+#[derive(Clone, Default)]
+pub struct MemoryDriver {
+    buffer: Rc<RefCell<Vec<u8>>>,
+}
+
+impl MemoryDriver {
+    pub fn contents(&self) -> Vec<u8> {
+        self.buffer.borrow().clone()
+    }
+}
+
+impl Driver for MemoryDriver {
+    fn name(&self) -> String {
+        "memoria".to_string()
+    }
+
+    fn write(&self, data: &[u8]) -> PrinterResult<()> {
+        self.buffer.try_borrow_mut()?.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn read(&self, _buf: &mut [u8]) -> PrinterResult<usize> {
+        Ok(0)
+    }
+
+    fn flush(&self) -> PrinterResult<()> {
+        Ok(())
+    }
+}
+
+// Texto a la izquierda y monto alineado a la derecha; si no caben se recorta el texto
+pub fn columns_line(left: &str, right: &str, width: usize) -> String {
+    let room = width.saturating_sub(right.chars().count() + 1);
+    let left: String = left.chars().take(room).collect();
+    let padding = width.saturating_sub(left.chars().count() + right.chars().count());
+    format!("{}{}{}", left, " ".repeat(padding), right)
+}
+
+// Parte un texto largo en líneas de `width` caracteres sin cortar palabras
+pub fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let length = line.chars().count();
+        if length > 0 && length + 1 + word.chars().count() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(feature = "graphics")]
+fn print_logo(printer: &mut Printer<MemoryDriver>, logo: Option<&str>, settings: &PrinterConfig) -> PrinterResult<()> {
+    use escpos::utils::{BitImageOption, BitImageSize};
+
+    if let Some(logo) = logo {
+        // El ancho en puntos debe ser múltiplo de 8; 12 puntos por carácter con la fuente A
+        let max_width = (u32::from(settings.columns) * 12) / 8 * 8;
+        printer.bit_image_option(logo, BitImageOption::new(Some(max_width), None, BitImageSize::Normal)?)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "graphics"))]
+fn print_logo(_printer: &mut Printer<MemoryDriver>, logo: Option<&str>, _settings: &PrinterConfig) -> PrinterResult<()> {
+    if let Some(logo) = logo {
+        warn!("Logo '{}' omitido: compile con la feature `graphics` para imprimirlo", logo);
+    }
+    Ok(())
+}
+
+// Comandos ESC/POS del recibo; los acentos van en la página de códigos Windows-1252 (la PC850
+// de escpos cambia el guion por otro carácter)
+pub fn render_receipt(receipt: &ReceiptJson, settings: &PrinterConfig) -> PrinterResult<Vec<u8>> {
+    let driver = MemoryDriver::default();
+    let width = usize::from(settings.columns);
+    let separator = "-".repeat(width);
+    let options = PrinterOptions::new(Some(PageCode::WPC1252), None, settings.columns);
+    let mut printer = Printer::new(driver.clone(), Protocol::default(), Some(options));
+
+    printer.init()?.justify(JustifyMode::CENTER)?;
+    // El logo de la sucursal tiene prioridad sobre el de la impresora si es un archivo local
+    let logo = receipt.header.logo.as_deref().filter(|logo| Path::new(logo).is_file()).or(settings.logo.as_deref());
+    print_logo(&mut printer, logo, settings)?;
+    printer.bold(true)?.writeln(&receipt.header.title)?.bold(false)?;
+    for line in &receipt.header.lines {
+        printer.writeln(line)?;
+    }
+    printer
+        .writeln(&receipt.header.date)?
+        .writeln(&format!("Cajero: {}", receipt.header.cashier))?;
+    if let Some(invoice_number) = &receipt.footer.invoice_number {
+        printer.bold(true)?.writeln(&format!("Factura: {}", invoice_number))?.bold(false)?;
+    }
+    if let Some(reprint) = &receipt.reprint {
+        printer
+            .bold(true)?
+            .writeln("REIMPRESIÓN/COPY")?
+            .bold(false)?
+            .writeln(&format!("Copia {} - {}", reprint.copy_number, reprint.reprinted_by))?;
+    }
+    if let Some(refund) = &receipt.refund {
+        printer
+            .bold(true)?
+            .writeln("DEVOLUCIÓN")?
+            .bold(false)?
+            .writeln(&refund.refund_id)?;
+    }
+
+    printer.justify(JustifyMode::LEFT)?.writeln(&separator)?;
+    for item in &receipt.items {
+        printer
+            .writeln(&item.name)?
+            .writeln(&columns_line(&format!("  {} x {}", item.quantity, item.price), &item.total.to_string(), width))?;
+        if !item.discount.is_zero() {
+            printer.writeln(&columns_line("  Descuento", &(-item.discount).to_string(), width))?;
+        }
+    }
+    printer.writeln(&separator)?;
+
+    let totals = &receipt.totals;
+    printer.writeln(&columns_line("Subtotal", &totals.subtotal.to_string(), width))?;
+    if !totals.discount.is_zero() {
+        printer.writeln(&columns_line("Descuento", &(-totals.discount).to_string(), width))?;
+    }
+    if !totals.tax.is_zero() {
+        printer.writeln(&columns_line("IVA", &totals.tax.to_string(), width))?;
+    }
+    printer
+        .bold(true)?
+        .writeln(&columns_line(&format!("TOTAL {}", totals.currency), &totals.total.to_string(), width))?
+        .bold(false)?;
+
+    let payment = &receipt.payment_info;
+    if payment.tenders.is_empty() {
+        if !payment.method.is_empty() {
+            printer.writeln(&format!("Pago: {}", payment.method))?;
+        }
+    } else {
+        for tender in &payment.tenders {
+            let label = format!("{} {}", tender.method.as_str(), tender.currency);
+            printer.writeln(&columns_line(&label, &tender.amount.to_string(), width))?;
+        }
+    }
+    if !totals.change.is_zero() {
+        printer.writeln(&columns_line("Cambio", &totals.change.to_string(), width))?;
+    }
+    if !payment.payment_ref.is_empty() {
+        printer.writeln(&format!("Ref: {}", payment.payment_ref))?;
+    }
+    if !payment.promocode.is_empty() {
+        printer.writeln(&format!("Promoción: {}", payment.promocode))?;
+    }
+    if let Some(refund) = &receipt.refund {
+        printer
+            .writeln(&format!("Motivo: {}", refund.reason))?
+            .writeln(&format!("Autorizó: {}", refund.authorized_by))?;
+    }
+
+    let footer = &receipt.footer;
+    if !footer.lines.is_empty() || footer.return_policy.is_some() {
+        printer.feed()?.justify(JustifyMode::CENTER)?;
+        for line in &footer.lines {
+            printer.writeln(line)?;
+        }
+        if let Some(return_policy) = &footer.return_policy {
+            for line in wrap_text(return_policy, width) {
+                printer.writeln(&line)?;
+            }
+        }
+    }
+    if let Some(qr_code_data) = &footer.qr_code_data {
+        printer
+            .feed()?
+            .justify(JustifyMode::CENTER)?
+            .qrcode_option(qr_code_data, QRCodeOption::new(QRCodeModel::Model2, 6, QRCodeCorrectionLevel::M))?;
+    }
+    printer.feeds(3)?.print_cut()?;
+
+    Ok(driver.contents())
+}
+
+fn write_all(driver: impl Driver, data: &[u8]) -> PrinterResult<()> {
+    driver.write(data)?;
+    driver.flush()
+}
+
+fn send(connection: &PrinterConnection, timeout: Duration, data: &[u8]) -> PrinterResult<()> {
+    match connection {
+        PrinterConnection::Usb { vendor_id, product_id } => {
+            write_all(UsbDriver::open(*vendor_id, *product_id, Some(timeout))?, data)
+        }
+        PrinterConnection::Network { host, port } => write_all(NetworkDriver::open(host, *port, Some(timeout))?, data),
+        PrinterConnection::File { path } => write_all(FileDriver::open(Path::new(path))?, data),
+    }
+}
+
+// Imprime el recibo en la impresora de la sucursal. Los drivers de escpos no son `Send`,
+// así que la conexión se abre y se usa dentro de un hilo bloqueante.
+pub async fn print_receipt(config: &AppConfig, branch: &str, receipt: &ReceiptJson) -> Result<(), Status> {
+    let Some(settings) = config.printers.get(branch).cloned() else {
+        warn!("La sucursal {} no tiene impresora configurada", branch);
+        return Err(Status::NotFound);
+    };
+
+    let data = render_receipt(receipt, &settings).map_err(|err| {
+        error!("Error al armar el ticket: {}", err);
+        Status::InternalServerError
+    })?;
+
+    let timeout = Duration::from_secs(settings.timeout_seconds);
+    match rocket::tokio::task::spawn_blocking(move || send(&settings.connection, timeout, &data)).await {
+        Ok(Ok(())) => {
+            info!("Recibo enviado a la impresora de la sucursal {}", branch);
+            Ok(())
+        }
+        Ok(Err(err)) => {
+            error!("No se pudo imprimir en la impresora de la sucursal {}: {}", branch, err);
+            Err(Status::ServiceUnavailable)
+        }
+        Err(err) => {
+            error!("Error en el hilo de impresión: {:?}", err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Money;
+    use crate::receipts::{PaymentInfo, ReceiptItem, ReceiptTotals, ReprintInfo};
+    use crate::branches::{Branch, ReceiptBranding, ReceiptTemplate};
+
+    #[test]
+    fn columns_line_aligns_amount_and_trims_text() {
+        assert_eq!(columns_line("Subtotal", "10.00", 16), "Subtotal   10.00");
+        assert_eq!(columns_line("Cinturón negro avanzado", "10.00", 16), "Cinturón n 10.00");
+        assert_eq!(wrap_text("Cambios dentro de 30 días", 12), vec!["Cambios", "dentro de 30", "días"]);
+    }
+
+    #[test]
+    fn render_receipt_includes_branch_data_invoice_reprint_and_qr_code() {
+        let mut branding = ReceiptBranding {
+            branch: Branch::fallback("RS"),
+            template: ReceiptTemplate::default(),
+            timezone: chrono_tz::America::Managua,
+        };
+        branding.branch.ruc = "J0310000000001".to_string();
+        branding.branch.return_policy = "Cambios dentro de los 30 días con el recibo y la prenda sin usar.".to_string();
+        let mut receipt = crate::receipts::build_receipt(
+            &branding,
+            "caja1".to_string(),
+            PaymentInfo {
+                method: "efectivo".to_string(),
+                payment_ref: String::new(),
+                promocode: String::new(),
+                tenders: Vec::new(),
+            },
+            vec![ReceiptItem {
+                name: "Dobok".to_string(),
+                quantity: 1,
+                price: Money::from_cents(150000),
+                discount: Money::ZERO,
+                taxable_base: Money::from_cents(150000),
+                tax: Money::ZERO,
+                total: Money::from_cents(150000),
+            }],
+            ReceiptTotals {
+                subtotal: Money::from_cents(150000),
+                discount: Money::ZERO,
+                tax: Money::ZERO,
+                total: Money::from_cents(150000),
+                change: Money::ZERO,
+                currency: "NIO".to_string(),
+            },
+            Some("sales:abc".to_string()),
+            Some("RS-000123".to_string()),
+        );
+        receipt.reprint = Some(ReprintInfo { copy_number: 2, reprinted_by: "caja1".to_string() });
+        let settings = PrinterConfig {
+            connection: PrinterConnection::File { path: "/dev/null".to_string() },
+            columns: 32,
+            logo: None,
+            timeout_seconds: 5,
+        };
+
+        let data = render_receipt(&receipt, &settings).unwrap();
+        let contains = |needle: &[u8]| data.windows(needle.len()).any(|window| window == needle);
+        assert!(data.starts_with(&[0x1B, 0x40]));
+        assert!(contains(b"Factura: RS-000123"));
+        assert!(contains(b"RUC J0310000000001"));
+        assert!(!contains(b"Tel. "));
+        assert!(contains(b"Cambios dentro de los 30"));
+        // "Ó" en Windows-1252
+        assert!(contains(b"REIMPRESI\xD3N/COPY"));
+        assert!(contains(b"Copia 2 - caja1"));
+        // GS ( k: bloque del código QR
+        assert!(contains(&[0x1D, 0x28, 0x6B]));
+        assert!(contains(b"sales:abc"));
+    }
+}
@@ -2,25 +2,330 @@ use rocket::http::Status;
 use rocket::serde::json::Json;
 use rocket::State;
 use surrealdb::engine::remote::ws::Client;
-use surrealdb::Surreal;
-use std::collections::HashMap;
-use log::{info, error};
-use serde::{Serialize, Deserialize};
+use surrealdb::{RecordId, Surreal};
+use log::{info, warn, error};
+use serde::{Serialize, Deserialize, Deserializer};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use crate::business_time;
+use crate::config::AppConfig;
+use crate::crud_sales::ProductWithQuantity;
+use crate::exchange_rates::{current_rates, normalize_currency, Rates};
+use crate::money::Money;
+use crate::pricing::{cart_quantities, discount_amount, price_lines, DiscountKind, DiscountValue, PricedLine};
+use crate::repository::{create_record, delete_where, find_one_by, merge_record, parse_record_id};
 
-#[derive(Serialize, Deserialize, Debug)]
+// Cuenta un uso de `$redemption.code` (y de su cliente, si viene) en la transacción de la venta.
+// Si el código llegó a su límite mientras se cobraba, el THROW cancela la venta completa.
+pub const REDEEM_DISCOUNT: &str = "IF $redemption {
+        LET $discount = (SELECT id, max_uses, max_uses_per_client, times_used FROM ONLY discount_codes
+            WHERE code = $redemption.code LIMIT 1);
+        IF !$discount OR (type::is::number($discount.max_uses) AND ($discount.times_used ?? 0) >= $discount.max_uses) {
+            THROW 'Código de descuento agotado: ' + $redemption.code;
+        };
+        UPDATE $discount.id SET times_used = (times_used ?? 0) + 1;
+        IF $redemption.customer {
+            LET $client_uses = (UPSERT type::thing('discount_redemptions', [$redemption.code, $redemption.customer])
+                SET code = $redemption.code, customer = $redemption.customer, uses = (uses ?? 0) + 1
+                RETURN VALUE uses)[0];
+            IF type::is::number($discount.max_uses_per_client) AND $client_uses > $discount.max_uses_per_client {
+                THROW 'Código de descuento agotado: ' + $redemption.code + ' para ' + <string> $redemption.customer;
+            };
+        };
+    };";
+
+// Debe coincidir con el mensaje del THROW de `REDEEM_DISCOUNT`
+const DISCOUNT_EXHAUSTED: &str = "Código de descuento agotado";
+
+pub fn is_discount_exhausted(err: &surrealdb::Error) -> bool {
+    err.to_string().contains(DISCOUNT_EXHAUSTED)
+}
+
+// `$redemption` de `REDEEM_DISCOUNT`; sin código se envía `None`
+#[derive(Serialize, Debug, Clone)]
+pub struct Redemption {
+    pub code: String,
+    pub customer: Option<RecordId>,
+}
+
+impl Redemption {
+    pub fn new(discount: Option<&DiscountCode>, customer: Option<&str>) -> Option<Redemption> {
+        discount.map(|discount| Redemption {
+            code: discount.code.clone(),
+            customer: client_key(customer).ok().flatten(),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct DiscountCode {
     pub code: String,
-    pub discount_type: String,
-    pub discount_value: f64,
+    pub discount_type: DiscountKind,
+    // Porcentaje o monto fijo en la moneda base, según `discount_type`. Se guarda como `decimal`;
+    // los códigos anteriores lo tienen como `float` y se siguen leyendo.
+    #[serde(with = "crate::money::decimal_number")]
+    pub discount_value: Decimal,
     pub active: bool,
+    // Vigencia en días del negocio (`YYYY-MM-DD`), ambos inclusive; sin ellos no vence
+    #[serde(default)]
+    pub starts_on: Option<NaiveDate>,
+    #[serde(default)]
+    pub ends_on: Option<NaiveDate>,
+    // Ventas que pueden usarlo en total y por cliente; sin valor no hay límite
+    #[serde(default)]
+    pub max_uses: Option<u32>,
+    #[serde(default)]
+    pub max_uses_per_client: Option<u32>,
+    // Subtotal mínimo del carrito, en la moneda base
+    #[serde(default)]
+    pub min_purchase: Option<Money>,
+    // Alcance: categorías de productos e IDs de productos, exámenes o mensualidades.
+    // Con todas las listas vacías el descuento aplica a todo el carrito.
+    #[serde(default)]
+    pub categories: Vec<String>,
+    #[serde(default)]
+    pub products: Vec<String>,
+    #[serde(default)]
+    pub exams: Vec<String>,
+    #[serde(default)]
+    pub monthly: Vec<String>,
+    // Ventas que ya lo usaron; solo lo cambia `REDEEM_DISCOUNT`
+    #[serde(default)]
+    pub times_used: u32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+// En los campos opcionales del código, `null` lo quita y omitirlo lo deja como está
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct UpdateDiscountCode {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub code: Option<String>,
-    pub discount_type: Option<String>,
-    pub discount_value: Option<f64>,
+    // Tipo y valor pueden cambiar por separado; se validan juntos antes de guardarlos
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discount_type: Option<DiscountKind>,
+    #[serde(default, with = "crate::money::decimal_number::option", skip_serializing_if = "Option::is_none")]
+    pub discount_value: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub active: Option<bool>,
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Option::is_none")]
+    pub starts_on: Option<Option<NaiveDate>>,
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Option::is_none")]
+    pub ends_on: Option<Option<NaiveDate>>,
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Option::is_none")]
+    pub max_uses: Option<Option<u32>>,
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Option::is_none")]
+    pub max_uses_per_client: Option<Option<u32>>,
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Option::is_none")]
+    pub min_purchase: Option<Option<Money>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub categories: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub products: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exams: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monthly: Option<Vec<String>>,
+}
+
+// Distingue un campo omitido (`None`) de uno enviado como `null` (`Some(None)`)
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+// Motivo por el que un código no aplica al carrito
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscountRejection {
+    NotFound,
+    Inactive,
+    NotStarted,
+    Expired,
+    UsageLimitReached,
+    ClientRequired,
+    InvalidClient,
+    ClientLimitReached,
+    MinimumNotMet,
+    NoEligibleItems,
+}
+
+impl DiscountRejection {
+    pub fn message(&self) -> &'static str {
+        match self {
+            DiscountRejection::NotFound => "El código de descuento no existe",
+            DiscountRejection::Inactive => "El código de descuento está inactivo",
+            DiscountRejection::NotStarted => "El código de descuento aún no está vigente",
+            DiscountRejection::Expired => "El código de descuento ya venció",
+            DiscountRejection::UsageLimitReached => "El código de descuento alcanzó su límite de usos",
+            DiscountRejection::ClientRequired => "El código de descuento requiere un cliente en la venta",
+            DiscountRejection::InvalidClient => "El cliente de la venta no es un ID de cliente válido",
+            DiscountRejection::ClientLimitReached => "El cliente ya usó este código el máximo de veces",
+            DiscountRejection::MinimumNotMet => "La compra no alcanza el monto mínimo del código",
+            DiscountRejection::NoEligibleItems => "Ningún artículo del carrito aplica para el código",
+        }
+    }
+}
+
+// Cliente de la venta como ID de `clients`, para que `abc` y `clients:abc` cuenten como el mismo
+// en los límites por cliente. Un cliente vacío cuenta como venta sin cliente.
+fn client_key(customer: Option<&str>) -> Result<Option<RecordId>, Status> {
+    match customer.map(str::trim).filter(|customer| !customer.is_empty()) {
+        Some(customer) => parse_record_id("clients", customer).map(Some),
+        None => Ok(None),
+    }
+}
+
+impl DiscountCode {
+    // El valor ya tipado; `None` si el guardado no sirve para su tipo
+    pub fn discount(&self) -> Option<DiscountValue> {
+        DiscountValue::new(self.discount_type, self.discount_value)
+    }
+
+    fn is_scoped(&self) -> bool {
+        !(self.categories.is_empty() && self.products.is_empty() && self.exams.is_empty() && self.monthly.is_empty())
+    }
+
+    // Si la línea entra en el alcance del código
+    pub fn applies_to(&self, line: &PricedLine) -> bool {
+        if !self.is_scoped() {
+            return true;
+        }
+        let ids = match line.product.table() {
+            "products" => &self.products,
+            "exams" => &self.exams,
+            "monthly" => &self.monthly,
+            _ => return false,
+        };
+        let product = line.product.to_string();
+        ids.contains(&product)
+            || line.category.as_ref().is_some_and(|category| self.categories.contains(category))
+    }
+
+    // Primera regla que el carrito no cumple. `min_purchase` ya viene en la moneda de la venta.
+    pub fn rejection(
+        &self,
+        lines: &[PricedLine],
+        client: &Result<Option<RecordId>, Status>,
+        today: NaiveDate,
+        client_uses: u32,
+        min_purchase: Option<Money>,
+    ) -> Option<DiscountRejection> {
+        let subtotal: Money = lines.iter().map(|line| line.line_total).sum();
+        if !self.active {
+            Some(DiscountRejection::Inactive)
+        } else if self.starts_on.is_some_and(|starts_on| today < starts_on) {
+            Some(DiscountRejection::NotStarted)
+        } else if self.ends_on.is_some_and(|ends_on| today > ends_on) {
+            Some(DiscountRejection::Expired)
+        } else if self.max_uses.is_some_and(|max_uses| self.times_used >= max_uses) {
+            Some(DiscountRejection::UsageLimitReached)
+        } else if self.max_uses_per_client.is_some() && client.is_err() {
+            Some(DiscountRejection::InvalidClient)
+        } else if self.max_uses_per_client.is_some() && matches!(client, Ok(None)) {
+            Some(DiscountRejection::ClientRequired)
+        } else if self.max_uses_per_client.is_some_and(|max_uses| client_uses >= max_uses) {
+            Some(DiscountRejection::ClientLimitReached)
+        } else if min_purchase.is_some_and(|min_purchase| subtotal < min_purchase) {
+            Some(DiscountRejection::MinimumNotMet)
+        } else if !lines.iter().any(|line| self.applies_to(line)) {
+            Some(DiscountRejection::NoEligibleItems)
+        } else {
+            None
+        }
+    }
+}
+
+// Carrito contra el que se valida un código
+pub struct DiscountCart<'a> {
+    pub lines: &'a [PricedLine],
+    pub customer: Option<&'a str>,
+    pub today: NaiveDate,
+    pub rates: &'a Rates,
+    pub currency: &'a str,
+}
+
+pub enum DiscountCheck {
+    Applies(DiscountCode),
+    Rejected(DiscountRejection),
+}
+
+// Busca `code` y revisa vigencia, usos, monto mínimo y alcance contra el carrito
+pub async fn check_discount(
+    database: &Surreal<Client>,
+    code: &str,
+    cart: &DiscountCart<'_>,
+) -> Result<DiscountCheck, Status> {
+    let discount = match find_one_by::<_, _, DiscountCode>(database, "discount_codes", "code", code.to_string()).await {
+        Ok(Some(discount)) => discount,
+        Ok(None) => return Ok(DiscountCheck::Rejected(DiscountRejection::NotFound)),
+        Err(err) => {
+            error!("Error al consultar el código de descuento '{}': {:?}", code, err);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    let client = client_key(cart.customer);
+    let client_uses = match (discount.max_uses_per_client, &client) {
+        (Some(_), Ok(Some(customer))) => client_uses(database, &discount.code, customer).await?,
+        _ => 0,
+    };
+    let min_purchase = match discount.min_purchase {
+        Some(amount) => Some(cart.rates.convert(amount, &cart.rates.base_currency, cart.currency)?),
+        None => None,
+    };
+
+    match discount.rejection(cart.lines, &client, cart.today, client_uses, min_purchase) {
+        Some(rejection) => Ok(DiscountCheck::Rejected(rejection)),
+        None => Ok(DiscountCheck::Applies(discount)),
+    }
+}
+
+// Usos del código por el cliente, según `REDEEM_DISCOUNT`
+async fn client_uses(database: &Surreal<Client>, code: &str, customer: &RecordId) -> Result<u32, Status> {
+    let query = "SELECT VALUE uses FROM ONLY type::thing('discount_redemptions', [$code, $customer]);";
+    match database
+        .query(query)
+        .bind(("code", code.to_string()))
+        .bind(("customer", customer.clone()))
+        .await
+    {
+        Ok(mut result) => result.take::<Option<u32>>(0).map(Option::unwrap_or_default).map_err(|err| {
+            error!("Error al deserializar los usos del código '{}': {:?}", code, err);
+            Status::InternalServerError
+        }),
+        Err(err) => {
+            error!("Error al consultar los usos del código '{}': {:?}", code, err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// Los IDs del alcance se guardan completos (`products:abc`) para compararlos con las líneas
+fn normalize_scope(ids: &mut [String], table: &str) -> Result<(), Status> {
+    for id in ids.iter_mut() {
+        *id = parse_record_id(table, id.trim())?.to_string();
+    }
+    Ok(())
+}
+
+fn check_dates(starts_on: Option<NaiveDate>, ends_on: Option<NaiveDate>) -> Result<(), Status> {
+    match (starts_on, ends_on) {
+        (Some(starts_on), Some(ends_on)) if ends_on < starts_on => {
+            warn!("La vigencia del código termina ({}) antes de empezar ({})", ends_on, starts_on);
+            Err(Status::UnprocessableEntity)
+        }
+        _ => Ok(()),
+    }
+}
+
+// Un porcentaje entre 0 y 100 o un monto fijo no negativo, redondeado al centavo
+fn check_discount_value(code: &str, kind: DiscountKind, value: Decimal) -> Result<DiscountValue, Status> {
+    DiscountValue::new(kind, value).ok_or_else(|| {
+        warn!("Valor de descuento inválido en el código '{}': {:?} {}", code, kind, value);
+        Status::UnprocessableEntity
+    })
 }
 
 pub async fn get_discount_codes(
@@ -42,88 +347,101 @@ pub async fn create_discount_code(
     database: &State<Surreal<Client>>,
     new_code: Json<DiscountCode>
 ) -> Result<Status, Status> {
-    let code = new_code.into_inner();
-    let query_check = format!(
-        "SELECT * FROM discount_codes WHERE code = '{}';",
-        code.code
-    );
-    let result_check: Result<_, surrealdb::Error> = database.query(&query_check).await;
-
-    if let Ok(mut results) = result_check {
-        if let Ok(Some(_)) = results.take::<Option<DiscountCode>>(0) {
-            log::error!(
-                "El código de promoción '{}' ya existe, use otro nombre por favor.",
-                code.code
-            );
-            return Err(Status::Conflict);
-        }
-    }
-
-    let query = format!(
-        "CREATE discount_codes CONTENT {{
-            code: '{}',
-            discount_type: '{}',
-            discount_value: {},
-            active: {}
-        }}",
-        code.code,
-        code.discount_type,
-        code.discount_value,
-        code.active
-    );
-
-    match database.query(&query).await {
+    let mut code = new_code.into_inner();
+    code.discount_value = check_discount_value(&code.code, code.discount_type, code.discount_value)?.value();
+    check_dates(code.starts_on, code.ends_on)?;
+    normalize_scope(&mut code.products, "products")?;
+    normalize_scope(&mut code.exams, "exams")?;
+    normalize_scope(&mut code.monthly, "monthly")?;
+    code.times_used = 0;
+
+    let result_check =
+        find_one_by::<_, _, DiscountCode>(database.inner(), "discount_codes", "code", code.code.clone()).await;
+
+    if let Ok(Some(_)) = result_check {
+        log::error!(
+            "El código de promoción '{}' ya existe, use otro nombre por favor.",
+            code.code
+        );
+        return Err(Status::Conflict);
+    }
+
+    match create_record::<_, _, DiscountCode>(database.inner(), "discount_codes", code).await {
         Ok(_) => {
             info!("Código de descuento creado exitosamente.");
             Ok(Status::Created)
         }
-        Err(_) => {
-            error!("Error al crear el código de descuento.");
+        Err(err) => {
+            error!("Error al crear el código de descuento: {:?}", err);
             Err(Status::InternalServerError)
         }
     }
 }
 
+impl UpdateDiscountCode {
+    fn is_empty(&self) -> bool {
+        self.code.is_none()
+            && self.discount_type.is_none()
+            && self.discount_value.is_none()
+            && self.active.is_none()
+            && self.starts_on.is_none()
+            && self.ends_on.is_none()
+            && self.max_uses.is_none()
+            && self.max_uses_per_client.is_none()
+            && self.min_purchase.is_none()
+            && self.categories.is_none()
+            && self.products.is_none()
+            && self.exams.is_none()
+            && self.monthly.is_none()
+    }
+}
+
 pub async fn update_discount_code(
     database: &State<Surreal<Client>>,
     discount_id: String,
     update_data: Json<UpdateDiscountCode>
 ) -> Result<Status, Status> {
-    let mut updates = HashMap::new();
-    let discount_value_str;
-    let active_str;
-    if let Some(code) = &update_data.code {
-        updates.insert("code", code);
-    }
-    if let Some(discount_type) = &update_data.discount_type {
-        updates.insert("discount_type", discount_type);
-    }
-    if let Some(discount_value) = &update_data.discount_value {
-        discount_value_str = discount_value.to_string();
-        updates.insert("discount_value", &discount_value_str);
-    }
-    if let Some(active) = &update_data.active {
-        active_str = active.to_string();
-        updates.insert("active", &active_str);
-    }
+    let record_id = parse_record_id("discount_codes", &discount_id)?;
+    let mut patch = update_data.into_inner();
 
-    if updates.is_empty() {
+    if patch.is_empty() {
         return Err(Status::BadRequest);
     }
+    for (ids, table) in [(&mut patch.products, "products"), (&mut patch.exams, "exams"), (&mut patch.monthly, "monthly")] {
+        if let Some(ids) = ids {
+            normalize_scope(ids, table)?;
+        }
+    }
 
-    let update_statements: Vec<String> = updates
-        .iter()
-        .map(|(key, value)| format!("{} = '{}'", key, value))
-        .collect();
-    let query = format!(
-        "UPDATE discount_codes:{} SET {}",
-        discount_id,
-        update_statements.join(", ")
-    );
+    // La vigencia se valida con lo que quedará guardado, no solo con lo que trae el cambio
+    let current = match database.select::<Option<DiscountCode>>(record_id.clone()).await {
+        Ok(Some(current)) => current,
+        Ok(None) => {
+            warn!("El código de descuento {} no existe", record_id);
+            return Err(Status::NotFound);
+        }
+        Err(err) => {
+            error!("Error al consultar el código de descuento {}: {:?}", record_id, err);
+            return Err(Status::InternalServerError);
+        }
+    };
+    check_dates(
+        patch.starts_on.unwrap_or(current.starts_on),
+        patch.ends_on.unwrap_or(current.ends_on),
+    )?;
+    if patch.discount_type.is_some() || patch.discount_value.is_some() {
+        let discount_type = patch.discount_type.unwrap_or(current.discount_type);
+        let discount_value = patch.discount_value.unwrap_or(current.discount_value);
+        patch.discount_type = Some(discount_type);
+        patch.discount_value = Some(check_discount_value(&current.code, discount_type, discount_value)?.value());
+    }
 
-    match database.query(&query).await {
+    match merge_record(database.inner(), record_id, patch).await {
         Ok(_) => Ok(Status::Ok),
-        Err(_) => Err(Status::InternalServerError),
+        Err(err) => {
+            error!("Error al actualizar el código de descuento: {:?}", err);
+            Err(Status::InternalServerError)
+        }
     }
 }
 
@@ -131,9 +449,7 @@ pub async fn delete_discount_code(
     database: &State<Surreal<Client>>,
     discount_id: String
 ) -> Result<Status, Status> {
-    let query = format!("DELETE discount_codes WHERE code = '{}';", discount_id);
-
-    match database.query(&query).await {
+    match delete_where(database.inner(), "discount_codes", "code", discount_id).await {
         Ok(_) => Ok(Status::Ok),
         Err(err) => {
             error!("Error al eliminar el código de descuento: {:?}", err);
@@ -142,3 +458,200 @@ pub async fn delete_discount_code(
     }
 }
 
+// Carrito que envía la caja en POST /cashier/promos/validate
+#[derive(Deserialize, Debug)]
+pub struct ValidateDiscountRequest {
+    pub code: String,
+    pub products: Vec<ProductWithQuantity>,
+    pub customer: Option<String>,
+    pub currency: String,
+}
+
+// Descuento que tendría el carrito con el código, o el motivo por el que no aplica
+#[derive(Serialize, Debug)]
+pub struct DiscountValidation {
+    pub code: String,
+    pub valid: bool,
+    pub currency: String,
+    pub subtotal: Money,
+    // Suma de las líneas dentro del alcance del código
+    pub eligible_subtotal: Money,
+    pub discount: Money,
+    pub reason: Option<DiscountRejection>,
+    pub message: Option<String>,
+}
+
+pub async fn validate_discount(
+    database: &State<Surreal<Client>>,
+    config: &AppConfig,
+    request: Json<ValidateDiscountRequest>,
+) -> Result<Json<DiscountValidation>, Status> {
+    let request = request.into_inner();
+    let currency = normalize_currency(&request.currency);
+    let quantities = cart_quantities(&request.products)?;
+    let rates = current_rates(database, config, vec![currency.clone()]).await?;
+    let lines = price_lines(database, quantities, &rates, &currency).await?;
+    let subtotal: Money = lines.iter().map(|line| line.line_total).sum();
+
+    let cart = DiscountCart {
+        lines: &lines,
+        customer: request.customer.as_deref(),
+        today: business_time::today(config.timezone),
+        rates: &rates,
+        currency: &currency,
+    };
+    let validation = match check_discount(database, request.code.trim(), &cart).await? {
+        DiscountCheck::Applies(discount) => {
+            let eligible_subtotal: Money = lines
+                .iter()
+                .filter(|line| discount.applies_to(line))
+                .map(|line| line.line_total)
+                .sum();
+            DiscountValidation {
+                valid: true,
+                discount: discount_amount(&discount, eligible_subtotal, &rates, &currency)?,
+                eligible_subtotal,
+                reason: None,
+                message: None,
+                code: discount.code,
+                currency,
+                subtotal,
+            }
+        }
+        DiscountCheck::Rejected(rejection) => {
+            info!("Código '{}' rechazado: {}", request.code, rejection.message());
+            DiscountValidation {
+                code: request.code,
+                valid: false,
+                currency,
+                subtotal,
+                eligible_subtotal: Money::ZERO,
+                discount: Money::ZERO,
+                reason: Some(rejection),
+                message: Some(rejection.message().to_string()),
+            }
+        }
+    };
+    Ok(Json(validation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxes::TaxTreatment;
+
+    fn line(product: (&str, &str), category: Option<&str>, cents: i64) -> PricedLine {
+        PricedLine {
+            product: RecordId::from(product),
+            name: product.1.to_string(),
+            category: category.map(str::to_string),
+            unit_price: Money::from_cents(cents),
+            quantity: 1,
+            line_total: Money::from_cents(cents),
+            available: None,
+            tax_treatment: TaxTreatment::Exempt,
+            tax_rate: String::new(),
+            tax_percentage: Decimal::ZERO,
+        }
+    }
+
+    fn code() -> DiscountCode {
+        DiscountCode {
+            code: "VERANO".to_string(),
+            discount_type: DiscountKind::Percentage,
+            discount_value: Decimal::from(10),
+            active: true,
+            starts_on: NaiveDate::from_ymd_opt(2026, 6, 1),
+            ends_on: NaiveDate::from_ymd_opt(2026, 6, 30),
+            max_uses: Some(100),
+            max_uses_per_client: None,
+            min_purchase: None,
+            categories: Vec::new(),
+            products: Vec::new(),
+            exams: Vec::new(),
+            monthly: Vec::new(),
+            times_used: 0,
+        }
+    }
+
+    #[test]
+    fn scope_matches_categories_and_listed_items() {
+        let mut discount = code();
+        let uniform = line(("products", "dobok"), Some("Uniformes"), 50000);
+        let exam = line(("exams", "cinta_amarilla"), None, 30000);
+        let fee = line(("monthly", "junio"), None, 120000);
+        assert!(discount.applies_to(&uniform) && discount.applies_to(&fee));
+
+        discount.categories = vec!["Uniformes".to_string()];
+        discount.monthly = vec!["monthly:junio".to_string()];
+        assert!(discount.applies_to(&uniform));
+        assert!(discount.applies_to(&fee));
+        assert!(!discount.applies_to(&exam));
+    }
+
+    #[test]
+    fn rejection_checks_window_limits_and_minimum() {
+        let june = |day| NaiveDate::from_ymd_opt(2026, 6, day).unwrap();
+        let lines = vec![line(("products", "dobok"), Some("Uniformes"), 50000)];
+        let mut discount = code();
+        assert_eq!(discount.rejection(&lines, &Ok(None), june(30), 0, None), None);
+        assert_eq!(
+            discount.rejection(&lines, &Ok(None), june(30).succ_opt().unwrap(), 0, None),
+            Some(DiscountRejection::Expired)
+        );
+        assert_eq!(
+            discount.rejection(&lines, &Ok(None), june(15), 0, Some(Money::from_cents(60000))),
+            Some(DiscountRejection::MinimumNotMet)
+        );
+
+        discount.max_uses_per_client = Some(1);
+        assert_eq!(discount.rejection(&lines, &client_key(Some(" ")), june(15), 0, None), Some(DiscountRejection::ClientRequired));
+        assert_eq!(
+            discount.rejection(&lines, &client_key(Some("users:ana")), june(15), 0, None),
+            Some(DiscountRejection::InvalidClient)
+        );
+        assert_eq!(client_key(Some("ana")).unwrap(), client_key(Some(" clients:ana ")).unwrap());
+        assert_eq!(
+            discount.rejection(&lines, &client_key(Some("clients:ana")), june(15), 1, None),
+            Some(DiscountRejection::ClientLimitReached)
+        );
+
+        discount.times_used = 100;
+        assert_eq!(
+            discount.rejection(&lines, &client_key(Some("clients:ana")), june(15), 0, None),
+            Some(DiscountRejection::UsageLimitReached)
+        );
+
+        discount = code();
+        discount.exams = vec!["exams:cinta_amarilla".to_string()];
+        assert_eq!(discount.rejection(&lines, &Ok(None), june(15), 0, None), Some(DiscountRejection::NoEligibleItems));
+    }
+
+    #[test]
+    fn update_tells_null_from_missing() {
+        let patch: UpdateDiscountCode = serde_json::from_str(r#"{"max_uses": null, "ends_on": "2026-07-15"}"#).unwrap();
+        assert_eq!(patch.max_uses, Some(None));
+        assert_eq!(patch.ends_on, Some(NaiveDate::from_ymd_opt(2026, 7, 15)));
+        assert_eq!(patch.starts_on, None);
+        assert!(!patch.is_empty());
+    }
+
+    #[test]
+    fn discount_values_keep_their_type() {
+        // Códigos anteriores: valor `float` y tipo en español
+        let legacy: DiscountCode =
+            serde_json::from_str(r#"{"code": "FIJO", "discount_type": "fijo", "discount_value": 12.345, "active": true}"#).unwrap();
+        assert_eq!(legacy.discount(), Some(DiscountValue::Fixed(Money::from_cents(1235))));
+
+        let percentage: DiscountCode =
+            serde_json::from_str(r#"{"code": "VERANO", "discount_type": "percentage", "discount_value": 12.5, "active": true}"#).unwrap();
+        assert_eq!(percentage.discount(), Some(DiscountValue::Percentage(Decimal::new(125, 1))));
+        let json = serde_json::to_value(&percentage).unwrap();
+        assert_eq!(json["discount_type"], "percentage");
+        assert_eq!(json["discount_value"], 12.5);
+
+        assert_eq!(DiscountValue::new(DiscountKind::Percentage, Decimal::from(101)), None);
+        assert_eq!(DiscountValue::new(DiscountKind::Fixed, Decimal::from(-1)), None);
+        assert!(serde_json::from_str::<DiscountCode>(r#"{"code": "X", "discount_type": "regalo", "discount_value": 1, "active": true}"#).is_err());
+    }
+}
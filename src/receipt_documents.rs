@@ -0,0 +1,384 @@
+use rocket::http::ContentType;
+use rocket::response::content::RawHtml;
+use rocket::serde::json::Json;
+use qrcode::{Color, QrCode};
+use log::warn;
+use crate::printer::{columns_line, wrap_text};
+use crate::receipts::ReceiptJson;
+
+// Formato del recibo pedido con `?format=`; sin él se responde JSON como siempre
+#[derive(FromFormField, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReceiptFormat {
+    #[default]
+    Json,
+    Html,
+    Pdf,
+}
+
+#[derive(Responder)]
+pub enum ReceiptDocument {
+    Json(Json<Box<ReceiptJson>>),
+    Html(RawHtml<String>),
+    Pdf((ContentType, Vec<u8>)),
+}
+
+pub fn receipt_document(receipt: ReceiptJson, format: ReceiptFormat) -> ReceiptDocument {
+    match format {
+        ReceiptFormat::Json => ReceiptDocument::Json(Json(Box::new(receipt))),
+        ReceiptFormat::Html => ReceiptDocument::Html(RawHtml(render_html(&receipt))),
+        ReceiptFormat::Pdf => ReceiptDocument::Pdf((ContentType::PDF, render_pdf(&receipt))),
+    }
+}
+
+// Módulos del código QR (`true` = oscuro) y su ancho; sin QR si el dato no cabe
+fn qr_modules(data: &str) -> Option<(usize, Vec<bool>)> {
+    match QrCode::new(data.as_bytes()) {
+        Ok(code) => Some((code.width(), code.to_colors().into_iter().map(|color| color == Color::Dark).collect())),
+        Err(err) => {
+            warn!("No se pudo generar el código QR de '{}': {:?}", data, err);
+            None
+        }
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// SVG en línea para que la página no dependa de archivos externos
+fn qr_svg(data: &str) -> Option<String> {
+    let (width, modules) = qr_modules(data)?;
+    let mut path = String::new();
+    for (index, _) in modules.iter().enumerate().filter(|(_, dark)| **dark) {
+        path.push_str(&format!("M{} {}h1v1h-1z", index % width + 4, index / width + 4));
+    }
+    Some(format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {size} {size}\" width=\"160\" height=\"160\" shape-rendering=\"crispEdges\">\
+         <rect width=\"{size}\" height=\"{size}\" fill=\"#fff\"/><path d=\"{path}\" fill=\"#000\"/></svg>",
+        size = width + 8,
+        path = path
+    ))
+}
+
+fn html_row(label: &str, amount: String, class: &str) -> String {
+    format!("<tr class=\"{}\"><td>{}</td><td class=\"amount\">{}</td></tr>", class, escape_html(label), amount)
+}
+
+// Página completa con estilos en línea, lista para adjuntar o compartir
+pub fn render_html(receipt: &ReceiptJson) -> String {
+    let header = &receipt.header;
+    let totals = &receipt.totals;
+    let payment = &receipt.payment_info;
+    let mut html = String::new();
+
+    html.push_str("<!DOCTYPE html>\n<html lang=\"es\">\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str(&format!("<title>{}</title>\n", escape_html(&header.title)));
+    html.push_str(
+        "<style>\
+         body{font-family:Helvetica,Arial,sans-serif;background:#f4f4f4;margin:0;padding:16px;color:#222}\
+         .receipt{max-width:380px;margin:0 auto;background:#fff;padding:20px;border-radius:6px}\
+         header,footer{text-align:center}h1{font-size:20px;margin:0 0 4px}h2{font-size:14px;margin:16px 0 4px}\
+         p{margin:2px 0}table{width:100%;border-collapse:collapse;font-size:13px}\
+         th,td{padding:3px 0;text-align:left}.amount{text-align:right;white-space:nowrap}\
+         .items td,.items th{border-bottom:1px solid #ddd}.total td{font-weight:bold;font-size:15px}\
+         .invoice{font-weight:bold}.refund,.reprint{color:#a00;font-weight:bold}footer{margin-top:16px;font-size:12px}\
+         .logo{max-width:160px;max-height:80px}.policy{margin-top:8px;color:#555}\
+         </style>\n</head>\n<body>\n<main class=\"receipt\">\n",
+    );
+
+    html.push_str("<header>\n");
+    // Solo se enlazan logos que el navegador puede cargar por sí mismo
+    if let Some(logo) = header.logo.as_deref().filter(|logo| logo.starts_with("https://") || logo.starts_with("http://") || logo.starts_with("data:image/")) {
+        html.push_str(&format!("<img class=\"logo\" src=\"{}\" alt=\"\">\n", escape_html(logo)));
+    }
+    html.push_str(&format!("<h1>{}</h1>\n", escape_html(&header.title)));
+    for line in &header.lines {
+        html.push_str(&format!("<p>{}</p>\n", escape_html(line)));
+    }
+    html.push_str(&format!("<p>{}</p>\n<p>Cajero: {}</p>\n", escape_html(&header.date), escape_html(&header.cashier)));
+    if let Some(invoice_number) = &receipt.footer.invoice_number {
+        html.push_str(&format!("<p class=\"invoice\">Factura: {}</p>\n", escape_html(invoice_number)));
+    }
+    if let Some(reprint) = &receipt.reprint {
+        html.push_str(&format!("<p class=\"reprint\">REIMPRESIÓN/COPY</p>\n<p>Copia {} - {}</p>\n",
+            reprint.copy_number, escape_html(&reprint.reprinted_by)));
+    }
+    if let Some(refund) = &receipt.refund {
+        html.push_str(&format!("<p class=\"refund\">DEVOLUCIÓN {}</p>\n", escape_html(&refund.refund_id)));
+    }
+    html.push_str("</header>\n");
+
+    html.push_str("<table class=\"items\">\n<tr><th>Descripción</th><th class=\"amount\">Cant.</th><th class=\"amount\">Precio</th><th class=\"amount\">Total</th></tr>\n");
+    for item in &receipt.items {
+        html.push_str(&format!(
+            "<tr><td>{}</td><td class=\"amount\">{}</td><td class=\"amount\">{}</td><td class=\"amount\">{}</td></tr>\n",
+            escape_html(&item.name), item.quantity, item.price, item.total
+        ));
+        if !item.discount.is_zero() {
+            html.push_str(&format!("<tr><td colspan=\"3\">Descuento</td><td class=\"amount\">{}</td></tr>\n", -item.discount));
+        }
+    }
+    html.push_str("</table>\n<table class=\"totals\">\n");
+    html.push_str(&html_row("Subtotal", totals.subtotal.to_string(), ""));
+    if !totals.discount.is_zero() {
+        html.push_str(&html_row("Descuento", (-totals.discount).to_string(), ""));
+    }
+    if !totals.tax.is_zero() {
+        html.push_str(&html_row("IVA", totals.tax.to_string(), ""));
+    }
+    html.push_str(&html_row(&format!("Total {}", totals.currency), totals.total.to_string(), "total"));
+    html.push_str("</table>\n");
+
+    html.push_str("<h2>Pago</h2>\n<table class=\"payment\">\n");
+    if payment.tenders.is_empty() {
+        html.push_str(&html_row(&payment.method, totals.total.to_string(), ""));
+    }
+    for tender in &payment.tenders {
+        html.push_str(&html_row(&format!("{} {}", tender.method.as_str(), tender.currency), tender.amount.to_string(), ""));
+    }
+    if !totals.change.is_zero() {
+        html.push_str(&html_row("Cambio", totals.change.to_string(), ""));
+    }
+    html.push_str("</table>\n");
+    if !payment.payment_ref.is_empty() {
+        html.push_str(&format!("<p>Ref: {}</p>\n", escape_html(&payment.payment_ref)));
+    }
+    if !payment.promocode.is_empty() {
+        html.push_str(&format!("<p>Promoción: {}</p>\n", escape_html(&payment.promocode)));
+    }
+    if let Some(refund) = &receipt.refund {
+        html.push_str(&format!("<p>Motivo: {}</p>\n<p>Autorizó: {}</p>\n", escape_html(&refund.reason), escape_html(&refund.authorized_by)));
+    }
+
+    html.push_str("<footer>\n");
+    for line in &receipt.footer.lines {
+        html.push_str(&format!("<p>{}</p>\n", escape_html(line)));
+    }
+    if let Some(return_policy) = &receipt.footer.return_policy {
+        html.push_str(&format!("<p class=\"policy\">{}</p>\n", escape_html(return_policy)));
+    }
+    if let Some(qr_code_data) = &receipt.footer.qr_code_data {
+        if let Some(svg) = qr_svg(qr_code_data) {
+            html.push_str(&svg);
+            html.push('\n');
+        }
+        html.push_str(&format!("<p>{}</p>\n", escape_html(qr_code_data)));
+    }
+    html.push_str("</footer>\n</main>\n</body>\n</html>\n");
+    html
+}
+
+// El PDF imita el ticket impreso: una tira de 80 mm en Courier, con el mismo ancho de columnas
+const PDF_COLUMNS: usize = 40;
+const PDF_FONT_SIZE: f32 = 8.0;
+const PDF_LINE_HEIGHT: f32 = 11.0;
+const PDF_MARGIN: f32 = 16.0;
+const PDF_QR_MODULE: f32 = 3.0;
+
+struct PdfLine {
+    text: String,
+    bold: bool,
+}
+
+fn pdf_line(text: String, bold: bool) -> PdfLine {
+    PdfLine { text, bold }
+}
+
+fn centered(text: &str) -> String {
+    let length = text.chars().count().min(PDF_COLUMNS);
+    let padding = (PDF_COLUMNS - length) / 2;
+    format!("{}{}", " ".repeat(padding), text.chars().take(PDF_COLUMNS).collect::<String>())
+}
+
+fn pdf_lines(receipt: &ReceiptJson) -> Vec<PdfLine> {
+    let header = &receipt.header;
+    let totals = &receipt.totals;
+    let payment = &receipt.payment_info;
+    let separator = "-".repeat(PDF_COLUMNS);
+    let mut lines = vec![pdf_line(centered(&header.title), true)];
+    lines.extend(header.lines.iter().map(|line| pdf_line(centered(line), false)));
+    lines.push(pdf_line(centered(&header.date), false));
+    lines.push(pdf_line(centered(&format!("Cajero: {}", header.cashier)), false));
+    if let Some(invoice_number) = &receipt.footer.invoice_number {
+        lines.push(pdf_line(centered(&format!("Factura: {}", invoice_number)), true));
+    }
+    if let Some(reprint) = &receipt.reprint {
+        lines.push(pdf_line(centered("REIMPRESIÓN/COPY"), true));
+        lines.push(pdf_line(centered(&format!("Copia {} - {}", reprint.copy_number, reprint.reprinted_by)), false));
+    }
+    if let Some(refund) = &receipt.refund {
+        lines.push(pdf_line(centered("DEVOLUCIÓN"), true));
+        lines.push(pdf_line(centered(&refund.refund_id), false));
+    }
+
+    lines.push(pdf_line(separator.clone(), false));
+    for item in &receipt.items {
+        lines.push(pdf_line(item.name.chars().take(PDF_COLUMNS).collect(), false));
+        lines.push(pdf_line(columns_line(&format!("  {} x {}", item.quantity, item.price), &item.total.to_string(), PDF_COLUMNS), false));
+        if !item.discount.is_zero() {
+            lines.push(pdf_line(columns_line("  Descuento", &(-item.discount).to_string(), PDF_COLUMNS), false));
+        }
+    }
+    lines.push(pdf_line(separator, false));
+
+    lines.push(pdf_line(columns_line("Subtotal", &totals.subtotal.to_string(), PDF_COLUMNS), false));
+    if !totals.discount.is_zero() {
+        lines.push(pdf_line(columns_line("Descuento", &(-totals.discount).to_string(), PDF_COLUMNS), false));
+    }
+    if !totals.tax.is_zero() {
+        lines.push(pdf_line(columns_line("IVA", &totals.tax.to_string(), PDF_COLUMNS), false));
+    }
+    lines.push(pdf_line(columns_line(&format!("TOTAL {}", totals.currency), &totals.total.to_string(), PDF_COLUMNS), true));
+
+    if payment.tenders.is_empty() && !payment.method.is_empty() {
+        lines.push(pdf_line(format!("Pago: {}", payment.method), false));
+    }
+    for tender in &payment.tenders {
+        let label = format!("{} {}", tender.method.as_str(), tender.currency);
+        lines.push(pdf_line(columns_line(&label, &tender.amount.to_string(), PDF_COLUMNS), false));
+    }
+    if !totals.change.is_zero() {
+        lines.push(pdf_line(columns_line("Cambio", &totals.change.to_string(), PDF_COLUMNS), false));
+    }
+    if !payment.payment_ref.is_empty() {
+        lines.push(pdf_line(format!("Ref: {}", payment.payment_ref), false));
+    }
+    if !payment.promocode.is_empty() {
+        lines.push(pdf_line(format!("Promoción: {}", payment.promocode), false));
+    }
+    if let Some(refund) = &receipt.refund {
+        lines.push(pdf_line(format!("Motivo: {}", refund.reason), false));
+        lines.push(pdf_line(format!("Autorizó: {}", refund.authorized_by), false));
+    }
+
+    let footer = &receipt.footer;
+    if !footer.lines.is_empty() || footer.return_policy.is_some() {
+        lines.push(pdf_line(String::new(), false));
+        lines.extend(footer.lines.iter().map(|line| pdf_line(centered(line), false)));
+        for line in footer.return_policy.iter().flat_map(|policy| wrap_text(policy, PDF_COLUMNS)) {
+            lines.push(pdf_line(line, false));
+        }
+    }
+    lines
+}
+
+// Texto para un string literal de PDF: las fuentes base usan WinAnsiEncoding, que coincide con
+// Latin-1 en los acentos; lo que no se puede representar sale como `?`
+fn pdf_string(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len() + 2);
+    bytes.push(b'(');
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                bytes.push(b'\\');
+                bytes.push(c as u8);
+            }
+            ' '..='~' => bytes.push(c as u8),
+            '\u{A0}'..='\u{FF}' => bytes.push(c as u32 as u8),
+            _ => bytes.push(b'?'),
+        }
+    }
+    bytes.push(b')');
+    bytes
+}
+
+// Las mismas líneas del PDF como texto plano, para la parte de texto de los correos
+pub fn render_text(receipt: &ReceiptJson) -> String {
+    let mut text: String = pdf_lines(receipt).into_iter().map(|line| line.text.trim_end().to_string() + "\n").collect();
+    if let Some(sale_id) = &receipt.footer.sale_id {
+        text.push_str(&format!("\n{}\n", sale_id));
+    }
+    text
+}
+
+// PDF de una sola página con las fuentes base Courier y Courier-Bold, sin dependencias externas
+pub fn render_pdf(receipt: &ReceiptJson) -> Vec<u8> {
+    let lines = pdf_lines(receipt);
+    let qr = receipt.footer.qr_code_data.as_deref().and_then(qr_modules);
+    let qr_height = qr.as_ref().map(|(width, _)| *width as f32 * PDF_QR_MODULE + PDF_LINE_HEIGHT * 2.0).unwrap_or(0.0);
+
+    let page_width = PDF_MARGIN * 2.0 + PDF_COLUMNS as f32 * PDF_FONT_SIZE * 0.6;
+    let page_height = PDF_MARGIN * 2.0 + lines.len() as f32 * PDF_LINE_HEIGHT + qr_height;
+
+    let mut content: Vec<u8> = Vec::new();
+    let mut y = page_height - PDF_MARGIN - PDF_FONT_SIZE;
+    for line in &lines {
+        let font = if line.bold { "F2" } else { "F1" };
+        content.extend_from_slice(format!("BT /{} {} Tf {:.2} {:.2} Td ", font, PDF_FONT_SIZE, PDF_MARGIN, y).as_bytes());
+        content.extend_from_slice(&pdf_string(&line.text));
+        content.extend_from_slice(b" Tj ET\n");
+        y -= PDF_LINE_HEIGHT;
+    }
+    if let Some((width, modules)) = &qr {
+        let left = (page_width - *width as f32 * PDF_QR_MODULE) / 2.0;
+        let top = y - PDF_LINE_HEIGHT + PDF_FONT_SIZE;
+        for (index, _) in modules.iter().enumerate().filter(|(_, dark)| **dark) {
+            let x = left + (index % width) as f32 * PDF_QR_MODULE;
+            let y = top - (index / width + 1) as f32 * PDF_QR_MODULE;
+            content.extend_from_slice(format!("{:.2} {:.2} {} {} re\n", x, y, PDF_QR_MODULE, PDF_QR_MODULE).as_bytes());
+        }
+        content.extend_from_slice(b"f\n");
+    }
+
+    let mut stream = format!("<< /Length {} >>\nstream\n", content.len()).into_bytes();
+    stream.extend_from_slice(&content);
+    stream.extend_from_slice(b"\nendstream");
+
+    let objects: Vec<Vec<u8>> = vec![
+        b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+        b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_vec(),
+        format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.2} {:.2}] /Resources << /Font << /F1 4 0 R /F2 5 0 R >> >> /Contents 6 0 R >>",
+            page_width, page_height
+        )
+        .into_bytes(),
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Courier /Encoding /WinAnsiEncoding >>".to_vec(),
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Courier-Bold /Encoding /WinAnsiEncoding >>".to_vec(),
+        stream,
+    ];
+
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (index, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n", index + 1).as_bytes());
+        pdf.extend_from_slice(object);
+        pdf.extend_from_slice(b"\nendobj\n");
+    }
+    let xref = pdf.len();
+    pdf.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+    for offset in offsets {
+        pdf.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    pdf.extend_from_slice(
+        format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, xref).as_bytes(),
+    );
+    pdf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pdf_string_escapes_and_encodes_latin1() {
+        assert_eq!(pdf_string("Promoción (10%)"), b"(Promoci\xF3n \\(10%\\))".to_vec());
+        assert_eq!(pdf_string("€"), b"(?)".to_vec());
+    }
+
+    #[test]
+    fn escape_html_escapes_markup() {
+        assert_eq!(escape_html("<b>Tom & \"Ana\"</b>"), "&lt;b&gt;Tom &amp; &quot;Ana&quot;&lt;/b&gt;");
+    }
+}
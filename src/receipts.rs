@@ -3,30 +3,42 @@ use serde::{Deserialize, Serialize};
 use surrealdb::engine::remote::ws::Client;
 use surrealdb::Surreal;
 use crate::crud_inventory::get_product_by_id;
-use crate::crud_sales::{ProductWithQuantity, SalesAsRecord};
+use crate::crud_sales::{resolve_sale_id, ProductWithQuantity, SaleItem};
+use crate::money::Money;
+use crate::pricing::Tender;
+use crate::repository::parse_record_id;
 use log::{info, warn, error};
-use chrono::Local;
+use chrono::{DateTime, Utc};
+use rocket::http::Status;
 use rocket::State;
+use crate::business_time;
+use crate::branches::{receipt_branding, ReceiptBranding};
+use crate::config::AppConfig;
 
 #[derive(Deserialize)]
 pub struct PrintedSales {
-    pub cashier: String,
+    // Venta devuelta por POST /cashier/sales; sin ella se usa la última venta del cajero
+    #[serde(default)]
+    pub sale_id: Option<String>,
     pub customer: Option<String>,
     pub payment_ref: String,
     pub products: Vec<ProductWithQuantity>,
     pub promocode: String,
-    pub total_paid: f64,
+    pub total_paid: Money,
     pub type_: String,
     pub currency: String,
-    pub change: f64,
+    pub change: Money,
 }
 
 #[derive(Serialize)]
 pub struct ReceiptItem {
     pub name: String,
-    pub quantity: u8,
-    pub price: f32,
-    pub total: f32,
+    pub quantity: u32,
+    pub price: Money,
+    pub discount: Money,
+    pub taxable_base: Money,
+    pub tax: Money,
+    pub total: Money,
 }
 
 #[derive(Serialize)]
@@ -37,12 +49,19 @@ pub struct ReceiptJson {
     pub totals: ReceiptTotals,
     pub footer: ReceiptFooter,
     pub last_sale_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refund: Option<RefundInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reprint: Option<ReprintInfo>,
 }
 
 #[derive(Serialize)]
 pub struct ReceiptHeader {
     pub title: String,
     pub branch: String,
+    // Líneas de la plantilla ya llenas con los datos de la sucursal (nombre, RUC, dirección...)
+    pub lines: Vec<String>,
+    pub logo: Option<String>,
     pub date: String,
     pub cashier: String,
 }
@@ -52,44 +71,149 @@ pub struct PaymentInfo {
     pub method: String,
     pub payment_ref: String,
     pub promocode: String,
+    pub tenders: Vec<Tender>,
 }
 
 #[derive(Serialize)]
 pub struct ReceiptTotals {
-    pub subtotal: f32,
-    pub total: f32,
+    pub subtotal: Money,
+    pub discount: Money,
+    pub tax: Money,
+    pub total: Money,
+    pub change: Money,
     pub currency: String,
 }
 
+// Presente solo en el comprobante de una devolución
+#[derive(Serialize)]
+pub struct RefundInfo {
+    pub refund_id: String,
+    pub reason: String,
+    pub authorized_by: String,
+}
+
+// Presente solo en las copias de un recibo ya entregado
+#[derive(Serialize)]
+pub struct ReprintInfo {
+    // 1 para la primera copia; el original no cuenta
+    pub copy_number: u32,
+    pub reprinted_by: String,
+}
+
 #[derive(Serialize)]
 pub struct ReceiptFooter {
+    pub lines: Vec<String>,
+    pub return_policy: Option<String>,
     pub sale_id: Option<String>,
+    pub invoice_number: Option<String>,
     pub qr_code_data: Option<String>,
 }
 
+// El cajero es el usuario de la sesión: `branding` es el de su sucursal y, sin `sale_id`, se
+// imprime su última venta
 pub async fn generate_receipt(
     sale: PrintedSales,
+    cashier: &str,
+    branding: &ReceiptBranding,
     database: &State<Surreal<Client>>,
-) -> ReceiptJson {
+) -> Result<ReceiptJson, Status> {
     info!("Iniciando generación del JSON del recibo.");
 
+    let printed = find_printed_sale(database, sale.sale_id.as_deref(), cashier).await;
+    let sale_id = printed.as_ref().map(|printed| printed.id.clone());
+
+    // Si la venta ya tiene líneas guardadas se usan tal cual; si no, se consultan los precios actuales
+    let (items, subtotal_price, discount, tax) = match get_sale_items(database, sale_id.as_deref()).await {
+        Some(sale_items) => {
+            let subtotal = sale_items.iter().map(|item| item.unit_price.times(item.quantity)).sum();
+            let discount = sale_items.iter().map(|item| item.discount).sum();
+            let tax = sale_items.iter().map(|item| item.tax).sum();
+            (receipt_items(&sale_items), subtotal, discount, tax)
+        }
+        None => {
+            let (items, subtotal) = live_receipt_items(database, &sale.products).await;
+            (items, subtotal, (subtotal - sale.total_paid).max(Money::ZERO), Money::ZERO)
+        }
+    };
+
+    Ok(build_receipt(
+        branding,
+        cashier.to_string(),
+        PaymentInfo {
+            method: sale.type_,
+            payment_ref: sale.payment_ref,
+            promocode: sale.promocode,
+            tenders: Vec::new(),
+        },
+        items,
+        ReceiptTotals {
+            subtotal: subtotal_price,
+            discount,
+            tax,
+            total: sale.total_paid,
+            change: sale.change,
+            currency: sale.currency,
+        },
+        sale_id,
+        printed.and_then(|printed| printed.invoice_number),
+    ))
+}
+
+pub fn receipt_items(items: &[SaleItem]) -> Vec<ReceiptItem> {
+    items
+        .iter()
+        .map(|item| ReceiptItem {
+            name: item.name.clone(),
+            quantity: item.quantity,
+            price: item.unit_price,
+            discount: item.discount,
+            taxable_base: item.taxable_base,
+            tax: item.tax,
+            total: item.line_total,
+        })
+        .collect()
+}
+
+async fn get_sale_items(
+    database: &State<Surreal<Client>>,
+    sale_id: Option<&str>,
+) -> Option<Vec<SaleItem>> {
+    let record_id = parse_record_id("sales", sale_id?).ok()?;
+    match database.query("SELECT VALUE items FROM ONLY $id;").bind(("id", record_id)).await {
+        // `take::<Option<_>>` desarmaría el arreglo de una sola línea; se lee como lista
+        Ok(mut result) => result.take::<Vec<SaleItem>>(0).ok().filter(|items| !items.is_empty()),
+        Err(e) => {
+            error!("Error al obtener las líneas de la venta: {:?}", e);
+            None
+        }
+    }
+}
+
+// Ventas antiguas sin líneas guardadas: nombre y precio se leen del inventario actual
+async fn live_receipt_items(
+    database: &State<Surreal<Client>>,
+    products: &[ProductWithQuantity],
+) -> (Vec<ReceiptItem>, Money) {
     let mut items = Vec::new();
-    let mut subtotal_price: f32 = 0.0;
+    let mut subtotal_price = Money::ZERO;
 
-    for product in &sale.products {
+    for product in products {
         let product_id = product.id.clone();
-        let quantity = product.qnt as u8;
+        let quantity = product.qnt;
 
         match get_product_by_id(database, product_id.clone()).await {
             Ok(product_response) => {
                 if let Some(product_name) = &product_response.name {
-                    let price = product_response.price.unwrap_or(0.0);
-                    let total = price as f32 * quantity as f32;
+                    let price = product_response.price.unwrap_or_default();
+                    let total = price.times(quantity);
                     subtotal_price += total;
                     items.push(ReceiptItem {
                         name: product_name.clone(),
                         quantity,
-                        price: price as f32,
+                        price,
+                        discount: Money::ZERO,
+                        taxable_base: total,
+                        tax: Money::ZERO,
                         total,
                     });
                 } else {
@@ -102,52 +226,195 @@ pub async fn generate_receipt(
         }
     }
 
-    let last_sale_id: Option<String> = get_last_sale_id(database).await;
+    (items, subtotal_price)
+}
+
+// Arma el recibo con los datos ya calculados de la venta y los de la sucursal
+// Fecha y hora del encabezado del recibo, en la zona del negocio
+const RECEIPT_DATE_FORMAT: &str = "%d-%m-%Y %H:%M";
 
+pub fn build_receipt(
+    branding: &ReceiptBranding,
+    cashier: String,
+    payment_info: PaymentInfo,
+    items: Vec<ReceiptItem>,
+    totals: ReceiptTotals,
+    sale_id: Option<String>,
+    invoice_number: Option<String>,
+) -> ReceiptJson {
+    let invoice = invoice_number.as_deref().unwrap_or_default();
+    let header_lines = branding.header_lines(&cashier, invoice);
+    let footer_lines = branding.footer_lines(&cashier, invoice);
     ReceiptJson {
         header: ReceiptHeader {
-            title: "Choi Taekwondo".to_string(),
-            branch: "Sucursal Reparto Serrano".to_string(),
-            date: Local::now().format("%d-%m-%Y %H:%M").to_string(),
-            cashier: sale.cashier.clone(),
-        },
-        payment_info: PaymentInfo {
-            method: sale.type_.clone(),
-            payment_ref: sale.payment_ref.clone(),
-            promocode: sale.promocode.clone(),
+            title: branding.branch.business_name.clone(),
+            branch: branding.branch.name.clone(),
+            lines: header_lines,
+            logo: branding.branch.logo.clone(),
+            date: business_time::format_local(branding.timezone, &Utc::now(), RECEIPT_DATE_FORMAT),
+            cashier,
         },
+        payment_info,
         items,
-        totals: ReceiptTotals {
-            subtotal: subtotal_price,
-            total: sale.total_paid as f32,
-            currency: sale.currency.clone(),
-        },
+        totals,
         footer: ReceiptFooter {
-            sale_id: last_sale_id.clone(),
-            qr_code_data: last_sale_id.clone(),
+            lines: footer_lines,
+            return_policy: branding.return_policy(),
+            sale_id: sale_id.clone(),
+            invoice_number,
+            qr_code_data: sale_id.clone().filter(|_| branding.template.show_qr),
         },
-        last_sale_id,
+        last_sale_id: sale_id,
+        refund: None,
+        reprint: None,
     }
 }
 
-async fn get_last_sale_id(database: &State<Surreal<Client>>) -> Option<String> {
-    let query = "SELECT id FROM (SELECT id, date FROM sales ORDER BY date DESC LIMIT 1);";
-    match database.query(query).await {
-        Ok(mut result) => {
-            if let Some(record) = result
-                .take::<Vec<SalesAsRecord>>(0)
-                .ok()
-                .and_then(|mut r| r.pop())
-            {
-                Some(record.id.to_string())
-            } else {
-                None
-            }
+#[derive(Deserialize)]
+struct PrintedSale {
+    id: String,
+    invoice_number: Option<String>,
+}
+
+// La venta indicada o, en las cajas que aún no la envían, la última registrada por el cajero
+async fn find_printed_sale(
+    database: &State<Surreal<Client>>,
+    sale_id: Option<&str>,
+    cashier: &str,
+) -> Option<PrintedSale> {
+    let result = match sale_id {
+        Some(sale_id) => {
+            let record_id = parse_record_id("sales", sale_id).ok()?;
+            database
+                .query("SELECT <string> id AS id, invoice_number FROM ONLY $id;")
+                .bind(("id", record_id))
+                .await
+        }
+        None => {
+            database
+                .query("SELECT <string> id AS id, invoice_number, date FROM sales WHERE cashier = $cashier ORDER BY date DESC LIMIT 1;")
+                .bind(("cashier", cashier.to_string()))
+                .await
         }
+    };
+    match result {
+        Ok(mut result) => result.take::<Option<PrintedSale>>(0).unwrap_or_else(|e| {
+            error!("Error al deserializar la venta del recibo: {:?}", e);
+            None
+        }),
         Err(e) => {
-            error!("Error al obtener el ID de la última venta: {:?}", e);
+            error!("Error al obtener la venta del recibo: {:?}", e);
             None
         }
     }
 }
 
+#[derive(Deserialize)]
+struct StoredSale {
+    id: String,
+    reprint_count: u32,
+    cashier: Option<String>,
+    invoice_number: Option<String>,
+    datetime: Option<DateTime<Utc>>,
+    type_: Option<String>,
+    payment_ref: Option<String>,
+    promocode: Option<String>,
+    tenders: Vec<Tender>,
+    items: Vec<SaleItem>,
+    products: Vec<String>,
+    subtotal: Option<Money>,
+    discount: Money,
+    tax: Money,
+    total_paid: Option<Money>,
+    change: Option<Money>,
+    currency: Option<String>,
+}
+
+// Copia del recibo de una venta ya registrada, armada solo con lo que quedó guardado en ella.
+// Cada copia suma uno al contador `reprint_count` de la venta y deja quién la pidió.
+pub async fn get_sale_receipt(
+    database: &State<Surreal<Client>>,
+    config: &AppConfig,
+    code: &str,
+    reprinted_by: &str,
+) -> Result<ReceiptJson, Status> {
+    let record_id = resolve_sale_id(database, code).await?;
+    // `UPDATE` no crea la venta si no existe; en ese caso la consulta siguiente devuelve NONE
+    let query = "UPDATE $id SET reprint_count = (reprint_count ?? 0) + 1,
+            last_reprint_at = time::now(), last_reprint_by = $reprinted_by RETURN NONE;
+        SELECT <string> id AS id, reprint_count, cashier, invoice_number,
+            IF type::is::datetime(date) THEN date END AS datetime,
+            type AS type_, payment_ref, promocode, tenders ?? [] AS tenders, items ?? [] AS items,
+            (products ?? []).map(|$product| <string> $product) AS products,
+            subtotal, discount ?? 0 AS discount, tax ?? 0 AS tax, total_paid, change, currency
+        FROM ONLY $id;";
+
+    let sale = match database
+        .query(query)
+        .bind(("id", record_id.clone()))
+        .bind(("reprinted_by", reprinted_by.to_string()))
+        .await
+    {
+        Ok(mut result) => result.take::<Option<StoredSale>>(1).map_err(|e| {
+            error!("Error al deserializar la venta {}: {:?}", record_id, e);
+            Status::InternalServerError
+        })?,
+        Err(e) => {
+            error!("Error al consultar la venta {}: {:?}", record_id, e);
+            return Err(Status::InternalServerError);
+        }
+    };
+    let Some(sale) = sale else {
+        warn!("La venta {} no existe", record_id);
+        return Err(Status::NotFound);
+    };
+
+    let cashier = sale.cashier.unwrap_or_default();
+    let branding = receipt_branding(database, config, &cashier).await?;
+    let total = sale.total_paid.unwrap_or_default();
+    // Las ventas anteriores a las líneas guardadas solo tienen la lista de productos, una unidad por aparición
+    let (items, subtotal) = if sale.items.is_empty() {
+        let mut products: Vec<ProductWithQuantity> = Vec::new();
+        for product in sale.products {
+            match products.iter_mut().find(|existing| existing.id == product) {
+                Some(existing) => existing.qnt += 1,
+                None => products.push(ProductWithQuantity { id: product, qnt: 1 }),
+            }
+        }
+        live_receipt_items(database, &products).await
+    } else {
+        let subtotal = sale.items.iter().map(|item| item.unit_price.times(item.quantity)).sum();
+        (receipt_items(&sale.items), subtotal)
+    };
+
+    let mut receipt = build_receipt(
+        &branding,
+        cashier,
+        PaymentInfo {
+            method: sale.type_.unwrap_or_default(),
+            payment_ref: sale.payment_ref.unwrap_or_default(),
+            promocode: sale.promocode.unwrap_or_default(),
+            tenders: sale.tenders,
+        },
+        items,
+        ReceiptTotals {
+            subtotal: sale.subtotal.unwrap_or(subtotal),
+            discount: sale.discount,
+            tax: sale.tax,
+            total,
+            change: sale.change.unwrap_or_default(),
+            currency: sale.currency.unwrap_or_default(),
+        },
+        Some(sale.id),
+        sale.invoice_number,
+    );
+    if let Some(datetime) = sale.datetime {
+        receipt.header.date = business_time::format_local(branding.timezone, &datetime, RECEIPT_DATE_FORMAT);
+    }
+    receipt.reprint = Some(ReprintInfo {
+        copy_number: sale.reprint_count,
+        reprinted_by: reprinted_by.to_string(),
+    });
+    info!("Copia {} del recibo de la venta {} pedida por {}", sale.reprint_count, record_id, reprinted_by);
+    Ok(receipt)
+}
@@ -0,0 +1,434 @@
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::{RecordId, Surreal};
+use log::{info, warn, error};
+use crate::crud_sales::{SaleItem, SaleStatus, RESTORE_STOCK, SELLABLE_TABLES};
+use crate::money::Money;
+use crate::pricing::{PaymentMethod, Tender};
+use crate::taxes::TaxTreatment;
+use crate::receipts::{build_receipt, PaymentInfo, ReceiptItem, ReceiptJson, ReceiptTotals, RefundInfo};
+use crate::repository::{parse_record_id, parse_record_id_in};
+use crate::branches::receipt_branding;
+use crate::config::AppConfig;
+
+// Vuelve a sumar dentro de la transacción lo devuelto de cada línea de `$items`: si dos
+// devoluciones simultáneas pasaron la primera validación, el THROW cancela la que sobra
+const CHECK_REFUNDABLE: &str = "FOR $line IN $items {
+        LET $sold = math::sum((SELECT VALUE items FROM ONLY $sale)[WHERE product = $line.product].quantity);
+        LET $refunded = math::sum(array::flatten(SELECT VALUE items FROM refunds WHERE sale = $sale)[WHERE product = $line.product].quantity);
+        IF $refunded + $line.quantity > $sold {
+            THROW 'Devolución excede lo vendido: ' + <string> $line.product;
+        };
+    };";
+
+// Marca la venta como `refunded` cuando ya no le queda nada pendiente. Se calcula dentro de la
+// transacción con las devoluciones guardadas, incluida la que se acaba de registrar.
+const SETTLE_STATUS: &str = "UPDATE $sale SET status = 'refunded';
+    FOR $line IN (SELECT VALUE items FROM ONLY $sale) {
+        LET $sold = math::sum((SELECT VALUE items FROM ONLY $sale)[WHERE product = $line.product].quantity);
+        LET $refunded = math::sum(array::flatten(SELECT VALUE items FROM refunds WHERE sale = $sale)[WHERE product = $line.product].quantity);
+        IF $refunded < $sold {
+            UPDATE $sale SET status = 'completed';
+        };
+    };";
+
+// Debe coincidir con el mensaje del THROW de `CHECK_REFUNDABLE`
+const EXCEEDS_SALE: &str = "Devolución excede lo vendido";
+
+// Debe coincidir con el mensaje del THROW de la transacción de `refund_sale`
+const SALE_VOIDED: &str = "Venta anulada";
+
+#[derive(Deserialize, Debug)]
+pub struct RefundLineRequest {
+    pub product: String,
+    pub quantity: u32,
+}
+
+// Sin `items` se devuelve todo lo que quede pendiente de la venta
+#[derive(Deserialize, Debug)]
+pub struct RefundRequest {
+    pub reason: String,
+    #[serde(default)]
+    pub items: Vec<RefundLineRequest>,
+    // Cómo se reintegra el dinero; si falta, en efectivo cuando la venta lo tuvo
+    pub method: Option<PaymentMethod>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RefundItem {
+    pub product: RecordId,
+    pub name: String,
+    pub unit_price: Money,
+    pub quantity: u32,
+    pub amount: Money,
+    // Parte del descuento de la línea vendida que corresponde a lo devuelto
+    #[serde(default)]
+    pub discount: Money,
+    // Parte de `amount` que corresponde a la base y al impuesto de la línea vendida
+    #[serde(default)]
+    pub tax_treatment: TaxTreatment,
+    #[serde(default)]
+    pub tax_rate: String,
+    #[serde(default)]
+    pub tax_percentage: f64,
+    #[serde(default)]
+    pub taxable_base: Money,
+    #[serde(default)]
+    pub tax: Money,
+}
+
+// Lo ya devuelto de una línea en devoluciones anteriores
+#[derive(Default)]
+struct Refunded {
+    quantity: u32,
+    amount: Money,
+    discount: Money,
+    taxable_base: Money,
+    tax: Money,
+}
+
+#[derive(Deserialize, Debug)]
+struct RefundableSale {
+    items: Option<Vec<SaleItem>>,
+    status: Option<SaleStatus>,
+    cashier: Option<String>,
+    payment_ref: Option<String>,
+    promocode: Option<String>,
+    #[serde(rename = "type")]
+    type_: Option<String>,
+    currency: Option<String>,
+    tenders: Option<Vec<Tender>>,
+    invoice_number: Option<String>,
+}
+
+fn already_refunded(previous: &[RefundItem], product: &RecordId) -> Refunded {
+    previous
+        .iter()
+        .filter(|item| item.product == *product)
+        .fold(Refunded::default(), |refunded, item| Refunded {
+            quantity: refunded.quantity + item.quantity,
+            amount: refunded.amount + item.amount,
+            discount: refunded.discount + item.discount,
+            taxable_base: refunded.taxable_base + item.taxable_base,
+            tax: refunded.tax + item.tax,
+        })
+}
+
+fn refund_items(
+    sale_items: &[SaleItem],
+    previous: &[RefundItem],
+    requested: Vec<RefundLineRequest>,
+) -> Result<Vec<RefundItem>, Status> {
+    // Devolución total: lo que quede de cada línea
+    let requested: Vec<(RecordId, u32)> = if requested.is_empty() {
+        sale_items
+            .iter()
+            .map(|item| (item.product.clone(), item.quantity.saturating_sub(already_refunded(previous, &item.product).quantity)))
+            .filter(|(_, quantity)| *quantity > 0)
+            .collect()
+    } else {
+        // El mismo producto en varias líneas se suma antes de compararlo con lo pendiente
+        let mut quantities: Vec<(RecordId, u32)> = Vec::new();
+        for line in requested {
+            let product = parse_record_id_in(SELLABLE_TABLES, &line.product)?;
+            match quantities.iter_mut().find(|(id, _)| *id == product) {
+                Some((_, quantity)) => *quantity = quantity.saturating_add(line.quantity),
+                None => quantities.push((product, line.quantity)),
+            }
+        }
+        quantities
+    };
+
+    if requested.is_empty() {
+        warn!("La venta ya fue devuelta por completo");
+        return Err(Status::Conflict);
+    }
+
+    requested
+        .into_iter()
+        .map(|(product, quantity)| {
+            let Some(item) = sale_items.iter().find(|item| item.product == product) else {
+                warn!("El producto {} no forma parte de la venta", product);
+                return Err(Status::UnprocessableEntity);
+            };
+            let refunded = already_refunded(previous, &product);
+            let pending = item.quantity.saturating_sub(refunded.quantity);
+            if quantity == 0 || quantity > pending {
+                warn!("Cantidad a devolver inválida para {}: {} (pendiente {})", product, quantity, pending);
+                return Err(Status::UnprocessableEntity);
+            }
+
+            // Se devuelve lo efectivamente cobrado por unidad, descuento e impuesto incluidos; la
+            // última devolución de la línea se lleva el residuo para que la suma cuadre con la venta
+            let portion = |sold: Money, refunded: Money| {
+                if quantity == pending {
+                    sold - refunded
+                } else {
+                    sold.prorate(quantity as i64, item.quantity as i64)
+                }
+            };
+
+            Ok(RefundItem {
+                product,
+                name: item.name.clone(),
+                unit_price: item.unit_price,
+                quantity,
+                amount: portion(item.line_total, refunded.amount),
+                discount: portion(item.discount, refunded.discount),
+                tax_treatment: item.tax_treatment,
+                tax_rate: item.tax_rate.clone(),
+                tax_percentage: item.tax_percentage,
+                taxable_base: portion(item.taxable_base, refunded.taxable_base),
+                tax: portion(item.tax, refunded.tax),
+            })
+        })
+        .collect()
+}
+
+pub async fn refund_sale(
+    database: &State<Surreal<Client>>,
+    config: &AppConfig,
+    sale_id: String,
+    authorized_by: &str,
+    shift: Option<RecordId>,
+    request: Json<RefundRequest>,
+) -> Result<Json<ReceiptJson>, Status> {
+    let request = request.into_inner();
+    if request.reason.trim().is_empty() {
+        warn!("Devolución sin motivo para la venta {}", sale_id);
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let sale_record = parse_record_id("sales", &sale_id)?;
+
+    let query = "SELECT items, status, cashier, payment_ref, promocode, type, currency, tenders, invoice_number FROM ONLY $sale;
+        SELECT VALUE items FROM refunds WHERE sale = $sale;";
+
+    let (sale, previous) = match database.query(query).bind(("sale", sale_record.clone())).await {
+        Ok(mut results) => {
+            let sale = results.take::<Option<RefundableSale>>(0);
+            let previous = results.take::<Vec<Vec<RefundItem>>>(1);
+            match (sale, previous) {
+                (Ok(Some(sale)), Ok(previous)) => (sale, previous.concat()),
+                (Ok(None), _) => {
+                    warn!("La venta {} no existe", sale_record);
+                    return Err(Status::NotFound);
+                }
+                (Err(err), _) | (_, Err(err)) => {
+                    error!("Error al deserializar la venta {}: {:?}", sale_record, err);
+                    return Err(Status::InternalServerError);
+                }
+            }
+        }
+        Err(err) => {
+            error!("Error al consultar la venta {}: {:?}", sale_record, err);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    if sale.status == Some(SaleStatus::Voided) {
+        warn!("La venta {} está anulada; no admite devoluciones", sale_record);
+        return Err(Status::Conflict);
+    }
+
+    // Las ventas anteriores a las líneas de venta no tienen cantidades ni precios que devolver
+    let Some(sale_items) = sale.items.filter(|items| !items.is_empty()) else {
+        warn!("La venta {} no tiene líneas registradas; no se puede devolver", sale_record);
+        return Err(Status::UnprocessableEntity);
+    };
+
+    let items = refund_items(&sale_items, &previous, request.items)?;
+    // El comprobante lleva los datos de la sucursal donde se hizo la venta
+    let cashier = sale.cashier.unwrap_or_default();
+    let branding = receipt_branding(database, config, &cashier).await?;
+    let total: Money = items.iter().map(|item| item.amount).sum();
+    let tax: Money = items.iter().map(|item| item.tax).sum();
+    // Como en la venta: subtotal bruto de las líneas y, aparte, su descuento
+    let subtotal: Money = items.iter().map(|item| item.unit_price.times(item.quantity)).sum();
+    let discount: Money = items.iter().map(|item| item.discount).sum();
+
+    let tenders = sale.tenders.unwrap_or_default();
+    let method = request.method.unwrap_or_else(|| {
+        if tenders.iter().any(|tender| tender.method == PaymentMethod::Cash) {
+            PaymentMethod::Cash
+        } else {
+            tenders
+                .first()
+                .map(|tender| tender.method)
+                .or_else(|| sale.type_.as_deref().and_then(|type_| type_.parse().ok()))
+                .unwrap_or(PaymentMethod::Cash)
+        }
+    });
+    let currency = sale.currency.unwrap_or_default();
+
+    // Registrar la devolución, reponer el inventario y acumular el monto en la venta juntos
+    let query = format!(
+        "BEGIN TRANSACTION;
+        IF (SELECT VALUE status FROM ONLY $sale) = 'voided' {{
+            THROW '{}';
+        }};
+        {}
+        {}
+        CREATE refunds SET
+            sale = $sale,
+            items = $items,
+            total = $total,
+            reason = $reason,
+            method = $method,
+            currency = $currency,
+            shift = $shift,
+            authorized_by = $authorized_by,
+            created_at = time::now()
+        RETURN id;
+        UPDATE $sale SET refunded_total = (refunded_total ?? 0) + $total;
+        {}
+        COMMIT TRANSACTION;",
+        SALE_VOIDED, CHECK_REFUNDABLE, RESTORE_STOCK, SETTLE_STATUS
+    );
+
+    let mut response = database
+        .query(query)
+        .bind(("sale", sale_record.clone()))
+        .bind(("items", items.clone()))
+        .bind(("total", total))
+        .bind(("reason", request.reason.clone()))
+        .bind(("method", method))
+        .bind(("currency", currency.clone()))
+        .bind(("shift", shift))
+        .bind(("authorized_by", authorized_by.to_string()))
+        .await
+        .map_err(|err| {
+            error!("Error al registrar la devolución de {}: {:?}", sale_record, err);
+            Status::InternalServerError
+        })?;
+
+    let errors = response.take_errors();
+    // Una anulación que se registró después de la primera lectura
+    if errors.values().any(|err| err.to_string().contains(SALE_VOIDED)) {
+        warn!("La venta {} se anuló antes de registrar la devolución", sale_record);
+        return Err(Status::Conflict);
+    }
+    if let Some(err) = errors.values().find(|err| err.to_string().contains(EXCEEDS_SALE)) {
+        warn!("Devolución de {} cancelada: {}", sale_record, err);
+        return Err(Status::Conflict);
+    }
+    if !errors.is_empty() {
+        error!("Error en la transacción de la devolución: {:?}", errors);
+        return Err(Status::InternalServerError);
+    }
+
+    // La revisión de la anulación, `CHECK_REFUNDABLE` y `RESTORE_STOCK` son las sentencias 0 a 2;
+    // `CREATE refunds`, la 3
+    let refund_id = match response.take::<Option<RecordId>>((3, "id")) {
+        Ok(Some(refund_id)) => refund_id,
+        Ok(None) => {
+            error!("La devolución no devolvió su ID");
+            return Err(Status::InternalServerError);
+        }
+        Err(err) => {
+            error!("Error al deserializar el ID de la devolución: {:?}", err);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    info!(
+        "Devolución {} de la venta {} por {} autorizada por {}",
+        refund_id, sale_record, total, authorized_by
+    );
+
+    let receipt_items = items
+        .iter()
+        .map(|item| ReceiptItem {
+            name: item.name.clone(),
+            quantity: item.quantity,
+            price: item.unit_price,
+            discount: item.discount,
+            taxable_base: item.taxable_base,
+            tax: item.tax,
+            total: item.amount,
+        })
+        .collect();
+
+    let mut receipt = build_receipt(
+        &branding,
+        cashier,
+        PaymentInfo {
+            method: sale.type_.unwrap_or_default(),
+            payment_ref: sale.payment_ref.unwrap_or_default(),
+            promocode: sale.promocode.unwrap_or_default(),
+            tenders,
+        },
+        receipt_items,
+        ReceiptTotals {
+            subtotal,
+            discount,
+            tax,
+            total,
+            change: Money::ZERO,
+            currency,
+        },
+        Some(sale_record.to_string()),
+        sale.invoice_number,
+    );
+    receipt.refund = Some(RefundInfo {
+        refund_id: refund_id.to_string(),
+        reason: request.reason,
+        authorized_by: authorized_by.to_string(),
+    });
+
+    Ok(Json(receipt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sold(quantity: u32, cents: i64) -> SaleItem {
+        SaleItem {
+            product: RecordId::from(("products", "dobok")),
+            name: "Dobok".to_string(),
+            unit_price: Money::from_cents(cents),
+            quantity,
+            discount: Money::ZERO,
+            line_total: Money::from_cents(cents).times(quantity),
+            tax_treatment: TaxTreatment::Exempt,
+            tax_rate: String::new(),
+            tax_percentage: 0.0,
+            taxable_base: Money::from_cents(cents).times(quantity),
+            tax: Money::ZERO,
+        }
+    }
+
+    fn line(quantity: u32) -> RefundLineRequest {
+        RefundLineRequest { product: "products:dobok".to_string(), quantity }
+    }
+
+    #[test]
+    fn refund_items_adds_up_repeated_products() {
+        let sale = vec![sold(2, 50000)];
+        assert_eq!(refund_items(&sale, &[], vec![line(2), line(2)]).unwrap_err(), Status::UnprocessableEntity);
+
+        let items = refund_items(&sale, &[], vec![line(1), line(1)]).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!((items[0].quantity, items[0].amount), (2, Money::from_cents(100000)));
+
+        let previous = refund_items(&sale, &[], vec![line(1)]).unwrap();
+        assert_eq!(refund_items(&sale, &previous, vec![line(1), line(1)]).unwrap_err(), Status::UnprocessableEntity);
+    }
+
+    #[test]
+    fn refund_items_prorate_the_line_discount() {
+        let mut item = sold(2, 50000);
+        item.discount = Money::from_cents(10001);
+        item.line_total = Money::from_cents(100000 - 10001);
+        let sale = vec![item];
+
+        let first = refund_items(&sale, &[], vec![line(1)]).unwrap();
+        assert_eq!((first[0].discount, first[0].amount), (Money::from_cents(5001), Money::from_cents(45000)));
+        let rest = refund_items(&sale, &first, Vec::new()).unwrap();
+        assert_eq!((rest[0].discount, rest[0].amount), (Money::from_cents(5000), Money::from_cents(44999)));
+    }
+}
@@ -0,0 +1,683 @@
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::Surreal;
+use log::{info, warn, error};
+use chrono::Utc;
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use crate::config::AppConfig;
+use crate::business_time;
+use crate::crud_sales::SaleStatus;
+use crate::money::Money;
+use crate::pricing::{PaymentMethod, Tender};
+use crate::branches::ReceiptBranding;
+use crate::receipts::{build_receipt, PaymentInfo, ReceiptItem, ReceiptJson, ReceiptTotals};
+
+// X: corte parcial que se puede sacar las veces que haga falta. Z: cierre del día, numerado
+// por sucursal; una vez emitido no se recalcula ni se modifica.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportKind {
+    #[serde(rename = "x")]
+    X,
+    #[serde(rename = "z")]
+    Z,
+}
+
+// Sin `date` es el día de hoy (`dd-mm-YYYY`); sin `branch`, la sucursal de este servidor;
+// sin `cashier`, todos los cajeros
+#[derive(Deserialize, Debug, Default)]
+pub struct ReportRequest {
+    pub date: Option<String>,
+    pub branch: Option<String>,
+    pub cashier: Option<String>,
+}
+
+// Lo cobrado con una forma de pago en una moneda; al efectivo ya se le restó el vuelto
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MethodTotal {
+    pub method: String,
+    pub currency: String,
+    pub count: u32,
+    pub amount: Money,
+    pub amount_base: Money,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CurrencyTotal {
+    pub currency: String,
+    pub count: u32,
+    pub total: Money,
+    pub total_base: Money,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CategoryTotal {
+    pub category: String,
+    pub quantity: u32,
+    pub total_base: Money,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PromocodeTotal {
+    pub promocode: String,
+    pub count: u32,
+    pub amount_base: Money,
+}
+
+// Los montos `*_base` y los totales están en la moneda base (`currency`)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SalesReport {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub kind: ReportKind,
+    // Solo los reportes Z llevan número, correlativo por sucursal
+    pub number: Option<u32>,
+    pub branch: String,
+    pub cashier: Option<String>,
+    pub date: String,
+    pub generated_at: String,
+    pub generated_by: String,
+    pub currency: String,
+    pub sales_count: u32,
+    pub gross_sales: Money,
+    pub discounts: Money,
+    pub tax: Money,
+    pub net_sales: Money,
+    pub refunds_count: u32,
+    pub refunds_total: Money,
+    pub voids_count: u32,
+    pub voids_total: Money,
+    pub net_total: Money,
+    // Primera y última factura del período; las anuladas se listan para que no falte ningún número.
+    // Los Z emitidos antes de la numeración no traen estos campos.
+    #[serde(default)]
+    pub first_invoice: Option<String>,
+    #[serde(default)]
+    pub last_invoice: Option<String>,
+    #[serde(default)]
+    pub voided_invoices: Vec<String>,
+    pub by_method: Vec<MethodTotal>,
+    pub by_currency: Vec<CurrencyTotal>,
+    pub by_category: Vec<CategoryTotal>,
+    pub by_promocode: Vec<PromocodeTotal>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ReportLine {
+    quantity: u32,
+    line_total: Money,
+    table: String,
+    category: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ReportSale {
+    status: SaleStatus,
+    invoice_number: Option<String>,
+    currency: Option<String>,
+    exchange_rate: f64,
+    subtotal: Option<Money>,
+    discount: Money,
+    tax: Money,
+    total_paid: Option<Money>,
+    change: Option<Money>,
+    tenders: Vec<Tender>,
+    type_: Option<String>,
+    promocode: Option<String>,
+    #[serde(default)]
+    items: Vec<ReportLine>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ReportRefund {
+    total: Money,
+    exchange_rate: f64,
+}
+
+fn rate_factor(rate: f64) -> Decimal {
+    Decimal::from_f64(rate).unwrap_or(Decimal::ONE)
+}
+
+// Nombre con que se agrupa una línea: la categoría del producto o el tipo de cobro
+fn line_category(line: &ReportLine) -> String {
+    match (line.table.as_str(), &line.category) {
+        ("products", Some(category)) if !category.is_empty() => category.clone(),
+        ("exams", _) => "Exámenes".to_string(),
+        ("monthly", _) => "Mensualidades".to_string(),
+        _ => "Sin categoría".to_string(),
+    }
+}
+
+fn add_method(by_method: &mut Vec<MethodTotal>, method: &str, currency: &str, count: u32, amount: Money, amount_base: Money) {
+    match by_method.iter_mut().find(|row| row.method == method && row.currency == currency) {
+        Some(row) => {
+            row.count += count;
+            row.amount += amount;
+            row.amount_base += amount_base;
+        }
+        None => by_method.push(MethodTotal {
+            method: method.to_string(),
+            currency: currency.to_string(),
+            count,
+            amount,
+            amount_base,
+        }),
+    }
+}
+
+// `sales` viene ordenado por número de factura
+fn summarize(report: &mut SalesReport, sales: &[ReportSale], refunds: &[ReportRefund]) {
+    let mut invoices = sales.iter().filter_map(|sale| sale.invoice_number.clone());
+    report.first_invoice = invoices.next();
+    report.last_invoice = invoices.next_back().or_else(|| report.first_invoice.clone());
+
+    for sale in sales {
+        let rate = rate_factor(sale.exchange_rate);
+        let total = sale.total_paid.unwrap_or_default();
+        let currency = sale.currency.clone().unwrap_or_else(|| report.currency.clone());
+
+        if sale.status == SaleStatus::Voided {
+            report.voided_invoices.extend(sale.invoice_number.clone());
+            report.voids_count += 1;
+            report.voids_total += total.scale(rate);
+            continue;
+        }
+
+        report.sales_count += 1;
+        report.gross_sales += sale.subtotal.unwrap_or(total).scale(rate);
+        report.discounts += sale.discount.scale(rate);
+        report.tax += sale.tax.scale(rate);
+        report.net_sales += total.scale(rate);
+
+        match report.by_currency.iter_mut().find(|row| row.currency == currency) {
+            Some(row) => {
+                row.count += 1;
+                row.total += total;
+                row.total_base += total.scale(rate);
+            }
+            None => report.by_currency.push(CurrencyTotal {
+                currency: currency.clone(),
+                count: 1,
+                total,
+                total_base: total.scale(rate),
+            }),
+        }
+
+        // Las ventas anteriores al pago dividido solo tienen `type`
+        if sale.tenders.is_empty() {
+            let method = sale
+                .type_
+                .as_deref()
+                .map(|type_| type_.parse::<PaymentMethod>().map(|method| method.as_str().to_string()).unwrap_or_else(|_| type_.to_lowercase()))
+                .unwrap_or_default();
+            add_method(&mut report.by_method, &method, &currency, 1, total, total.scale(rate));
+        } else {
+            for tender in &sale.tenders {
+                let tender_rate = tender.exchange_rate.map(rate_factor).unwrap_or(rate);
+                add_method(&mut report.by_method, tender.method.as_str(), &tender.currency, 1, tender.amount, tender.amount.scale(tender_rate));
+            }
+            if let Some(change) = sale.change.filter(|change| !change.is_zero()) {
+                add_method(&mut report.by_method, PaymentMethod::Cash.as_str(), &currency, 0, -change, -change.scale(rate));
+            }
+        }
+
+        if sale.items.is_empty() {
+            let category = "Sin detalle".to_string();
+            match report.by_category.iter_mut().find(|row| row.category == category) {
+                Some(row) => row.total_base += total.scale(rate),
+                None => report.by_category.push(CategoryTotal { category, quantity: 0, total_base: total.scale(rate) }),
+            }
+        }
+        for line in &sale.items {
+            let category = line_category(line);
+            match report.by_category.iter_mut().find(|row| row.category == category) {
+                Some(row) => {
+                    row.quantity += line.quantity;
+                    row.total_base += line.line_total.scale(rate);
+                }
+                None => report.by_category.push(CategoryTotal {
+                    category,
+                    quantity: line.quantity,
+                    total_base: line.line_total.scale(rate),
+                }),
+            }
+        }
+
+        let promocode = sale.promocode.clone().unwrap_or_default();
+        if !promocode.is_empty() && !sale.discount.is_zero() {
+            match report.by_promocode.iter_mut().find(|row| row.promocode == promocode) {
+                Some(row) => {
+                    row.count += 1;
+                    row.amount_base += sale.discount.scale(rate);
+                }
+                None => report.by_promocode.push(PromocodeTotal {
+                    promocode,
+                    count: 1,
+                    amount_base: sale.discount.scale(rate),
+                }),
+            }
+        }
+    }
+
+    for refund in refunds {
+        report.refunds_count += 1;
+        report.refunds_total += refund.total.scale(rate_factor(refund.exchange_rate));
+    }
+
+    report.net_total = report.net_sales - report.refunds_total;
+}
+
+// Arma el reporte del día con las ventas guardadas; no escribe nada
+async fn build_report(
+    database: &State<Surreal<Client>>,
+    config: &AppConfig,
+    kind: ReportKind,
+    generated_by: &str,
+    request: ReportRequest,
+) -> Result<SalesReport, Status> {
+    let now = Utc::now();
+    let day = match request.date.as_deref() {
+        Some(date) => business_time::parse_day(date)?,
+        None => business_time::today(config.timezone),
+    };
+    let branch = request.branch.filter(|branch| !branch.trim().is_empty()).unwrap_or_else(|| config.branch.clone());
+    let cashier = request.cashier.filter(|cashier| !cashier.trim().is_empty());
+    let (from, to) = business_time::day_range(config.timezone, day, day)?;
+
+    // Las ventas anteriores a las sucursales pertenecen a la de este servidor
+    let query = "SELECT status ?? 'completed' AS status, invoice_number, invoice_sequence, currency, exchange_rate ?? 1 AS exchange_rate,
+            subtotal, discount ?? 0 AS discount, tax ?? 0 AS tax, total_paid, change, tenders ?? [] AS tenders,
+            type AS type_, promocode,
+            (SELECT quantity, line_total, record::tb(product) AS table, product.category AS category
+                FROM $parent.items) AS items
+        FROM sales
+        WHERE date >= $from AND date < $to
+            AND (branch ?? $default_branch) = $branch
+            AND ($cashier = NONE OR cashier = $cashier)
+        ORDER BY invoice_sequence;
+        SELECT total, sale.exchange_rate ?? 1 AS exchange_rate FROM refunds
+        WHERE created_at >= $from AND created_at < $to
+            AND (sale.branch ?? $default_branch) = $branch
+            AND ($cashier = NONE OR authorized_by = $cashier);";
+
+    let (sales, refunds) = match database
+        .query(query)
+        .bind(("default_branch", config.branch.clone()))
+        .bind(("branch", branch.clone()))
+        .bind(("cashier", cashier.clone()))
+        .bind(("from", from))
+        .bind(("to", to))
+        .await
+    {
+        Ok(mut results) => {
+            let sales = results.take::<Vec<ReportSale>>(0);
+            let refunds = results.take::<Vec<ReportRefund>>(1);
+            match (sales, refunds) {
+                (Ok(sales), Ok(refunds)) => (sales, refunds),
+                (Err(err), _) | (_, Err(err)) => {
+                    error!("Error al deserializar las ventas del reporte: {:?}", err);
+                    return Err(Status::InternalServerError);
+                }
+            }
+        }
+        Err(err) => {
+            error!("Error al consultar las ventas del reporte: {:?}", err);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    let mut report = SalesReport {
+        id: None,
+        kind,
+        number: None,
+        branch,
+        cashier,
+        date: day.format("%d-%m-%Y").to_string(),
+        generated_at: business_time::format_local(config.timezone, &now, "%d-%m-%Y %H:%M"),
+        generated_by: generated_by.to_string(),
+        currency: config.base_currency.clone(),
+        sales_count: 0,
+        gross_sales: Money::ZERO,
+        discounts: Money::ZERO,
+        tax: Money::ZERO,
+        net_sales: Money::ZERO,
+        refunds_count: 0,
+        refunds_total: Money::ZERO,
+        voids_count: 0,
+        voids_total: Money::ZERO,
+        net_total: Money::ZERO,
+        first_invoice: None,
+        last_invoice: None,
+        voided_invoices: Vec::new(),
+        by_method: Vec::new(),
+        by_currency: Vec::new(),
+        by_category: Vec::new(),
+        by_promocode: Vec::new(),
+    };
+    summarize(&mut report, &sales, &refunds);
+    Ok(report)
+}
+
+pub async fn get_x_report(
+    database: &State<Surreal<Client>>,
+    config: &AppConfig,
+    generated_by: &str,
+    request: ReportRequest,
+) -> Result<Json<SalesReport>, Status> {
+    let report = build_report(database, config, ReportKind::X, generated_by, request).await?;
+    info!("Reporte X de {} del {} generado por {}", report.branch, report.date, generated_by);
+    Ok(Json(report))
+}
+
+// Debe coincidir con el mensaje del THROW de `close_z_report`
+const Z_REPORT_EXISTS: &str = "Reporte Z ya emitido";
+
+// Emite el Z del día; uno solo por sucursal, día y cajero (o sucursal completa)
+pub async fn close_z_report(
+    database: &State<Surreal<Client>>,
+    config: &AppConfig,
+    generated_by: &str,
+    request: ReportRequest,
+) -> Result<Json<SalesReport>, Status> {
+    let mut report = build_report(database, config, ReportKind::Z, generated_by, request).await?;
+    let scope = report.cashier.clone().unwrap_or_default();
+
+    // El número sale de un contador por sucursal dentro de la misma transacción
+    let query = format!(
+        "BEGIN TRANSACTION;
+        IF (SELECT VALUE id FROM ONLY type::thing('z_reports', [$branch, $date, $scope])) != NONE {{
+            THROW '{}';
+        }};
+        LET $number = (UPSERT type::thing('counters', ['z_report', $branch]) SET value = (value ?? 0) + 1 RETURN VALUE value)[0];
+        CREATE type::thing('z_reports', [$branch, $date, $scope]) CONTENT $report;
+        UPDATE ONLY type::thing('z_reports', [$branch, $date, $scope]) SET number = $number RETURN VALUE number;
+        COMMIT TRANSACTION;",
+        Z_REPORT_EXISTS
+    );
+
+    let mut response = database
+        .query(query)
+        .bind(("branch", report.branch.clone()))
+        .bind(("date", report.date.clone()))
+        .bind(("scope", scope))
+        .bind(("report", report.clone()))
+        .await
+        .map_err(|err| {
+            error!("Error al emitir el reporte Z: {:?}", err);
+            Status::InternalServerError
+        })?;
+
+    let errors = response.take_errors();
+    if errors.values().any(|err| err.to_string().contains(Z_REPORT_EXISTS)) {
+        warn!("El reporte Z de {} del {} ya fue emitido", report.branch, report.date);
+        return Err(Status::Conflict);
+    }
+    if !errors.is_empty() {
+        error!("Error en la transacción del reporte Z: {:?}", errors);
+        return Err(Status::InternalServerError);
+    }
+
+    report.number = match response.take::<Option<u32>>(3) {
+        Ok(Some(number)) => Some(number),
+        Ok(None) => {
+            error!("El reporte Z no devolvió su número");
+            return Err(Status::InternalServerError);
+        }
+        Err(err) => {
+            error!("Error al deserializar el número del reporte Z: {:?}", err);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    info!(
+        "Reporte Z #{} de {} del {} emitido por {}",
+        report.number.unwrap_or_default(), report.branch, report.date, generated_by
+    );
+    Ok(Json(report))
+}
+
+pub async fn get_z_reports(
+    database: &State<Surreal<Client>>,
+    branch: String,
+) -> Result<Json<Vec<SalesReport>>, Status> {
+    let query = "SELECT *, <string> id AS id FROM z_reports WHERE branch = $branch ORDER BY number DESC;";
+
+    match database.query(query).bind(("branch", branch)).await {
+        Ok(mut results) => results.take::<Vec<SalesReport>>(0).map(Json).map_err(|err| {
+            error!("Error al deserializar los reportes Z: {:?}", err);
+            Status::InternalServerError
+        }),
+        Err(err) => {
+            error!("Error al consultar los reportes Z: {:?}", err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+pub async fn get_z_report(
+    database: &State<Surreal<Client>>,
+    branch: String,
+    number: u32,
+) -> Result<Json<SalesReport>, Status> {
+    let query = "SELECT *, <string> id AS id FROM z_reports WHERE branch = $branch AND number = $number LIMIT 1;";
+
+    match database.query(query).bind(("branch", branch.clone())).bind(("number", number)).await {
+        Ok(mut results) => match results.take::<Option<SalesReport>>(0) {
+            Ok(Some(report)) => Ok(Json(report)),
+            Ok(None) => {
+                warn!("No existe el reporte Z #{} de {}", number, branch);
+                Err(Status::NotFound)
+            }
+            Err(err) => {
+                error!("Error al deserializar el reporte Z #{}: {:?}", number, err);
+                Err(Status::InternalServerError)
+            }
+        },
+        Err(err) => {
+            error!("Error al consultar el reporte Z #{}: {:?}", number, err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+fn report_line(name: String, quantity: u32, amount: Money, total: Money) -> ReceiptItem {
+    ReceiptItem {
+        name,
+        quantity,
+        price: amount,
+        discount: Money::ZERO,
+        taxable_base: total,
+        tax: Money::ZERO,
+        total,
+    }
+}
+
+// El reporte en el formato del recibo, para imprimirlo con la misma impresora de la caja
+pub fn report_receipt(report: &SalesReport, branding: &ReceiptBranding) -> ReceiptJson {
+    let mut items: Vec<ReceiptItem> = Vec::new();
+    for row in &report.by_method {
+        items.push(report_line(format!("Pago: {} {}", row.method, row.currency), row.count, row.amount, row.amount_base));
+    }
+    for row in &report.by_currency {
+        items.push(report_line(format!("Moneda: {}", row.currency), row.count, row.total, row.total_base));
+    }
+    for row in &report.by_category {
+        items.push(report_line(format!("Categoría: {}", row.category), row.quantity, row.total_base, row.total_base));
+    }
+    for row in &report.by_promocode {
+        items.push(report_line(format!("Promoción: {}", row.promocode), row.count, row.amount_base, row.amount_base));
+    }
+    items.push(report_line("Devoluciones".to_string(), report.refunds_count, report.refunds_total, -report.refunds_total));
+    items.push(report_line("Anulaciones".to_string(), report.voids_count, report.voids_total, Money::ZERO));
+    if let (Some(first), Some(last)) = (&report.first_invoice, &report.last_invoice) {
+        items.push(report_line(format!("Facturas: {} a {}", first, last), 0, Money::ZERO, Money::ZERO));
+    }
+    for invoice in &report.voided_invoices {
+        items.push(report_line(format!("Anulada: {}", invoice), 0, Money::ZERO, Money::ZERO));
+    }
+
+    let mut receipt = build_receipt(
+        branding,
+        report.generated_by.clone(),
+        PaymentInfo {
+            method: String::new(),
+            payment_ref: String::new(),
+            promocode: String::new(),
+            tenders: Vec::new(),
+        },
+        items,
+        ReceiptTotals {
+            subtotal: report.gross_sales,
+            discount: report.discounts,
+            tax: report.tax,
+            total: report.net_total,
+            change: Money::ZERO,
+            currency: report.currency.clone(),
+        },
+        None,
+        None,
+    );
+
+    let scope = report.cashier.as_deref().map(|cashier| format!(" - {}", cashier)).unwrap_or_default();
+    receipt.header.title = match (report.kind, report.number) {
+        (ReportKind::Z, Some(number)) => format!("Reporte Z #{:06} {}{}", number, report.date, scope),
+        _ => format!("Reporte X {}{}", report.date, scope),
+    };
+    receipt.header.date = report.generated_at.clone();
+    receipt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_report() -> SalesReport {
+        SalesReport {
+            id: None,
+            kind: ReportKind::X,
+            number: None,
+            branch: "RS".to_string(),
+            cashier: None,
+            date: "15-06-2026".to_string(),
+            generated_at: String::new(),
+            generated_by: "admin".to_string(),
+            currency: "NIO".to_string(),
+            sales_count: 0,
+            gross_sales: Money::ZERO,
+            discounts: Money::ZERO,
+            tax: Money::ZERO,
+            net_sales: Money::ZERO,
+            refunds_count: 0,
+            refunds_total: Money::ZERO,
+            voids_count: 0,
+            voids_total: Money::ZERO,
+            net_total: Money::ZERO,
+            first_invoice: None,
+            last_invoice: None,
+            voided_invoices: Vec::new(),
+            by_method: Vec::new(),
+            by_currency: Vec::new(),
+            by_category: Vec::new(),
+            by_promocode: Vec::new(),
+        }
+    }
+
+    fn sale(invoice: &str, currency: &str, exchange_rate: f64, subtotal: i64, discount: i64, total: i64) -> ReportSale {
+        ReportSale {
+            status: SaleStatus::Completed,
+            invoice_number: Some(invoice.to_string()),
+            currency: Some(currency.to_string()),
+            exchange_rate,
+            subtotal: Some(Money::from_cents(subtotal)),
+            discount: Money::from_cents(discount),
+            tax: Money::ZERO,
+            total_paid: Some(Money::from_cents(total)),
+            change: None,
+            tenders: Vec::new(),
+            type_: None,
+            promocode: None,
+            items: Vec::new(),
+        }
+    }
+
+    fn line(table: &str, category: Option<&str>, quantity: u32, line_total: i64) -> ReportLine {
+        ReportLine {
+            quantity,
+            line_total: Money::from_cents(line_total),
+            table: table.to_string(),
+            category: category.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn summary_nets_change_voids_and_refunds() {
+        // Pagó 1000 en efectivo por una venta de 900 con el código VERANO
+        let mut uniforms = sale("RS-000001", "NIO", 1.0, 100000, 10000, 90000);
+        uniforms.tenders = vec![Tender {
+            method: PaymentMethod::Cash,
+            amount: Money::from_cents(100000),
+            currency: "NIO".to_string(),
+            reference: None,
+            exchange_rate: None,
+        }];
+        uniforms.change = Some(Money::from_cents(10000));
+        uniforms.promocode = Some("VERANO".to_string());
+        uniforms.items = vec![line("products", Some("Uniformes"), 2, 60000), line("exams", None, 1, 30000)];
+
+        let mut voided = sale("RS-000002", "NIO", 1.0, 50000, 0, 50000);
+        voided.status = SaleStatus::Voided;
+        voided.type_ = Some("efectivo".to_string());
+
+        // Ventas anteriores al pago dividido: solo `type`, y sin líneas guardadas
+        let mut dollars = sale("RS-000003", "USD", 36.5, 2000, 0, 2000);
+        dollars.type_ = Some("Tarjeta".to_string());
+        let mut fee = sale("RS-000004", "NIO", 1.0, 35000, 5000, 30000);
+        fee.type_ = Some("Efectivo".to_string());
+        fee.promocode = Some("VERANO".to_string());
+        fee.items = vec![line("monthly", None, 1, 30000)];
+
+        let refunds = vec![
+            ReportRefund { total: Money::from_cents(10000), exchange_rate: 1.0 },
+            ReportRefund { total: Money::from_cents(500), exchange_rate: 36.5 },
+        ];
+        let mut report = empty_report();
+        summarize(&mut report, &[uniforms, voided, dollars, fee], &refunds);
+
+        assert_eq!((report.sales_count, report.voids_count), (3, 1));
+        assert_eq!(report.voids_total, Money::from_cents(50000));
+        assert_eq!(report.voided_invoices, vec!["RS-000002".to_string()]);
+        assert_eq!(report.first_invoice.as_deref(), Some("RS-000001"));
+        assert_eq!(report.last_invoice.as_deref(), Some("RS-000004"));
+        assert_eq!(report.gross_sales, Money::from_cents(100000 + 73000 + 35000));
+        assert_eq!(report.discounts, Money::from_cents(15000));
+        assert_eq!(report.net_sales, Money::from_cents(90000 + 73000 + 30000));
+        assert_eq!((report.refunds_count, report.refunds_total), (2, Money::from_cents(10000 + 18250)));
+        assert_eq!(report.net_total, Money::from_cents(193000 - 28250));
+
+        let method = |method: &str, currency: &str| {
+            let row = report.by_method.iter().find(|row| row.method == method && row.currency == currency).unwrap();
+            (row.count, row.amount, row.amount_base)
+        };
+        assert_eq!(method("efectivo", "NIO"), (2, Money::from_cents(90000 + 30000), Money::from_cents(120000)));
+        assert_eq!(method("tarjeta", "USD"), (1, Money::from_cents(2000), Money::from_cents(73000)));
+        assert_eq!(report.by_method.len(), 2);
+
+        let usd = report.by_currency.iter().find(|row| row.currency == "USD").unwrap();
+        assert_eq!((usd.count, usd.total, usd.total_base), (1, Money::from_cents(2000), Money::from_cents(73000)));
+
+        let category = |name: &str| {
+            let row = report.by_category.iter().find(|row| row.category == name).unwrap();
+            (row.quantity, row.total_base)
+        };
+        assert_eq!(category("Uniformes"), (2, Money::from_cents(60000)));
+        assert_eq!(category("Exámenes"), (1, Money::from_cents(30000)));
+        assert_eq!(category("Mensualidades"), (1, Money::from_cents(30000)));
+        assert_eq!(category("Sin detalle"), (0, Money::from_cents(73000)));
+
+        assert_eq!(report.by_promocode.len(), 1);
+        assert_eq!((report.by_promocode[0].count, report.by_promocode[0].amount_base), (2, Money::from_cents(15000)));
+    }
+}
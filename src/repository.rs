@@ -0,0 +1,307 @@
+use rocket::http::Status;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use surrealdb::{Connection, RecordId, Surreal};
+use log::warn;
+use std::str::FromStr;
+
+// Capa común de acceso a datos: todo valor que viene del usuario viaja como parámetro
+// (`$content`, `$value`, `$id`), nunca interpolado dentro del texto de la consulta.
+
+// Convierte el ID recibido en la ruta (`tabla:id` o solo `id`) en un RecordId de `table`
+pub fn parse_record_id(table: &str, raw: &str) -> Result<RecordId, Status> {
+    parse_record_id_in(&[table], raw)
+}
+
+// Igual que `parse_record_id`, pero aceptando cualquiera de las tablas indicadas
+pub fn parse_record_id_in(tables: &[&str], raw: &str) -> Result<RecordId, Status> {
+    let record_id = match raw.split_once(':') {
+        Some((table, _)) if tables.contains(&table) => RecordId::from_str(raw).map_err(|_| {
+            warn!("ID de registro inválido: {}", raw);
+            Status::BadRequest
+        })?,
+        Some(_) => {
+            warn!("El ID {} no pertenece a ninguna de las tablas {:?}", raw, tables);
+            return Err(Status::BadRequest);
+        }
+        None if !raw.is_empty() => RecordId::from((tables[0], raw)),
+        None => return Err(Status::BadRequest),
+    };
+
+    // Defensa adicional: la tabla del ID ya parseado debe ser una de las esperadas
+    if tables.contains(&record_id.table()) {
+        Ok(record_id)
+    } else {
+        warn!("El ID {} no pertenece a ninguna de las tablas {:?}", raw, tables);
+        Err(Status::BadRequest)
+    }
+}
+
+pub async fn create_record<C, T, R>(
+    database: &Surreal<C>,
+    table: &str,
+    content: T,
+) -> Result<Option<R>, surrealdb::Error>
+where
+    C: Connection,
+    T: Serialize + 'static,
+    R: DeserializeOwned,
+{
+    database
+        .query("CREATE type::table($table) CONTENT $content RETURN AFTER;")
+        .bind(("table", table.to_string()))
+        .bind(("content", content))
+        .await?
+        .take::<Option<R>>(0)
+}
+
+// Actualiza solo los campos presentes en `patch`
+pub async fn merge_record<C, T>(
+    database: &Surreal<C>,
+    record_id: RecordId,
+    patch: T,
+) -> Result<(), surrealdb::Error>
+where
+    C: Connection,
+    T: Serialize + 'static,
+{
+    database
+        .query("UPDATE $id MERGE $patch;")
+        .bind(("id", record_id))
+        .bind(("patch", patch))
+        .await?
+        .check()?;
+    Ok(())
+}
+
+// Reemplaza el contenido completo del registro
+pub async fn replace_record<C, T>(
+    database: &Surreal<C>,
+    record_id: RecordId,
+    content: T,
+) -> Result<(), surrealdb::Error>
+where
+    C: Connection,
+    T: Serialize + 'static,
+{
+    database
+        .query("UPDATE $id CONTENT $content;")
+        .bind(("id", record_id))
+        .bind(("content", content))
+        .await?
+        .check()?;
+    Ok(())
+}
+
+pub async fn delete_record<C>(database: &Surreal<C>, record_id: RecordId) -> Result<(), surrealdb::Error>
+where
+    C: Connection,
+{
+    database
+        .query("DELETE $id;")
+        .bind(("id", record_id))
+        .await?
+        .check()?;
+    Ok(())
+}
+
+pub async fn find_one_by<C, V, R>(
+    database: &Surreal<C>,
+    table: &str,
+    field: &str,
+    value: V,
+) -> Result<Option<R>, surrealdb::Error>
+where
+    C: Connection,
+    V: Serialize + 'static,
+    R: DeserializeOwned,
+{
+    database
+        .query("SELECT * FROM type::table($table) WHERE type::field($field) = $value LIMIT 1;")
+        .bind(("table", table.to_string()))
+        .bind(("field", field.to_string()))
+        .bind(("value", value))
+        .await?
+        .take::<Option<R>>(0)
+}
+
+pub async fn delete_where<C, V>(
+    database: &Surreal<C>,
+    table: &str,
+    field: &str,
+    value: V,
+) -> Result<(), surrealdb::Error>
+where
+    C: Connection,
+    V: Serialize + 'static,
+{
+    database
+        .query("DELETE type::table($table) WHERE type::field($field) = $value;")
+        .bind(("table", table.to_string()))
+        .bind(("field", field.to_string()))
+        .bind(("value", value))
+        .await?
+        .check()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crud_clients::NewCliente;
+    use crate::money::Money;
+    use crate::pricing::{DiscountKind, DiscountValue};
+    use rust_decimal::Decimal;
+    use crate::promos::{DiscountCode, UpdateDiscountCode};
+    use serde::Deserialize;
+    use surrealdb::engine::local::{Db, Mem};
+
+    const HOSTILE: [&str; 3] = ["O'Brien", "'; DELETE users;--", "Robert'); DROP TABLE clients;--"];
+
+    #[derive(Deserialize, Debug)]
+    struct StoredClient {
+        fullname: String,
+        email: Option<String>,
+    }
+
+    async fn test_database() -> Surreal<Db> {
+        let database = Surreal::new::<Mem>(()).await.expect("base de datos en memoria");
+        database.use_ns("test").use_db("test").await.expect("namespace de pruebas");
+        database
+            .query("CREATE users CONTENT { username: 'admin', fullname: 'Administrador' };")
+            .await
+            .expect("usuario inicial");
+        database
+    }
+
+    fn client_named(fullname: &str) -> NewCliente {
+        NewCliente {
+            fullname: fullname.to_string(),
+            is_minor: false,
+            phone: None,
+            email: Some(format!("{}@example.com", fullname)),
+            monthly_pay_ref: None,
+            is_preferred: false,
+            schedule: None,
+            is_active: true,
+            times: None,
+        }
+    }
+
+    async fn count(database: &Surreal<Db>, table: &str) -> usize {
+        let total: Option<usize> = database
+            .query("RETURN count(SELECT id FROM type::table($table));")
+            .bind(("table", table.to_string()))
+            .await
+            .unwrap()
+            .take(0)
+            .unwrap();
+        total.unwrap_or_default()
+    }
+
+    #[rocket::async_test]
+    async fn create_record_stores_hostile_text_literally() {
+        let database = test_database().await;
+
+        for name in HOSTILE {
+            let created: Option<StoredClient> =
+                create_record(&database, "clients", client_named(name)).await.unwrap();
+            let created = created.expect("registro creado");
+            assert_eq!(created.fullname, name);
+            assert_eq!(created.email.as_deref(), Some(format!("{}@example.com", name).as_str()));
+        }
+
+        assert_eq!(count(&database, "clients").await, HOSTILE.len());
+        assert_eq!(count(&database, "users").await, 1);
+    }
+
+    #[rocket::async_test]
+    async fn find_one_by_matches_hostile_text_exactly() {
+        let database = test_database().await;
+        let _: Option<StoredClient> =
+            create_record(&database, "clients", client_named("O'Brien")).await.unwrap();
+
+        let found: Option<StoredClient> =
+            find_one_by(&database, "clients", "fullname", "O'Brien".to_string()).await.unwrap();
+        assert_eq!(found.map(|client| client.fullname).as_deref(), Some("O'Brien"));
+
+        let missing: Option<StoredClient> =
+            find_one_by(&database, "users", "username", "'; DELETE users;--".to_string()).await.unwrap();
+        assert!(missing.is_none());
+        assert_eq!(count(&database, "users").await, 1);
+    }
+
+    #[rocket::async_test]
+    async fn merge_record_and_delete_where_treat_values_as_data() {
+        let database = test_database().await;
+        let created: Option<DiscountCode> = create_record(
+            &database,
+            "discount_codes",
+            DiscountCode {
+                code: "VERANO".to_string(),
+                discount_type: DiscountKind::Fixed,
+                discount_value: Decimal::new(1250, 2),
+                active: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert!(created.is_some());
+
+        let ids: Vec<RecordId> = database
+            .query("SELECT VALUE id FROM discount_codes;")
+            .await
+            .unwrap()
+            .take(0)
+            .unwrap();
+        let patch = UpdateDiscountCode {
+            code: Some("'; DELETE users;--".to_string()),
+            ..Default::default()
+        };
+        merge_record(&database, ids[0].clone(), patch).await.unwrap();
+
+        let stored: Option<DiscountCode> =
+            find_one_by(&database, "discount_codes", "code", "'; DELETE users;--".to_string()).await.unwrap();
+        let stored = stored.expect("código actualizado");
+        assert_eq!(stored.discount(), Some(DiscountValue::Fixed(Money::from_cents(1250))));
+        let is_decimal: Option<bool> = database
+            .query("RETURN type::is::decimal((SELECT VALUE discount_value FROM discount_codes)[0]);")
+            .await
+            .unwrap()
+            .take(0)
+            .unwrap();
+        assert_eq!(is_decimal, Some(true));
+        assert_eq!(count(&database, "users").await, 1);
+
+        // Los códigos anteriores guardaban el valor como `float`
+        database
+            .query("CREATE discount_codes CONTENT { code: 'VIEJO', discount_type: 'fijo', discount_value: 12.345f, active: true };")
+            .await
+            .unwrap();
+        let legacy: Option<DiscountCode> =
+            find_one_by(&database, "discount_codes", "code", "VIEJO".to_string()).await.unwrap();
+        assert_eq!(legacy.and_then(|code| code.discount()), Some(DiscountValue::Fixed(Money::from_cents(1235))));
+        delete_where(&database, "discount_codes", "code", "VIEJO".to_string()).await.unwrap();
+
+        delete_where(&database, "discount_codes", "code", "' OR true;--".to_string()).await.unwrap();
+        assert_eq!(count(&database, "discount_codes").await, 1);
+        delete_where(&database, "discount_codes", "code", "'; DELETE users;--".to_string()).await.unwrap();
+        assert_eq!(count(&database, "discount_codes").await, 0);
+    }
+
+    #[test]
+    fn parse_record_id_rejects_injected_ids() {
+        assert!(parse_record_id("users", "users:1; DELETE users").is_err());
+        assert!(parse_record_id("users", "sales:abc").is_err());
+        assert!(parse_record_id("users", "").is_err());
+
+        let prefixed = parse_record_id("products", "products:abc").unwrap();
+        assert_eq!(prefixed.table(), "products");
+
+        // Un ID sin tabla se toma como llave literal, incluso con comillas
+        let literal = parse_record_id("users", "O'Brien").unwrap();
+        assert_eq!(literal.table(), "users");
+        assert_eq!(literal, RecordId::from(("users", "O'Brien")));
+    }
+}
@@ -0,0 +1,135 @@
+use rocket::request::FromParam;
+use log::warn;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+// Roles válidos del sistema; cualquier otro valor se rechaza al recibirlo en la API
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Role {
+    #[serde(rename = "admin")]
+    Admin,
+    #[serde(rename = "usuario")]
+    Cashier,
+    #[serde(rename = "instructor")]
+    Instructor,
+    #[serde(rename = "supervisor")]
+    Supervisor,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Role::Admin, Role::Cashier, Role::Instructor, Role::Supervisor];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Cashier => "usuario",
+            Role::Instructor => "instructor",
+            Role::Supervisor => "supervisor",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Role::ALL
+            .into_iter()
+            .find(|role| role.as_str() == value)
+            .ok_or_else(|| format!("Rol desconocido: '{}'", value))
+    }
+}
+
+impl<'a> FromParam<'a> for Role {
+    type Error = String;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        param.parse()
+    }
+}
+
+// Los usuarios antiguos guardan `roles` como un solo texto; los nuevos como lista
+fn raw_roles<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StoredRoles {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match StoredRoles::deserialize(deserializer)? {
+        StoredRoles::One(role) => vec![role],
+        StoredRoles::Many(roles) => roles,
+    })
+}
+
+// Roles de un usuario guardado. Un rol desconocido o antiguo se omite con un aviso para no
+// dejar sin listado de usuarios ni sin inicio de sesión por un solo registro.
+pub fn deserialize_roles<'de, D>(deserializer: D) -> Result<Vec<Role>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(raw_roles(deserializer)?
+        .iter()
+        .filter_map(|role| match role.parse() {
+            Ok(role) => Some(role),
+            Err(err) => {
+                warn!("{} en un usuario guardado; se omite", err);
+                None
+            }
+        })
+        .collect())
+}
+
+// Roles recibidos en la API; ahí un rol desconocido sí es un error
+pub fn deserialize_requested_roles<'de, D>(deserializer: D) -> Result<Vec<Role>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    raw_roles(deserializer)?
+        .iter()
+        .map(|role| role.parse().map_err(serde::de::Error::custom))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Stored {
+        #[serde(deserialize_with = "deserialize_roles")]
+        roles: Vec<Role>,
+    }
+
+    #[derive(Deserialize)]
+    struct Requested {
+        #[serde(deserialize_with = "deserialize_requested_roles")]
+        roles: Vec<Role>,
+    }
+
+    #[test]
+    fn stored_roles_skip_unknown_values() {
+        let stored: Stored = serde_json::from_str(r#"{"roles": ["admin", "cajero"]}"#).unwrap();
+        assert_eq!(stored.roles, vec![Role::Admin]);
+        let stored: Stored = serde_json::from_str(r#"{"roles": "usuario"}"#).unwrap();
+        assert_eq!(stored.roles, vec![Role::Cashier]);
+    }
+
+    #[test]
+    fn requested_roles_reject_unknown_values() {
+        let requested: Requested = serde_json::from_str(r#"{"roles": ["admin", "instructor"]}"#).unwrap();
+        assert_eq!(requested.roles, vec![Role::Admin, Role::Instructor]);
+        assert!(serde_json::from_str::<Requested>(r#"{"roles": ["admin", "cajero"]}"#).is_err());
+    }
+}
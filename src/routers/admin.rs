@@ -1,18 +1,28 @@
 use crate::crud::{delete_user, create_user, update_user, get_users, UpdateUser, User, UserAsString};
 use crate::crud_inventory::{create_product, get_product, get_product_by_id, update_product, delete_product, get_category, Product, UpdateProduct, ProductAsString, Category, create_category, delete_category};
 use crate::promos::{get_discount_codes, create_discount_code, update_discount_code, delete_discount_code, DiscountCode, UpdateDiscountCode};
-use crate::crud_sales::{get_sales_by_date_range, SimplifiedSales, get_sales, Sales, SalesAsString, SalesAsRecord};
+use crate::crud_sales::{get_sales_by_date_range, SimplifiedSales, get_sales, Sales, SalesAsString, SalesPage, DEFAULT_PAGE_SIZE};
 use crate::crud_clients::*;
 use rocket::http::Status;
 use rocket::serde::json::Json;
 use rocket::Route;
 use rocket::State;
 use rocket_basicauth::BasicAuth;
-use crate::auth::*;
 use surrealdb::engine::remote::ws::Client;
 use surrealdb::Surreal;
 use crate::exams::*;
 use crate::crud_bundles::{create_bundle, get_bundles, get_bundle_by_id, update_bundle, delete_bundle, Bundle};
+use crate::sessions::{get_user_sessions, revoke_user_session, revoke_all_user_sessions, SessionAsString};
+use crate::permissions::*;
+use crate::roles::Role;
+use crate::exchange_rates::{get_exchange_rates, set_exchange_rate, ExchangeRate};
+use crate::config::AppConfig;
+use crate::taxes::{get_tax_rates, get_tax_summary, set_tax_rate, TaxRate, TaxSummary};
+use crate::reports::{close_z_report, get_x_report, get_z_report, get_z_reports, report_receipt, ReportRequest, SalesReport};
+use crate::receipts::ReceiptJson;
+use crate::mail::{get_mail_messages, queue_test_mail, retry_mail, MailKind, MailMessage, MailStatus, TestMailRequest};
+use crate::statements::{queue_monthly_mail_request, MonthlyMailRequest, MonthlyMailSummary};
+use crate::branches::{branch_branding, get_branches, get_receipt_template, set_branch, set_receipt_template, Branch, ReceiptTemplate};
 
 
 pub fn routes() -> Vec<Route> {
@@ -20,6 +30,11 @@ pub fn routes() -> Vec<Route> {
         update_user_route,
         get_user_route,
         delete_user_route,
+        get_user_sessions_route,
+        revoke_user_session_route,
+        revoke_all_user_sessions_route,
+        get_permissions_route,
+        update_role_permissions_route,
         get_product_route,
         update_product_route,
         create_product_route,
@@ -34,6 +49,26 @@ pub fn routes() -> Vec<Route> {
         delete_categories_route,
         get_sales_route,
         get_sales_by_date_range_route,
+        get_exchange_rates_route,
+        set_exchange_rate_route,
+        get_tax_rates_route,
+        set_tax_rate_route,
+        get_tax_summary_route,
+        get_x_report_route,
+        print_x_report_route,
+        close_z_report_route,
+        get_z_reports_route,
+        get_z_report_route,
+        print_z_report_route,
+        get_branches_route,
+        set_branch_route,
+        get_receipt_template_route,
+        set_receipt_template_route,
+        get_mail_messages_route,
+        retry_mail_route,
+        queue_test_mail_route,
+        queue_statements_route,
+        queue_reminders_route,
         create_clients_route,
         get_clients_route,
         update_clients_route,
@@ -57,7 +92,7 @@ pub fn routes() -> Vec<Route> {
 #[post("/clients", format = "json", data = "<new_client>")]
 pub async fn create_clients_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    user: RequirePermission<ClientsWrite>,
     new_client: Json<NewCliente>,
 ) -> Result<Status, Status> {
     // Verificar si el usuario tiene el rol de admin
@@ -72,108 +107,284 @@ pub async fn create_clients_route(
 #[delete("/clients/<client_id>")]
 pub async fn delete_clients_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<ClientsDelete>,
     client_id: String,
 ) -> Result<Status, Status> {
-    if user.is_admin() {
-        delete_client(database, client_id).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    delete_client(database, client_id).await
 }
 
 #[get("/clients")]
 pub async fn get_clients_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<ClientsRead>,
 ) -> Result<Json<Vec<ClienteAsString>>, Status> {
-    if user.is_admin() {
-        get_clients(database).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    get_clients(database).await
 }
 
 #[put("/clients/<client_id>", format = "json", data = "<updated_data>")]
 pub async fn update_clients_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<ClientsWrite>,
     client_id: String,
     updated_data: Json<UpdateCliente>,
 ) -> Result<Status, Status> {
-    if user.is_admin() {
-        update_client(database, client_id, updated_data).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    update_client(database, client_id, updated_data).await
 }
 
 //Obtener las ventas:
-#[get("/sales")]
+#[get("/sales?<include_voided>")]
 pub async fn get_sales_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    config: &State<AppConfig>,
+    _user: RequirePermission<SalesRead>,
+    include_voided: Option<bool>,
 ) -> Result<Json<Vec<SimplifiedSales>>, Status> {
-    if user.is_admin() || user.has_role("usuario") {
-        get_sales(database).await
-    } else {
-        Err(Status::Forbidden)
+    get_sales(database, config.timezone, include_voided.unwrap_or(false)).await
+}
+
+
+// Tasas de cambio diarias
+#[get("/exchange-rates")]
+pub async fn get_exchange_rates_route(
+    database: &State<Surreal<Client>>,
+    _user: RequirePermission<ExchangeRatesRead>,
+) -> Result<Json<Vec<ExchangeRate>>, Status> {
+    get_exchange_rates(database).await
+}
+
+#[post("/exchange-rates", format = "json", data = "<new_rate>")]
+pub async fn set_exchange_rate_route(
+    database: &State<Surreal<Client>>,
+    config: &State<AppConfig>,
+    _user: RequirePermission<ExchangeRatesWrite>,
+    new_rate: Json<ExchangeRate>,
+) -> Result<Status, Status> {
+    set_exchange_rate(database, &config.base_currency, new_rate).await
+}
+
+// Tasas de impuesto (IVA) y resumen para el contador
+#[get("/tax-rates")]
+pub async fn get_tax_rates_route(
+    database: &State<Surreal<Client>>,
+    _user: RequirePermission<TaxesRead>,
+) -> Result<Json<Vec<TaxRate>>, Status> {
+    get_tax_rates(database).await
+}
+
+#[post("/tax-rates", format = "json", data = "<new_rate>")]
+pub async fn set_tax_rate_route(
+    database: &State<Surreal<Client>>,
+    _user: RequirePermission<TaxesWrite>,
+    new_rate: Json<TaxRate>,
+) -> Result<Status, Status> {
+    set_tax_rate(database, new_rate).await
+}
+
+#[post("/reports/taxes", format = "json", data = "<date_range>")]
+pub async fn get_tax_summary_route(
+    database: &State<Surreal<Client>>,
+    config: &State<AppConfig>,
+    _user: RequirePermission<TaxesRead>,
+    date_range: Json<serde_json::Value>,
+) -> Result<Json<TaxSummary>, Status> {
+    let start_date = date_range.get("start_date").and_then(|v| v.as_str()).unwrap_or("");
+    let end_date = date_range.get("end_date").and_then(|v| v.as_str()).unwrap_or("");
+
+    if start_date.is_empty() || end_date.is_empty() {
+        return Err(Status::BadRequest);
     }
+
+    get_tax_summary(database, config, start_date.to_string(), end_date.to_string()).await
+}
+
+// Reportes X (corte parcial) y Z (cierre numerado del día)
+#[post("/reports/x", format = "json", data = "<request>")]
+pub async fn get_x_report_route(
+    database: &State<Surreal<Client>>,
+    config: &State<AppConfig>,
+    user: RequirePermission<ReportsRead>,
+    request: Json<ReportRequest>,
+) -> Result<Json<SalesReport>, Status> {
+    get_x_report(database, config, &user.username, request.into_inner()).await
+}
+
+#[post("/reports/x/receipt", format = "json", data = "<request>")]
+pub async fn print_x_report_route(
+    database: &State<Surreal<Client>>,
+    config: &State<AppConfig>,
+    user: RequirePermission<ReportsRead>,
+    request: Json<ReportRequest>,
+) -> Result<Json<ReceiptJson>, Status> {
+    let report = get_x_report(database, config, &user.username, request.into_inner()).await?;
+    let branding = branch_branding(database, config, &report.branch).await?;
+    Ok(Json(report_receipt(&report, &branding)))
 }
 
+#[post("/reports/z", format = "json", data = "<request>")]
+pub async fn close_z_report_route(
+    database: &State<Surreal<Client>>,
+    config: &State<AppConfig>,
+    user: RequirePermission<ReportsClose>,
+    request: Json<ReportRequest>,
+) -> Result<Json<SalesReport>, Status> {
+    close_z_report(database, config, &user.username, request.into_inner()).await
+}
+
+#[get("/reports/z?<branch>")]
+pub async fn get_z_reports_route(
+    database: &State<Surreal<Client>>,
+    config: &State<AppConfig>,
+    _user: RequirePermission<ReportsRead>,
+    branch: Option<String>,
+) -> Result<Json<Vec<SalesReport>>, Status> {
+    get_z_reports(database, branch.unwrap_or_else(|| config.branch.clone())).await
+}
+
+#[get("/reports/z/<branch>/<number>")]
+pub async fn get_z_report_route(
+    database: &State<Surreal<Client>>,
+    _user: RequirePermission<ReportsRead>,
+    branch: String,
+    number: u32,
+) -> Result<Json<SalesReport>, Status> {
+    get_z_report(database, branch, number).await
+}
+
+#[get("/reports/z/<branch>/<number>/receipt")]
+pub async fn print_z_report_route(
+    database: &State<Surreal<Client>>,
+    config: &State<AppConfig>,
+    _user: RequirePermission<ReportsRead>,
+    branch: String,
+    number: u32,
+) -> Result<Json<ReceiptJson>, Status> {
+    let report = get_z_report(database, branch, number).await?;
+    let branding = branch_branding(database, config, &report.branch).await?;
+    Ok(Json(report_receipt(&report, &branding)))
+}
+
+// Sucursales y plantilla de los recibos
+#[get("/branches")]
+pub async fn get_branches_route(
+    database: &State<Surreal<Client>>,
+    _user: RequirePermission<BranchesRead>,
+) -> Result<Json<Vec<Branch>>, Status> {
+    get_branches(database).await
+}
+
+#[post("/branches", format = "json", data = "<new_branch>")]
+pub async fn set_branch_route(
+    database: &State<Surreal<Client>>,
+    _user: RequirePermission<BranchesWrite>,
+    new_branch: Json<Branch>,
+) -> Result<Status, Status> {
+    set_branch(database, new_branch).await
+}
+
+#[get("/receipt-template")]
+pub async fn get_receipt_template_route(
+    database: &State<Surreal<Client>>,
+    _user: RequirePermission<BranchesRead>,
+) -> Result<Json<ReceiptTemplate>, Status> {
+    get_receipt_template(database).await
+}
+
+#[put("/receipt-template", format = "json", data = "<template>")]
+pub async fn set_receipt_template_route(
+    database: &State<Surreal<Client>>,
+    _user: RequirePermission<BranchesWrite>,
+    template: Json<ReceiptTemplate>,
+) -> Result<Status, Status> {
+    set_receipt_template(database, template).await
+}
+
+// Cola de correos con la bitácora de cada envío
+#[get("/mail?<status>&<limit>")]
+pub async fn get_mail_messages_route(
+    database: &State<Surreal<Client>>,
+    _user: RequirePermission<MailRead>,
+    status: Option<MailStatus>,
+    limit: Option<u32>,
+) -> Result<Json<Vec<MailMessage>>, Status> {
+    get_mail_messages(database, status, limit.unwrap_or(100).min(500)).await
+}
+
+#[post("/mail/<mail_id>/retry")]
+pub async fn retry_mail_route(
+    database: &State<Surreal<Client>>,
+    _user: RequirePermission<MailSend>,
+    mail_id: String,
+) -> Result<Status, Status> {
+    retry_mail(database, mail_id).await
+}
+
+#[post("/mail/test", format = "json", data = "<request>")]
+pub async fn queue_test_mail_route(
+    database: &State<Surreal<Client>>,
+    config: &State<AppConfig>,
+    _user: RequirePermission<MailSend>,
+    request: Json<TestMailRequest>,
+) -> Result<Status, Status> {
+    queue_test_mail(database, config, request).await
+}
+
+// Estados de cuenta y recordatorios de mensualidades; sin cuerpo, los del mes en curso
+#[post("/mail/statements", format = "json", data = "<request>")]
+pub async fn queue_statements_route(
+    database: &State<Surreal<Client>>,
+    config: &State<AppConfig>,
+    _user: RequirePermission<MailSend>,
+    request: Json<MonthlyMailRequest>,
+) -> Result<Json<MonthlyMailSummary>, Status> {
+    queue_monthly_mail_request(database, config, MailKind::Statement, request).await
+}
+
+#[post("/mail/reminders", format = "json", data = "<request>")]
+pub async fn queue_reminders_route(
+    database: &State<Surreal<Client>>,
+    config: &State<AppConfig>,
+    _user: RequirePermission<MailSend>,
+    request: Json<MonthlyMailRequest>,
+) -> Result<Json<MonthlyMailSummary>, Status> {
+    queue_monthly_mail_request(database, config, MailKind::Reminder, request).await
+}
 
 //CRUD de los codigos de promoción
 
 #[get("/promos")]
 pub async fn get_discount_codes_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<PromosRead>,
 ) -> Result<Json<Vec<DiscountCode>>, Status> {
-    if user.is_admin() {
-        get_discount_codes(database).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    get_discount_codes(database).await
 }
 
 #[post("/promos", format = "json", data = "<new_code>")]
 pub async fn create_discount_code_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<PromosWrite>,
     new_code: Json<DiscountCode>,
 ) -> Result<Status, Status> {
-    if user.is_admin() {
-        create_discount_code(database, new_code).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    create_discount_code(database, new_code).await
 }
 
 #[put("/promos/<discount_id>", format = "json", data = "<update_data>")]
 pub async fn update_discount_code_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<PromosWrite>,
     discount_id: String,
     update_data: Json<UpdateDiscountCode>,
 ) -> Result<Status, Status> {
-    if user.is_admin() {
-        update_discount_code(database, discount_id, update_data).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    update_discount_code(database, discount_id, update_data).await
 }
 
 #[delete("/promos/<discount_id>")]
 pub async fn delete_discount_code_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<PromosWrite>,
     discount_id: String,
 ) -> Result<Status, Status> {
-    if user.is_admin() {
-        delete_discount_code(database, discount_id).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    delete_discount_code(database, discount_id).await
 }
 
 
@@ -181,156 +392,158 @@ pub async fn delete_discount_code_route(
 #[post("/users", format = "json", data = "<new_user>")]
 pub async fn create_user_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequireAdmin,
     new_user: Json<User>,
 ) -> Result<Status, Status> {
-    if user.is_admin() {
-        create_user(database, new_user).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    create_user(database, new_user).await
 }
 
 #[put("/users/<user_id>", format = "json", data = "<update_data>")]
 pub async fn update_user_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequireAdmin,
     user_id: String,
     update_data: Json<UpdateUser>,
 ) -> Result<Status, Status> {
-    if user.is_admin() {
-        update_user(database, user_id, update_data).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    update_user(database, user_id, update_data).await
 }
 
 #[get("/users")]
 pub async fn get_user_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequireAdmin,
 ) -> Result<Json<Vec<UserAsString>>, Status> {
-    if user.is_admin() {
-        get_users(database).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    get_users(database).await
 }
 #[delete("/users/<user_id>")]
 pub async fn delete_user_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    user: RequireAdmin,
     user_id: String,
 ) -> Result<Status, Status> {
-    if user.is_admin() {
-        delete_user(database, user_id).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    // Cerrar las sesiones abiertas antes de eliminar al usuario
+    revoke_all_user_sessions(database, user_id.clone(), &user.username).await?;
+    delete_user(database, user_id).await
+}
+
+// Sesiones activas de los usuarios
+#[get("/users/<user_id>/sessions")]
+pub async fn get_user_sessions_route(
+    database: &State<Surreal<Client>>,
+    _user: RequireAdmin,
+    user_id: String,
+) -> Result<Json<Vec<SessionAsString>>, Status> {
+    get_user_sessions(database, user_id).await
+}
+
+#[delete("/users/<user_id>/sessions/<session_id>")]
+pub async fn revoke_user_session_route(
+    database: &State<Surreal<Client>>,
+    user: RequireAdmin,
+    user_id: String,
+    session_id: String,
+) -> Result<Status, Status> {
+    revoke_user_session(database, user_id, session_id, &user.username).await
+}
+
+#[delete("/users/<user_id>/sessions")]
+pub async fn revoke_all_user_sessions_route(
+    database: &State<Surreal<Client>>,
+    user: RequireAdmin,
+    user_id: String,
+) -> Result<Status, Status> {
+    revoke_all_user_sessions(database, user_id, &user.username).await
 }
+// Matriz de permisos por rol
+#[get("/permissions")]
+pub async fn get_permissions_route(
+    database: &State<Surreal<Client>>,
+    _user: RequireAdmin,
+) -> Result<Json<Vec<RolePermissions>>, Status> {
+    get_permissions(database).await
+}
+
+#[put("/permissions/<role>", format = "json", data = "<update_data>")]
+pub async fn update_role_permissions_route(
+    database: &State<Surreal<Client>>,
+    _user: RequireAdmin,
+    role: Role,
+    update_data: Json<UpdateRolePermissions>,
+) -> Result<Status, Status> {
+    update_role_permissions(database, role, update_data).await
+}
+
 // CRUD del Inventariado
 
 #[get("/inventory/categories")]
 pub async fn get_categories_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<InventoryRead>,
 ) -> Result<Json<Vec<String>>, Status> {
-    if user.is_admin() {
-        get_category(database).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    get_category(database).await
 }
 
 #[get("/inventory")]
 pub async fn get_product_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<InventoryRead>,
 ) -> Result<Json<Vec<ProductAsString>>, Status> {
-    if user.has_role("admin") || user.has_role("usuario") {
-        get_product(database).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    get_product(database).await
 }
 
 #[get("/inventory/<product_id>")]
 pub async fn get_product_by_id_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<InventoryRead>,
     product_id: String,
 ) -> Result<Json<ProductAsString>, Status> {
-    if user.has_role("admin") || user.has_role("usuario") {
-        get_product_by_id(database, product_id).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    get_product_by_id(database, product_id).await
 }
 
 #[post("/inventory/categories", format = "json", data = "<new_category>")]
 pub async fn create_categories_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<InventoryManage>,
     new_category: Json<Category>,
 ) -> Result<Status, Status> {
-    if user.is_admin() {
-        create_category(database, new_category).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    create_category(database, new_category).await
 }
 
 #[post("/inventory", format = "json", data = "<new_product>")]
 pub async fn create_product_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<InventoryManage>,
     new_product: Json<Product>,
 ) -> Result<Status, Status> {
-    if user.is_admin() {
-        create_product(database, new_product).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    create_product(database, new_product).await
 }
 
 #[put("/inventory/<product_id>", format = "json", data = "<update_data>")]
 pub async fn update_product_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<InventoryWrite>,
     product_id: String,
     update_data: Json<UpdateProduct>,
 ) -> Result<Status, Status> {
-    if user.is_admin() {
-        update_product(database, product_id, update_data).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    update_product(database, product_id, update_data).await
 }
 
 #[delete("/inventory/<product_id>")]
 pub async fn delete_product_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<InventoryManage>,
     product_id: String,
 ) -> Result<Status, Status> {
-    if user.is_admin() {
-        delete_product(database, product_id).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    delete_product(database, product_id).await
 }
 
 #[delete("/inventory/categories/<category>")]
 pub async fn delete_categories_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<InventoryManage>,
     category: String,
 ) -> Result<Status, Status> {
-    if user.is_admin() {
-        delete_category(database, category).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    delete_category(database, category).await
 }
 
 //Examenes 
@@ -338,142 +551,111 @@ pub async fn delete_categories_route(
 #[post("/exams", format = "json", data = "<new_exam>")]
 pub async fn create_exam_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<ExamsWrite>,
     new_exam: Json<Exam>,
 ) -> Result<Status, Status> {
-    if user.is_admin() {
-        create_exam(database, new_exam).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    create_exam(database, new_exam).await
 }
 
 #[get("/exams")]
 pub async fn get_exams_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<ExamsRead>,
 ) -> Result<Json<Vec<ExamAsString>>, Status> {
-    if user.has_role("admin") || user.has_role("usuario") {
-        get_exams(database).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    get_exams(database).await
 }
 
 #[put("/exams/<exam_id>", format = "json", data = "<update_data>")]
 pub async fn update_exam_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<ExamsWrite>,
     exam_id: String,
     update_data: Json<UpdateExam>,
 ) -> Result<Status, Status> {
-    if user.is_admin() {
-        update_exam(database, exam_id, update_data).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    update_exam(database, exam_id, update_data).await
 }
 
 #[delete("/exams/<exam_id>")]
 pub async fn delete_exam_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<ExamsWrite>,
     exam_id: String,
 ) -> Result<Status, Status> {
-    if user.is_admin() {
-        delete_exam(database, exam_id).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    delete_exam(database, exam_id).await
 }
 
 #[post("/sales/date-range", format = "json", data = "<date_range>")]
 pub async fn get_sales_by_date_range_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    config: &State<AppConfig>,
+    _user: RequirePermission<SalesRead>,
     date_range: Json<serde_json::Value>,
-) -> Result<Json<Vec<SimplifiedSales>>, Status> {
-    if user.has_role("admin") || user.has_role("usuario") {
-        let start_date = date_range.get("start_date").and_then(|v| v.as_str()).unwrap_or("");
-        let end_date = date_range.get("end_date").and_then(|v| v.as_str()).unwrap_or("");
+) -> Result<Json<SalesPage>, Status> {
+    let start_date = date_range.get("start_date").and_then(|v| v.as_str()).unwrap_or("");
+    let end_date = date_range.get("end_date").and_then(|v| v.as_str()).unwrap_or("");
 
-        if start_date.is_empty() || end_date.is_empty() {
-            return Err(Status::BadRequest);
-        }
+    let include_voided = date_range.get("include_voided").and_then(|v| v.as_bool()).unwrap_or(false);
+    let page = date_range.get("page").and_then(|v| v.as_u64()).unwrap_or(1);
+    let page_size = date_range.get("page_size").and_then(|v| v.as_u64()).unwrap_or(DEFAULT_PAGE_SIZE as u64);
 
-        get_sales_by_date_range(database, start_date.to_string(), end_date.to_string()).await
-    } else {
-        Err(Status::Forbidden)
+    if start_date.is_empty() || end_date.is_empty() {
+        return Err(Status::BadRequest);
     }
+
+    let (Ok(page), Ok(page_size)) = (u32::try_from(page), u32::try_from(page_size)) else {
+        return Err(Status::BadRequest);
+    };
+
+    get_sales_by_date_range(database, config.timezone, start_date.to_string(), end_date.to_string(), include_voided, page, page_size).await
 }
 
 // Crear un Bundle
 #[post("/bundles", format = "json", data = "<new_bundle>")]
 pub async fn create_bundle_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<BundlesManage>,
     new_bundle: Json<Bundle>,
 ) -> Result<Status, Status> {
-    if user.is_admin() {
-        create_bundle(database, new_bundle).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    create_bundle(database, new_bundle).await
 }
 
 // Obtener todos los Bundles
 #[get("/bundles")]
 pub async fn get_bundles_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<BundlesRead>,
 ) -> Result<Json<Vec<Bundle>>, Status> {
-    if user.is_admin() {
-        get_bundles(database).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    get_bundles(database).await
 }
 
 // Obtener un Bundle por ID
 #[get("/bundles/<bundle_id>")]
 pub async fn get_bundle_by_id_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<BundlesRead>,
     bundle_id: String,
 ) -> Result<Json<Bundle>, Status> {
-    if user.is_admin() {
-        get_bundle_by_id(database, bundle_id).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    get_bundle_by_id(database, bundle_id).await
 }
 
 // Actualizar un Bundle
 #[put("/bundles/<bundle_id>", format = "json", data = "<updated_bundle>")]
 pub async fn update_bundle_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<BundlesWrite>,
     bundle_id: String,
     updated_bundle: Json<Bundle>,
 ) -> Result<Status, Status> {
-    if user.is_admin() {
-        update_bundle(database, bundle_id, updated_bundle).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    update_bundle(database, bundle_id, updated_bundle).await
 }
 
 // Eliminar un Bundle
 #[delete("/bundles/<bundle_id>")]
 pub async fn delete_bundle_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<BundlesManage>,
     bundle_id: String,
 ) -> Result<Status, Status> {
-    if user.is_admin() {
-        delete_bundle(database, bundle_id).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    delete_bundle(database, bundle_id).await
 }
 
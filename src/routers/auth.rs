@@ -0,0 +1,40 @@
+use crate::auth::{login, logout, refresh, AuthenticatedUser, LoginRequest, LoginResponse, RefreshRequest};
+use crate::config::AppConfig;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::Route;
+use rocket::State;
+use std::net::IpAddr;
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::Surreal;
+
+pub fn routes() -> Vec<Route> {
+    routes![login_route, refresh_route, logout_route]
+}
+
+#[post("/login", format = "json", data = "<credentials>")]
+pub async fn login_route(
+    database: &State<Surreal<Client>>,
+    config: &State<AppConfig>,
+    credentials: Json<LoginRequest>,
+    ip: Option<IpAddr>,
+) -> Result<Json<LoginResponse>, Status> {
+    login(database, config, credentials, ip).await
+}
+
+#[post("/refresh", format = "json", data = "<request>")]
+pub async fn refresh_route(
+    database: &State<Surreal<Client>>,
+    config: &State<AppConfig>,
+    request: Json<RefreshRequest>,
+) -> Result<Json<LoginResponse>, Status> {
+    refresh(database, config, request).await
+}
+
+#[post("/logout")]
+pub async fn logout_route(
+    database: &State<Surreal<Client>>,
+    user: AuthenticatedUser,
+) -> Result<Status, Status> {
+    logout(database, user).await
+}
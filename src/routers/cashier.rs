@@ -1,5 +1,5 @@
 use crate::crud_inventory::{get_product, update_product, get_category, get_product_by_id, Product, ProductAsString, Category, UpdateProduct};
-use crate::promos::{get_discount_codes, DiscountCode};
+use crate::promos::{get_discount_codes, validate_discount, DiscountCode, DiscountValidation, ValidateDiscountRequest};
 use rocket::http::Status;
 use rocket::serde::json::Json;
 use rocket::Route;
@@ -7,24 +7,41 @@ use rocket::State;
 use rocket_basicauth::BasicAuth;
 use surrealdb::engine::remote::ws::Client;
 use surrealdb::Surreal;
-use crate::crud_sales::{SimplifiedSales, create_sales, get_sales, Sales, update_products_for_new_quantities, ProductWithQuantity, SalesAsString, SalesAsRecord};
-use chrono::{FixedOffset, Utc, DateTime};
+use crate::crud_sales::{SimplifiedSales, create_sales, NumberedSale, get_sales, find_sale, void_sale, Sales, update_products_for_new_quantities, ProductWithQuantity, SalesAsString, VoidRequest};
 use crate::crud_clients::*;
 use crate::exams::*;
-use crate::auth::*;
+use crate::permissions::*;
 use crate::receipts::*;
 use crate::schedules::*;
 use serde_json::Value;
 use crate::crud_bundles::{get_bundles, get_bundle_by_id, update_bundle, Bundle};
+use crate::checkout::{checkout, CheckoutRequest};
+use crate::refunds::{refund_sale, RefundRequest};
+use crate::exchange_rates::{get_current_rates, ExchangeRate};
+use crate::config::AppConfig;
+use crate::printer::print_receipt;
+use crate::branches::receipt_branding;
+use crate::receipt_documents::{receipt_document, ReceiptDocument, ReceiptFormat};
+use crate::shifts::{add_cash_movement, close_shift, find_open_shift, get_current_shift, open_shift, CashMovementRequest, CloseShiftRequest, OpenShiftRequest, ShiftAsString};
 
 pub fn routes() -> Vec<Route> {
     routes![update_product_route,
         get_product_route,
         get_product_by_id_route,
         get_discount_codes_route,
+        validate_discount_route,
         get_categories_route,
         create_sales_route,
+        checkout_route,
+        refund_sale_route,
+        void_sale_route,
+        get_current_rates_route,
+        get_current_shift_route,
+        open_shift_route,
+        add_cash_movement_route,
+        close_shift_route,
         get_sales_route,
+        find_sale_route,
         get_clients_route,
         update_clients_route,
         create_clients_route,
@@ -33,180 +50,262 @@ pub fn routes() -> Vec<Route> {
         //get_inscription_route,
         //create_inscription_route,
         print_receipt_route,
+        print_receipt_to_printer_route,
+        get_sale_receipt_route,
         get_cashier_payments_route,
         update_cashier_payment_route,
         get_bundles_route,
         update_bundle_route] }
 
-fn get_current_date_utc_minus_6() -> String {
-    let utc_minus_6 = FixedOffset::west(6 * 3600);
-    let now = Utc::now();
-    now.with_timezone(&utc_minus_6)
-        .format("%d-%m-%y %H:%M")
-        .to_string()
-}
-
 #[post("/clients", format = "json", data = "<new_client>")]
 pub async fn create_clients_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<ClientsWrite>,
     new_client: Json<NewCliente>,
 ) -> Result<Status, Status> {
-    if user.has_role("usuario") || user.is_admin(){
-        create_client(database, new_client).await
-            .map(|_| Status::Created)
-            .map_err(|err| err)
-    } else {
-        Err(Status::Forbidden)
-    }
+    create_client(database, new_client).await
+        .map(|_| Status::Created)
+        .map_err(|err| err)
 }
 
 #[get("/clients")]
 pub async fn get_clients_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<ClientsRead>,
 ) -> Result<Json<Vec<ClienteAsString>>, Status> {
-    if user.has_role("usuario") || user.is_admin() {
-        get_clients(database).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    get_clients(database).await
 }
 
 #[put("/clients/<client_id>", format = "json", data = "<updated_data>")]
 pub async fn update_clients_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<ClientsWrite>,
     client_id: String,
     updated_data: Json<UpdateCliente>,
 ) -> Result<Status, Status> {
-    if user.has_role("usuario") || user.is_admin(){
-        update_client(database, client_id, updated_data).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    update_client(database, client_id, updated_data).await
 }
 
-#[get("/sales")]
+#[get("/sales?<include_voided>")]
 pub async fn get_sales_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    config: &State<AppConfig>,
+    _user: RequirePermission<SalesRead>,
+    include_voided: Option<bool>,
 ) -> Result<Json<Vec<SimplifiedSales>>, Status> {
-    if user.has_role("usuario") || user.is_admin(){
-        get_sales(database).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    get_sales(database, config.timezone, include_voided.unwrap_or(false)).await
 }
 
 #[post("/sales", format = "json", data = "<new_sale>")]
 pub async fn create_sales_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    config: &State<AppConfig>,
+    user: RequirePermission<SalesCreate>,
     new_sale: Json<Sales>,
+) -> Result<(Status, Json<NumberedSale>), Status> {
+    let shift = find_open_shift(database, &user.username).await?;
+    create_sales(database, config, &user.username, shift, new_sale).await
+        .map(|sale| (Status::Created, sale))
+}
+
+// Venta, inventario y recibo en una sola operación
+#[post("/checkout", format = "json", data = "<request>")]
+pub async fn checkout_route(
+    database: &State<Surreal<Client>>,
+    config: &State<AppConfig>,
+    user: RequirePermission<SalesCreate>,
+    request: Json<CheckoutRequest>,
+) -> Result<Json<ReceiptJson>, Status> {
+    let shift = find_open_shift(database, &user.username).await?;
+    checkout(database, config, &user.username, shift, request).await
+}
+
+// Tasas vigentes hoy para convertir montos en la caja
+#[get("/exchange-rates")]
+pub async fn get_current_rates_route(
+    database: &State<Surreal<Client>>,
+    config: &State<AppConfig>,
+    _user: RequirePermission<ExchangeRatesRead>,
+) -> Result<Json<Vec<ExchangeRate>>, Status> {
+    get_current_rates(database, config).await
+}
+
+// Devolución total o parcial; queda registrado quién la autorizó
+#[post("/sales/<sale_id>/refund", format = "json", data = "<request>")]
+pub async fn refund_sale_route(
+    database: &State<Surreal<Client>>,
+    config: &State<AppConfig>,
+    user: RequirePermission<SalesRefund>,
+    sale_id: String,
+    request: Json<RefundRequest>,
+) -> Result<Json<ReceiptJson>, Status> {
+    let shift = find_open_shift(database, &user.username).await?;
+    refund_sale(database, config, sale_id, &user.username, shift, request).await
+}
+
+// Turno de caja: apertura con fondo, entradas y salidas de efectivo, y cierre con arqueo
+#[get("/shift")]
+pub async fn get_current_shift_route(
+    database: &State<Surreal<Client>>,
+    user: RequirePermission<ShiftsManage>,
+) -> Result<Json<ShiftAsString>, Status> {
+    get_current_shift(database, &user.username).await
+}
+
+#[post("/shift/open", format = "json", data = "<request>")]
+pub async fn open_shift_route(
+    database: &State<Surreal<Client>>,
+    user: RequirePermission<ShiftsManage>,
+    request: Json<OpenShiftRequest>,
+) -> Result<Json<ShiftAsString>, Status> {
+    open_shift(database, &user.username, request).await
+}
+
+#[post("/shift/movements", format = "json", data = "<request>")]
+pub async fn add_cash_movement_route(
+    database: &State<Surreal<Client>>,
+    user: RequirePermission<ShiftsManage>,
+    request: Json<CashMovementRequest>,
 ) -> Result<Status, Status> {
-    if user.has_role("usuario") || user.is_admin() {
-        let current_date = get_current_date_utc_minus_6();
-        let mut sale = new_sale.into_inner();
-        sale.date = Some(current_date);
-        create_sales(database, Json(sale)).await
-            .map(|_| Status::Created)
-            .map_err(|err| err)
-    } else {
-        Err(Status::Forbidden)
-    }
+    add_cash_movement(database, &user.username, request).await
+}
+
+#[post("/shift/close", format = "json", data = "<request>")]
+pub async fn close_shift_route(
+    database: &State<Surreal<Client>>,
+    user: RequirePermission<ShiftsManage>,
+    request: Json<CloseShiftRequest>,
+) -> Result<Json<ShiftAsString>, Status> {
+    close_shift(database, &user.username, request).await
+}
+
+// Anulación con credenciales o PIN de un supervisor presente en la caja
+#[post("/sales/<sale_id>/void", format = "json", data = "<request>")]
+pub async fn void_sale_route(
+    database: &State<Surreal<Client>>,
+    user: RequirePermission<SalesCreate>,
+    sale_id: String,
+    request: Json<VoidRequest>,
+) -> Result<Status, Status> {
+    void_sale(database, sale_id, &user.username, request).await
 }
 
 #[get("/promos")]
 pub async fn get_discount_codes_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<PromosRead>,
 ) -> Result<Json<Vec<DiscountCode>>, Status> {
-    if user.has_role("usuario") || user.is_admin() {
-        get_discount_codes(database).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    get_discount_codes(database).await
+}
+
+// Descuento que tendría el carrito con el código, o el motivo por el que no aplica
+#[post("/promos/validate", format = "json", data = "<request>")]
+pub async fn validate_discount_route(
+    database: &State<Surreal<Client>>,
+    config: &State<AppConfig>,
+    _user: RequirePermission<PromosRead>,
+    request: Json<ValidateDiscountRequest>,
+) -> Result<Json<DiscountValidation>, Status> {
+    validate_discount(database, config, request).await
 }
 
 #[get("/inventory/categories")]
 pub async fn get_categories_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<InventoryRead>,
 ) -> Result<Json<Vec<String>>, Status> {
-    if user.has_role("usuario") || user.is_admin() {
-        get_category(database).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    get_category(database).await
 }
 
 #[get("/inventory")]
 pub async fn get_product_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<InventoryRead>,
 ) -> Result<Json<Vec<ProductAsString>>, Status> {
-    if user.has_role("usuario") || user.is_admin() {
-        get_product(database).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    get_product(database).await
 }
 
 #[put("/inventory/<product_id>", format = "json", data = "<update_data>")]
 pub async fn update_product_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<InventoryWrite>,
     product_id: String,
     update_data: Json<UpdateProduct>,
 ) -> Result<Status, Status> {
-    if user.has_role("usuario") || user.is_admin(){
-        update_product(database, product_id, update_data).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    update_product(database, product_id, update_data).await
 }
 
 #[get("/inventory/<product_id>")]
 pub async fn get_product_by_id_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<InventoryRead>,
     product_id: String,
 ) -> Result<Json<ProductAsString>, Status> {
-    if user.has_role("usuario") || user.is_admin() {
-        get_product_by_id(database, product_id).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    get_product_by_id(database, product_id).await
 }
 
 #[post("/update_inventory", format = "json", data = "<products>")]
 pub async fn update_products_for_new_quantities_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<InventoryWrite>,
     products: Json<Vec<ProductWithQuantity>>,
 ) -> Result<Status, Status> {
-    if user.has_role("usuario") || user.is_admin() {
-        update_products_for_new_quantities(database, products).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    update_products_for_new_quantities(database, products).await
 }
 
 
-// Impresion
-#[post("/receipt", format = "json", data = "<sale>")]
+// Impresion; `?format=html` o `?format=pdf` devuelven el recibo como documento
+#[post("/receipt?<format>", format = "json", data = "<sale>")]
 pub async fn print_receipt_route(
     sale: Json<PrintedSales>,
-    user: AuthenticatedUser,
+    user: RequirePermission<ReceiptsPrint>,
+    database: &State<Surreal<Client>>,
+    config: &State<AppConfig>,
+    format: Option<ReceiptFormat>,
+) -> Result<ReceiptDocument, Status> {
+    let branding = receipt_branding(database, config, &user.username).await?;
+    let receipt = generate_receipt(sale.into_inner(), &user.username, &branding, database).await?;
+    Ok(receipt_document(receipt, format.unwrap_or_default()))
+}
+
+// Copia marcada como reimpresión del recibo de una venta ya registrada. `sale_id` acepta
+// también lo leído del QR del recibo o el número de factura.
+#[get("/sales/<sale_id>/receipt?<format>")]
+pub async fn get_sale_receipt_route(
+    database: &State<Surreal<Client>>,
+    config: &State<AppConfig>,
+    user: RequirePermission<ReceiptsPrint>,
+    sale_id: String,
+    format: Option<ReceiptFormat>,
+) -> Result<ReceiptDocument, Status> {
+    let receipt = get_sale_receipt(database, config, &sale_id, &user.username).await?;
+    Ok(receipt_document(receipt, format.unwrap_or_default()))
+}
+
+// Venta buscada con el escáner de la caja: QR del recibo, ID o número de factura
+#[get("/sales/lookup?<code>")]
+pub async fn find_sale_route(
+    database: &State<Surreal<Client>>,
+    config: &State<AppConfig>,
+    _user: RequirePermission<SalesRead>,
+    code: String,
+) -> Result<Json<SimplifiedSales>, Status> {
+    find_sale(database, config.timezone, &code).await
+}
+
+// Igual que /receipt, pero además lo envía a la impresora térmica de la sucursal del cajero
+#[post("/receipt/print", format = "json", data = "<sale>")]
+pub async fn print_receipt_to_printer_route(
+    sale: Json<PrintedSales>,
+    user: RequirePermission<ReceiptsPrint>,
     database: &State<Surreal<Client>>,
+    config: &State<AppConfig>,
 ) -> Result<Json<ReceiptJson>, Status> {
-    if user.has_role("usuario") || user.is_admin() {
-        let receipt = generate_receipt(sale.into_inner(), database).await;
-        Ok(Json(receipt))
-    } else {
-        Err(Status::Forbidden)
-    }
+    // La impresora es la de la sucursal del cajero, la misma que sale en el recibo
+    let branding = receipt_branding(database, config, &user.username).await?;
+    let receipt = generate_receipt(sale.into_inner(), &user.username, &branding, database).await?;
+    print_receipt(config, &branding.branch.code, &receipt).await?;
+    Ok(Json(receipt))
 }
 
 
@@ -214,18 +313,15 @@ pub async fn print_receipt_route(
 #[get("/payments")]
 pub async fn get_cashier_payments_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<PaymentsRead>,
 ) -> Result<Json<Vec<SimplifiedPaymentAsString>>, Status> {
-    if user.has_role("usuario") || user.is_admin() {
-        get_history_payments(database).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    get_history_payments(database).await
 }
 
 #[put("/payments/<payment_id>", format = "json", data = "<updated_payment>")]
 pub async fn update_cashier_payment_route(
     database: &State<Surreal<Client>>,
+    _user: RequirePermission<PaymentsUpdate>,
     payment_id: String,
     updated_payment: Json<Value>,
 ) -> Result<Status, Status> {
@@ -236,27 +332,19 @@ pub async fn update_cashier_payment_route(
 #[get("/bundles")]
 pub async fn get_bundles_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<BundlesRead>,
 ) -> Result<Json<Vec<Bundle>>, Status> {
-    if user.has_role("usuario") || user.is_admin() {
-        get_bundles(database).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    get_bundles(database).await
 }
 
 // Actualizar un Bundle
 #[put("/bundles/<bundle_id>", format = "json", data = "<updated_bundle>")]
 pub async fn update_bundle_route(
     database: &State<Surreal<Client>>,
-    user: AuthenticatedUser,
+    _user: RequirePermission<BundlesWrite>,
     bundle_id: String,
     updated_bundle: Json<Bundle>,
 ) -> Result<Status, Status> {
-    if user.has_role("usuario") || user.is_admin() {
-        update_bundle(database, bundle_id, updated_bundle).await
-    } else {
-        Err(Status::Forbidden)
-    }
+    update_bundle(database, bundle_id, updated_bundle).await
 }
 
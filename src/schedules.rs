@@ -8,6 +8,7 @@ use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 use log::{info, error};
 use serde_json::Value;
+use crate::repository::{merge_record, parse_record_id};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UpdatePayment {
@@ -94,25 +95,12 @@ pub async fn update_payment(
         return Err(Status::BadRequest);
     }
 
-    // Serializar los datos proporcionados como JSON
-    let updated_content = match serde_json::to_string(&updated_data) {
-        Ok(json) => json,
-        Err(err) => {
-            log::error!("Error al serializar los datos de actualización: {:?}", err);
-            return Err(Status::BadRequest);
-        }
-    };
-
-    // Construir la consulta SQL dinámica
-    let query = format!(
-        "UPDATE {} MERGE {};",
-        payment_id, updated_content
-    );
+    let record_id = parse_record_id("payments", &payment_id)?;
 
-    log::info!("Ejecutando query de actualización dinámica: {}", query);
+    log::info!("Actualizando el pago: {}", record_id);
 
     // Ejecutar la consulta
-    match database.query(&query).await {
+    match merge_record(database.inner(), record_id, updated_data).await {
         Ok(_) => {
             log::info!("Pago actualizado correctamente: {}", payment_id);
             Ok(Status::Ok)
@@ -0,0 +1,337 @@
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::sql::Thing;
+use surrealdb::Surreal;
+use log::{info, warn, error};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+
+#[derive(Deserialize, Debug)]
+pub struct SessionAsRecord {
+    pub id: Thing,
+    pub user: Thing,
+    pub username: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SessionAsString {
+    pub id: String,
+    pub username: String,
+    pub ip: Option<String>,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+    pub expires_at: String,
+}
+
+// Resultado de validar un refresh token contra la tabla `sessions`
+pub enum RefreshCheck {
+    Valid(SessionAsRecord),
+    Invalid,
+    Reused(Thing),
+}
+
+fn generate_secret() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(48)
+        .map(char::from)
+        .collect()
+}
+
+// El refresh token tiene la forma `<id de la sesión>.<secreto>`; solo se guarda el hash del secreto
+fn split_refresh_token(refresh_token: &str) -> Option<(Thing, &str)> {
+    let (session_key, secret) = refresh_token.split_once('.')?;
+    if session_key.is_empty() || secret.is_empty() {
+        return None;
+    }
+    Some((Thing::from(("sessions", session_key)), secret))
+}
+
+pub async fn create_session(
+    database: &State<Surreal<Client>>,
+    user_id: Thing,
+    username: &str,
+    ip: Option<String>,
+) -> Result<(String, String), Status> {
+    let secret = generate_secret();
+    // El refresh token vence a los 7 días; después hay que iniciar sesión de nuevo
+    let query = "CREATE sessions CONTENT {
+            user: $user,
+            username: $username,
+            refresh_hash: crypto::sha256($secret),
+            ip: $ip,
+            revoked: false,
+            created_at: time::now(),
+            last_used_at: time::now(),
+            expires_at: time::now() + 7d
+        } RETURN id, user, username;";
+
+    let result = database
+        .query(query)
+        .bind(("user", user_id))
+        .bind(("username", username.to_string()))
+        .bind(("secret", secret.clone()))
+        .bind(("ip", ip))
+        .await;
+
+    match result {
+        Ok(mut results) => match results.take::<Option<SessionAsRecord>>(0) {
+            Ok(Some(session)) => {
+                let session_key = session.id.id.to_raw();
+                info!("Sesión {} creada para el usuario {}", session_key, username);
+                let refresh_token = format!("{}.{}", session_key, secret);
+                Ok((session_key, refresh_token))
+            }
+            Ok(None) => {
+                error!("La creación de la sesión no devolvió resultados.");
+                Err(Status::InternalServerError)
+            }
+            Err(err) => {
+                error!("Error al deserializar la sesión creada: {:?}", err);
+                Err(Status::InternalServerError)
+            }
+        },
+        Err(err) => {
+            error!("Error al crear la sesión para {}: {:?}", username, err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+pub async fn is_session_active(database: &State<Surreal<Client>>, session_key: &str) -> bool {
+    let query = "SELECT VALUE id FROM ONLY $session
+        WHERE revoked = false AND expires_at > time::now();";
+
+    match database
+        .query(query)
+        .bind(("session", Thing::from(("sessions", session_key))))
+        .await
+    {
+        Ok(mut results) => matches!(results.take::<Option<Thing>>(0), Ok(Some(_))),
+        Err(err) => {
+            error!("Error al consultar la sesión {}: {:?}", session_key, err);
+            false
+        }
+    }
+}
+
+pub async fn check_refresh_token(
+    database: &State<Surreal<Client>>,
+    refresh_token: &str,
+) -> Result<RefreshCheck, Status> {
+    let Some((session_id, secret)) = split_refresh_token(refresh_token) else {
+        return Ok(RefreshCheck::Invalid);
+    };
+
+    let query = "SELECT id, user, username, refresh_hash = crypto::sha256($secret) AS matches
+        FROM ONLY $session
+        WHERE revoked = false AND expires_at > time::now();";
+
+    #[derive(Deserialize)]
+    struct Candidate {
+        id: Thing,
+        user: Thing,
+        username: String,
+        matches: bool,
+    }
+
+    let mut results = database
+        .query(query)
+        .bind(("session", session_id.clone()))
+        .bind(("secret", secret.to_string()))
+        .await
+        .map_err(|err| {
+            error!("Error al validar el refresh token: {:?}", err);
+            Status::InternalServerError
+        })?;
+
+    match results.take::<Option<Candidate>>(0) {
+        Ok(Some(candidate)) if candidate.matches => Ok(RefreshCheck::Valid(SessionAsRecord {
+            id: candidate.id,
+            user: candidate.user,
+            username: candidate.username,
+        })),
+        // La sesión existe pero el secreto ya fue rotado: alguien reutilizó un token viejo
+        Ok(Some(_)) => Ok(RefreshCheck::Reused(session_id)),
+        Ok(None) => Ok(RefreshCheck::Invalid),
+        Err(err) => {
+            error!("Error al deserializar la sesión: {:?}", err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// Cambia el secreto solo si la sesión sigue con el de `refresh_token`. Devuelve `None` si otra
+// renovación ya lo rotó (o la sesión se revocó o venció) entre la validación y este UPDATE.
+pub async fn rotate_refresh_token(
+    database: &State<Surreal<Client>>,
+    session_id: Thing,
+    refresh_token: &str,
+) -> Result<Option<String>, Status> {
+    let Some((_, old_secret)) = split_refresh_token(refresh_token) else {
+        return Ok(None);
+    };
+    let secret = generate_secret();
+    let query = "UPDATE $session SET
+            refresh_hash = crypto::sha256($secret),
+            last_used_at = time::now()
+        WHERE refresh_hash = crypto::sha256($old_secret) AND revoked = false AND expires_at > time::now()
+        RETURN VALUE id;";
+
+    let rotated = match database
+        .query(query)
+        .bind(("session", session_id.clone()))
+        .bind(("secret", secret.clone()))
+        .bind(("old_secret", old_secret.to_string()))
+        .await
+    {
+        Ok(mut results) => results.take::<Vec<Thing>>(0).map_err(|err| {
+            error!("Error al rotar el refresh token de {}: {:?}", session_id, err);
+            Status::InternalServerError
+        })?,
+        Err(err) => {
+            error!("Error al rotar el refresh token de {}: {:?}", session_id, err);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    if rotated.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(format!("{}.{}", session_id.id.to_raw(), secret)))
+}
+
+pub async fn revoke_session(
+    database: &State<Surreal<Client>>,
+    session_id: Thing,
+    revoked_by: &str,
+) -> Result<Status, Status> {
+    let query = "UPDATE $session SET
+            revoked = true,
+            revoked_at = time::now(),
+            revoked_by = $revoked_by
+        WHERE revoked = false;";
+
+    match database
+        .query(query)
+        .bind(("session", session_id.clone()))
+        .bind(("revoked_by", revoked_by.to_string()))
+        .await
+    {
+        Ok(_) => {
+            info!("Sesión {} revocada por {}", session_id, revoked_by);
+            Ok(Status::Ok)
+        }
+        Err(err) => {
+            error!("Error al revocar la sesión {}: {:?}", session_id, err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+pub async fn get_user_sessions(
+    database: &State<Surreal<Client>>,
+    user_id: String,
+) -> Result<Json<Vec<SessionAsString>>, Status> {
+    let query = "SELECT
+            <string> id AS id,
+            username,
+            ip,
+            <string> created_at AS created_at,
+            <string> last_used_at AS last_used_at,
+            <string> expires_at AS expires_at
+        FROM sessions
+        WHERE user = $user AND revoked = false AND expires_at > time::now()
+        ORDER BY created_at DESC;";
+
+    match database
+        .query(query)
+        .bind(("user", Thing::from(("users", user_id.as_str()))))
+        .await
+    {
+        Ok(mut results) => match results.take::<Vec<SessionAsString>>(0) {
+            Ok(sessions) => Ok(Json(sessions)),
+            Err(err) => {
+                error!("Error al deserializar las sesiones: {:?}", err);
+                Err(Status::InternalServerError)
+            }
+        },
+        Err(err) => {
+            error!("Error al obtener las sesiones del usuario {}: {:?}", user_id, err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+pub async fn revoke_user_session(
+    database: &State<Surreal<Client>>,
+    user_id: String,
+    session_key: String,
+    revoked_by: &str,
+) -> Result<Status, Status> {
+    let query = "UPDATE $session SET
+            revoked = true,
+            revoked_at = time::now(),
+            revoked_by = $revoked_by
+        WHERE user = $user AND revoked = false
+        RETURN id;";
+
+    let result = database
+        .query(query)
+        .bind(("session", Thing::from(("sessions", session_key.as_str()))))
+        .bind(("user", Thing::from(("users", user_id.as_str()))))
+        .bind(("revoked_by", revoked_by.to_string()))
+        .await;
+
+    match result {
+        Ok(mut results) => match results.take::<Vec<Thing>>(0) {
+            Ok(revoked) if !revoked.is_empty() => {
+                info!("Sesión {} del usuario {} revocada por {}", session_key, user_id, revoked_by);
+                Ok(Status::Ok)
+            }
+            Ok(_) => {
+                warn!("La sesión {} no existe o no pertenece al usuario {}", session_key, user_id);
+                Err(Status::NotFound)
+            }
+            Err(err) => {
+                error!("Error al deserializar la sesión revocada: {:?}", err);
+                Err(Status::InternalServerError)
+            }
+        },
+        Err(err) => {
+            error!("Error al revocar la sesión {}: {:?}", session_key, err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+pub async fn revoke_all_user_sessions(
+    database: &State<Surreal<Client>>,
+    user_id: String,
+    revoked_by: &str,
+) -> Result<Status, Status> {
+    let query = "UPDATE sessions SET
+            revoked = true,
+            revoked_at = time::now(),
+            revoked_by = $revoked_by
+        WHERE user = $user AND revoked = false;";
+
+    match database
+        .query(query)
+        .bind(("user", Thing::from(("users", user_id.as_str()))))
+        .bind(("revoked_by", revoked_by.to_string()))
+        .await
+    {
+        Ok(_) => {
+            info!("Todas las sesiones del usuario {} revocadas por {}", user_id, revoked_by);
+            Ok(Status::Ok)
+        }
+        Err(err) => {
+            error!("Error al revocar las sesiones del usuario {}: {:?}", user_id, err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
@@ -0,0 +1,495 @@
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::{RecordId, Surreal};
+use log::{info, warn, error};
+use crate::exchange_rates::normalize_currency;
+use crate::money::Money;
+use crate::pricing::{PaymentMethod, Tender};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShiftStatus {
+    #[serde(rename = "open")]
+    Open,
+    #[serde(rename = "closed")]
+    Closed,
+}
+
+// Efectivo en una moneda, p. ej. el fondo de apertura
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CashAmount {
+    pub currency: String,
+    pub amount: Money,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OpenShiftRequest {
+    pub opening_float: Vec<CashAmount>,
+}
+
+// Entradas y salidas de efectivo que no son ventas: cambio traído del banco, pagos a proveedores…
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CashMovementKind {
+    #[serde(rename = "pay_in")]
+    PayIn,
+    #[serde(rename = "pay_out")]
+    PayOut,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CashMovementRequest {
+    pub kind: CashMovementKind,
+    pub currency: String,
+    pub amount: Money,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CashMovement {
+    pub kind: CashMovementKind,
+    pub currency: String,
+    pub amount: Money,
+    pub reason: String,
+    pub created_at: String,
+}
+
+// Billetes o monedas de una misma denominación contados al cerrar
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DenominationCount {
+    pub currency: String,
+    pub denomination: Money,
+    pub count: u32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CloseShiftRequest {
+    pub counted: Vec<DenominationCount>,
+    #[serde(default)]
+    pub notes: String,
+}
+
+// Arqueo de una moneda; `difference` positiva es sobrante y negativa faltante
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CashSummary {
+    pub currency: String,
+    pub opening_float: Money,
+    pub cash_sales: Money,
+    pub change_given: Money,
+    pub cash_refunds: Money,
+    pub pay_ins: Money,
+    pub pay_outs: Money,
+    pub expected: Money,
+    pub counted: Money,
+    pub difference: Money,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ShiftAsString {
+    pub id: String,
+    pub cashier: String,
+    pub status: ShiftStatus,
+    pub opened_at: String,
+    pub closed_at: Option<String>,
+    pub opening_float: Vec<CashAmount>,
+    #[serde(default)]
+    pub movements: Vec<CashMovement>,
+    #[serde(default)]
+    pub counted: Vec<DenominationCount>,
+    #[serde(default)]
+    pub summary: Vec<CashSummary>,
+    pub notes: Option<String>,
+}
+
+// Lo que aporta al arqueo una venta del turno; el vuelto se entrega en la moneda de la venta
+#[derive(Deserialize, Debug)]
+pub struct ShiftSale {
+    #[serde(default)]
+    pub tenders: Vec<Tender>,
+    pub change: Option<Money>,
+    pub currency: String,
+}
+
+// Devolución registrada durante el turno, reintegrada en la moneda de la venta
+#[derive(Deserialize, Debug)]
+pub struct ShiftRefund {
+    pub total: Money,
+    pub method: PaymentMethod,
+    pub currency: String,
+}
+
+const SHIFT_FIELDS: &str = "
+    <string> id AS id,
+    cashier,
+    status,
+    <string> opened_at AS opened_at,
+    IF closed_at != NONE THEN <string> closed_at END AS closed_at,
+    opening_float,
+    (SELECT kind, currency, amount, reason, <string> created_at AS created_at
+        FROM $parent.movements) AS movements,
+    counted ?? [] AS counted,
+    summary ?? [] AS summary,
+    notes";
+
+// Turno abierto del cajero; las ventas y devoluciones que registre quedan ligadas a él
+pub async fn find_open_shift(
+    database: &State<Surreal<Client>>,
+    cashier: &str,
+) -> Result<Option<RecordId>, Status> {
+    let query = "SELECT VALUE id FROM shifts WHERE cashier = $cashier AND status = 'open' LIMIT 1;";
+
+    match database.query(query).bind(("cashier", cashier.to_string())).await {
+        Ok(mut results) => results.take::<Option<RecordId>>(0).map_err(|err| {
+            error!("Error al deserializar el turno de {}: {:?}", cashier, err);
+            Status::InternalServerError
+        }),
+        Err(err) => {
+            error!("Error al consultar el turno de {}: {:?}", cashier, err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+async fn require_open_shift(
+    database: &State<Surreal<Client>>,
+    cashier: &str,
+) -> Result<RecordId, Status> {
+    find_open_shift(database, cashier).await?.ok_or_else(|| {
+        warn!("{} no tiene un turno abierto", cashier);
+        Status::NotFound
+    })
+}
+
+async fn load_shift(
+    database: &State<Surreal<Client>>,
+    shift_id: RecordId,
+) -> Result<Json<ShiftAsString>, Status> {
+    let query = format!("SELECT {} FROM ONLY $shift;", SHIFT_FIELDS);
+
+    match database.query(query).bind(("shift", shift_id.clone())).await {
+        Ok(mut results) => match results.take::<Option<ShiftAsString>>(0) {
+            Ok(Some(shift)) => Ok(Json(shift)),
+            Ok(None) => Err(Status::NotFound),
+            Err(err) => {
+                error!("Error al deserializar el turno {}: {:?}", shift_id, err);
+                Err(Status::InternalServerError)
+            }
+        },
+        Err(err) => {
+            error!("Error al consultar el turno {}: {:?}", shift_id, err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+pub async fn get_current_shift(
+    database: &State<Surreal<Client>>,
+    cashier: &str,
+) -> Result<Json<ShiftAsString>, Status> {
+    let shift_id = require_open_shift(database, cashier).await?;
+    load_shift(database, shift_id).await
+}
+
+// Debe coincidir con el mensaje del THROW de `open_shift`
+const SHIFT_ALREADY_OPEN: &str = "Turno ya abierto";
+
+pub async fn open_shift(
+    database: &State<Surreal<Client>>,
+    cashier: &str,
+    request: Json<OpenShiftRequest>,
+) -> Result<Json<ShiftAsString>, Status> {
+    let mut opening_float = request.into_inner().opening_float;
+    for cash in &mut opening_float {
+        cash.currency = normalize_currency(&cash.currency);
+        if cash.amount < Money::ZERO || cash.currency.is_empty() {
+            warn!("Fondo de apertura inválido: {} {}", cash.amount, cash.currency);
+            return Err(Status::UnprocessableEntity);
+        }
+    }
+
+    // La comprobación y el alta van juntas para que no queden dos turnos abiertos del mismo cajero
+    let query = format!(
+        "BEGIN TRANSACTION;
+        IF (SELECT VALUE id FROM shifts WHERE cashier = $cashier AND status = 'open') != [] {{
+            THROW '{}: ' + $cashier;
+        }};
+        CREATE shifts CONTENT {{
+            cashier: $cashier,
+            status: 'open',
+            opening_float: $opening_float,
+            movements: [],
+            opened_at: time::now()
+        }} RETURN id;
+        COMMIT TRANSACTION;",
+        SHIFT_ALREADY_OPEN
+    );
+
+    let mut response = database
+        .query(query)
+        .bind(("cashier", cashier.to_string()))
+        .bind(("opening_float", opening_float))
+        .await
+        .map_err(|err| {
+            error!("Error al abrir el turno de {}: {:?}", cashier, err);
+            Status::InternalServerError
+        })?;
+
+    let errors = response.take_errors();
+    if errors.values().any(|err| err.to_string().contains(SHIFT_ALREADY_OPEN)) {
+        warn!("{} ya tiene un turno abierto", cashier);
+        return Err(Status::Conflict);
+    }
+    if !errors.is_empty() {
+        error!("Error en la transacción de apertura del turno: {:?}", errors);
+        return Err(Status::InternalServerError);
+    }
+
+    let shift_id = match response.take::<Option<RecordId>>((1, "id")) {
+        Ok(Some(shift_id)) => shift_id,
+        Ok(None) => {
+            error!("La apertura del turno no devolvió su ID");
+            return Err(Status::InternalServerError);
+        }
+        Err(err) => {
+            error!("Error al deserializar el ID del turno: {:?}", err);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    info!("Turno {} abierto por {}", shift_id, cashier);
+    load_shift(database, shift_id).await
+}
+
+pub async fn add_cash_movement(
+    database: &State<Surreal<Client>>,
+    cashier: &str,
+    request: Json<CashMovementRequest>,
+) -> Result<Status, Status> {
+    let mut movement = request.into_inner();
+    movement.currency = normalize_currency(&movement.currency);
+    if movement.amount <= Money::ZERO || movement.currency.is_empty() {
+        warn!("Movimiento de caja inválido: {} {}", movement.amount, movement.currency);
+        return Err(Status::UnprocessableEntity);
+    }
+    if movement.reason.trim().is_empty() {
+        warn!("Movimiento de caja sin motivo");
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let shift_id = require_open_shift(database, cashier).await?;
+
+    let query = "UPDATE $shift SET movements += {
+            kind: $kind,
+            currency: $currency,
+            amount: $amount,
+            reason: $reason,
+            created_at: time::now()
+        } WHERE status = 'open';";
+
+    let result = database
+        .query(query)
+        .bind(("shift", shift_id.clone()))
+        .bind(("kind", movement.kind))
+        .bind(("currency", movement.currency.clone()))
+        .bind(("amount", movement.amount))
+        .bind(("reason", movement.reason))
+        .await;
+
+    match result.map(|response| response.check().err()) {
+        Ok(None) => {
+            info!("Movimiento {:?} de {} {} en el turno {}", movement.kind, movement.amount, movement.currency, shift_id);
+            Ok(Status::Created)
+        }
+        Ok(Some(err)) | Err(err) => {
+            error!("Error al registrar el movimiento en el turno {}: {:?}", shift_id, err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+fn summary_row<'a>(summary: &'a mut Vec<CashSummary>, currency: &str) -> &'a mut CashSummary {
+    let index = match summary.iter().position(|line| line.currency == currency) {
+        Some(index) => index,
+        None => {
+            summary.push(CashSummary {
+                currency: currency.to_string(),
+                opening_float: Money::ZERO,
+                cash_sales: Money::ZERO,
+                change_given: Money::ZERO,
+                cash_refunds: Money::ZERO,
+                pay_ins: Money::ZERO,
+                pay_outs: Money::ZERO,
+                expected: Money::ZERO,
+                counted: Money::ZERO,
+                difference: Money::ZERO,
+            });
+            summary.len() - 1
+        }
+    };
+    &mut summary[index]
+}
+
+// Efectivo esperado por moneda: fondo + efectivo recibido − vuelto − devoluciones en efectivo
+// + entradas − salidas, comparado con lo contado
+pub fn cash_summary(
+    opening_float: &[CashAmount],
+    movements: &[CashMovement],
+    sales: &[ShiftSale],
+    refunds: &[ShiftRefund],
+    counted: &[DenominationCount],
+) -> Vec<CashSummary> {
+    let mut summary: Vec<CashSummary> = Vec::new();
+
+    for cash in opening_float {
+        summary_row(&mut summary, &cash.currency).opening_float += cash.amount;
+    }
+    for sale in sales {
+        for tender in sale.tenders.iter().filter(|tender| tender.method == PaymentMethod::Cash) {
+            summary_row(&mut summary, &tender.currency).cash_sales += tender.amount;
+        }
+        if let Some(change) = sale.change.filter(|change| !change.is_zero()) {
+            summary_row(&mut summary, &sale.currency).change_given += change;
+        }
+    }
+    for refund in refunds.iter().filter(|refund| refund.method == PaymentMethod::Cash) {
+        summary_row(&mut summary, &refund.currency).cash_refunds += refund.total;
+    }
+    for movement in movements {
+        let line = summary_row(&mut summary, &movement.currency);
+        match movement.kind {
+            CashMovementKind::PayIn => line.pay_ins += movement.amount,
+            CashMovementKind::PayOut => line.pay_outs += movement.amount,
+        }
+    }
+    for count in counted {
+        summary_row(&mut summary, &count.currency).counted += count.denomination.times(count.count);
+    }
+
+    for line in &mut summary {
+        line.expected = line.opening_float + line.cash_sales - line.change_given - line.cash_refunds + line.pay_ins - line.pay_outs;
+        line.difference = line.counted - line.expected;
+    }
+    summary
+}
+
+pub async fn close_shift(
+    database: &State<Surreal<Client>>,
+    cashier: &str,
+    request: Json<CloseShiftRequest>,
+) -> Result<Json<ShiftAsString>, Status> {
+    let request = request.into_inner();
+    let mut counted = request.counted;
+    for count in &mut counted {
+        count.currency = normalize_currency(&count.currency);
+        if count.denomination <= Money::ZERO || count.currency.is_empty() {
+            warn!("Denominación inválida en el arqueo: {} {}", count.denomination, count.currency);
+            return Err(Status::UnprocessableEntity);
+        }
+    }
+
+    let shift_id = require_open_shift(database, cashier).await?;
+    let shift = load_shift(database, shift_id.clone()).await?.into_inner();
+
+    // Las ventas anuladas no dejaron dinero en la caja
+    let query = "SELECT tenders, change, currency FROM sales WHERE shift = $shift AND status != 'voided';
+        SELECT total, method, currency FROM refunds WHERE shift = $shift;";
+
+    let (sales, refunds) = match database.query(query).bind(("shift", shift_id.clone())).await {
+        Ok(mut results) => {
+            let sales = results.take::<Vec<ShiftSale>>(0);
+            let refunds = results.take::<Vec<ShiftRefund>>(1);
+            match (sales, refunds) {
+                (Ok(sales), Ok(refunds)) => (sales, refunds),
+                (Err(err), _) | (_, Err(err)) => {
+                    error!("Error al deserializar los movimientos del turno {}: {:?}", shift_id, err);
+                    return Err(Status::InternalServerError);
+                }
+            }
+        }
+        Err(err) => {
+            error!("Error al consultar los movimientos del turno {}: {:?}", shift_id, err);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    let summary = cash_summary(&shift.opening_float, &shift.movements, &sales, &refunds, &counted);
+    for line in summary.iter().filter(|line| !line.difference.is_zero()) {
+        warn!("Arqueo del turno {} en {}: diferencia de {}", shift_id, line.currency, line.difference);
+    }
+
+    let query = "UPDATE $shift SET
+            status = 'closed',
+            closed_at = time::now(),
+            counted = $counted,
+            summary = $summary,
+            notes = $notes
+        WHERE status = 'open';";
+
+    let result = database
+        .query(query)
+        .bind(("shift", shift_id.clone()))
+        .bind(("counted", counted))
+        .bind(("summary", summary))
+        .bind(("notes", request.notes))
+        .await;
+
+    match result.map(|response| response.check().err()) {
+        Ok(None) => {
+            info!("Turno {} cerrado por {}", shift_id, cashier);
+            load_shift(database, shift_id).await
+        }
+        Ok(Some(err)) | Err(err) => {
+            error!("Error al cerrar el turno {}: {:?}", shift_id, err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cash(amount: i64, currency: &str) -> Tender {
+        Tender {
+            method: PaymentMethod::Cash,
+            amount: Money::from_cents(amount),
+            currency: currency.to_string(),
+            reference: None,
+            exchange_rate: None,
+        }
+    }
+
+    #[test]
+    fn expected_cash_follows_each_currency() {
+        let opening_float = vec![CashAmount { currency: "NIO".to_string(), amount: Money::from_cents(100000) }];
+        let movements = vec![CashMovement {
+            kind: CashMovementKind::PayOut,
+            currency: "NIO".to_string(),
+            amount: Money::from_cents(5000),
+            reason: "Agua".to_string(),
+            created_at: String::new(),
+        }];
+        // Pagó 20 USD por una venta en córdobas y recibió el vuelto en córdobas
+        let mut card = cash(30000, "NIO");
+        card.method = PaymentMethod::Card;
+        let sales = vec![
+            ShiftSale { tenders: vec![cash(2000, "USD")], change: Some(Money::from_cents(13400)), currency: "NIO".to_string() },
+            ShiftSale { tenders: vec![card], change: None, currency: "NIO".to_string() },
+        ];
+        let refunds = vec![ShiftRefund { total: Money::from_cents(10000), method: PaymentMethod::Cash, currency: "NIO".to_string() }];
+        let counted = vec![
+            DenominationCount { currency: "NIO".to_string(), denomination: Money::from_cents(50000), count: 1 },
+            DenominationCount { currency: "NIO".to_string(), denomination: Money::from_cents(10000), count: 3 },
+            DenominationCount { currency: "USD".to_string(), denomination: Money::from_cents(2000), count: 1 },
+        ];
+
+        let summary = cash_summary(&opening_float, &movements, &sales, &refunds, &counted);
+
+        let nio = summary.iter().find(|line| line.currency == "NIO").unwrap();
+        assert_eq!(nio.expected, Money::from_cents(100000 - 13400 - 10000 - 5000));
+        assert_eq!(nio.difference, Money::from_cents(80000 - 71600));
+        let usd = summary.iter().find(|line| line.currency == "USD").unwrap();
+        assert_eq!((usd.expected, usd.difference), (Money::from_cents(2000), Money::ZERO));
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use chrono::{Datelike, NaiveDate};
+use log::{info, warn, error};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::Surreal;
+use crate::branches::branch_branding;
+use crate::business_time;
+use crate::config::AppConfig;
+use crate::mail::{enqueue_mail, MailConfig, MailKind, OutgoingMail};
+use crate::receipt_documents::escape_html;
+
+// Llaves de `months` en la tabla `payments`, en orden
+pub const MONTHS: [&str; 12] = [
+    "Enero", "Febrero", "Marzo", "Abril", "Mayo", "Junio",
+    "Julio", "Agosto", "Septiembre", "Octubre", "Noviembre", "Diciembre",
+];
+
+#[derive(Deserialize, Debug)]
+struct ClientPayments {
+    client: String,
+    fullname: Option<String>,
+    email: Option<String>,
+    months: Vec<HashMap<String, bool>>,
+}
+
+// Mes para el que se encolan los correos; sin datos, el mes en curso
+#[derive(Deserialize, Debug, Default)]
+pub struct MonthlyMailRequest {
+    pub year: Option<i32>,
+    pub month: Option<u32>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct MonthlyMailSummary {
+    pub queued: u32,
+    // Ya encolados antes para el mismo cliente y mes
+    pub already_queued: u32,
+    // Sin correo válido o, en los recordatorios, al día
+    pub skipped: u32,
+}
+
+// Meses de enero a `through` con si están pagados. `payments.months` guarda un solo objeto.
+pub fn month_statuses(months: &[HashMap<String, bool>], through: u32) -> Vec<(&'static str, bool)> {
+    let paid = months.first();
+    MONTHS
+        .iter()
+        .take(through as usize)
+        .map(|name| (*name, paid.and_then(|months| months.get(*name)).copied().unwrap_or(false)))
+        .collect()
+}
+
+fn monthly_mail(
+    kind: MailKind,
+    business_name: &str,
+    fullname: &str,
+    year: i32,
+    month: u32,
+    statuses: &[(&'static str, bool)],
+) -> (String, String, String) {
+    let month_name = MONTHS[month as usize - 1];
+    let pending = statuses.iter().filter(|(_, paid)| !paid).count();
+    let (subject, intro, rows, closing) = match kind {
+        MailKind::Reminder => (
+            format!("Recordatorio de pago {} {} - {}", month_name, year, business_name),
+            format!("Le recordamos que tiene mensualidades pendientes de {}:", year),
+            statuses.iter().filter(|(_, paid)| !paid).collect::<Vec<_>>(),
+            "Puede pagarlas en cualquiera de nuestras sucursales.".to_string(),
+        ),
+        _ => (
+            format!("Estado de cuenta {} {} - {}", month_name, year, business_name),
+            format!("Este es el estado de sus mensualidades de {} hasta {}:", year, month_name),
+            statuses.iter().collect::<Vec<_>>(),
+            if pending == 0 {
+                "Está al día. ¡Gracias!".to_string()
+            } else {
+                format!("Meses pendientes: {}", pending)
+            },
+        ),
+    };
+    let label = |paid: bool| if paid { "Pagado" } else { "Pendiente" };
+
+    let mut text = format!("Hola {}:\n\n{}\n\n", fullname, intro);
+    for (name, paid) in &rows {
+        text.push_str(&format!("  {:<12}{}\n", name, label(*paid)));
+    }
+    text.push_str(&format!("\n{}\n\n{}\n", closing, business_name));
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html lang=\"es\">\n<body style=\"font-family:Helvetica,Arial,sans-serif;color:#222\">\n<p>Hola {}:</p>\n<p>{}</p>\n<table>\n",
+        escape_html(fullname), escape_html(&intro)
+    );
+    for (name, paid) in &rows {
+        html.push_str(&format!("<tr><td style=\"padding-right:24px\">{}</td><td>{}</td></tr>\n", name, label(*paid)));
+    }
+    html.push_str(&format!("</table>\n<p>{}</p>\n<p>{}</p>\n</body>\n</html>\n", escape_html(&closing), escape_html(business_name)));
+
+    (subject, text, html)
+}
+
+// Encola el estado de cuenta o el recordatorio del mes para cada cliente activo con correo.
+// Cada cliente recibe uno solo por mes aunque se pida varias veces.
+pub async fn queue_monthly_mail(
+    database: &Surreal<Client>,
+    config: &AppConfig,
+    kind: MailKind,
+    year: i32,
+    month: u32,
+) -> Result<MonthlyMailSummary, Status> {
+    if !(1..=12).contains(&month) {
+        warn!("Mes inválido para los correos mensuales: {}", month);
+        return Err(Status::BadRequest);
+    }
+    let business_name = branch_branding(database, config, &config.branch).await?.branch.business_name;
+
+    let query = "SELECT <string> client_id AS client, client_id.fullname AS fullname, client_id.email AS email,
+            months ?? [] AS months
+        FROM payments WHERE year = $year AND client_id.is_active = true;";
+    let clients = match database.query(query).bind(("year", year)).await {
+        Ok(mut results) => results.take::<Vec<ClientPayments>>(0).map_err(|err| {
+            error!("Error al deserializar los pagos de {}: {:?}", year, err);
+            Status::InternalServerError
+        })?,
+        Err(err) => {
+            error!("Error al consultar los pagos de {}: {:?}", year, err);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    let mut summary = MonthlyMailSummary::default();
+    for client in clients {
+        let Some(email) = client.email.filter(|email| !email.trim().is_empty()) else {
+            summary.skipped += 1;
+            continue;
+        };
+        let statuses = month_statuses(&client.months, month);
+        if kind == MailKind::Reminder && statuses.iter().all(|(_, paid)| *paid) {
+            summary.skipped += 1;
+            continue;
+        }
+
+        let fullname = client.fullname.unwrap_or_default();
+        let (subject, text_body, html_body) = monthly_mail(kind, &business_name, &fullname, year, month, &statuses);
+        let client_key: String = client.client.split_once(':').map_or(client.client.as_str(), |(_, id)| id).chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+        let key = format!("{}_{}_{}_{:02}", kind.as_str(), client_key, year, month);
+        let mail = OutgoingMail {
+            kind,
+            recipient: email,
+            subject,
+            text_body,
+            html_body: Some(html_body),
+            reference: Some(client.client),
+        };
+
+        match enqueue_mail(database, config, Some(key), mail).await {
+            Ok(true) => summary.queued += 1,
+            Ok(false) => summary.already_queued += 1,
+            Err(status) if status == Status::UnprocessableEntity => summary.skipped += 1,
+            Err(status) => return Err(status),
+        }
+    }
+
+    info!(
+        "Correos '{}' de {}/{}: {} encolados, {} ya encolados, {} omitidos",
+        kind.as_str(), month, year, summary.queued, summary.already_queued, summary.skipped
+    );
+    Ok(summary)
+}
+
+pub async fn queue_monthly_mail_request(
+    database: &Surreal<Client>,
+    config: &AppConfig,
+    kind: MailKind,
+    request: Json<MonthlyMailRequest>,
+) -> Result<Json<MonthlyMailSummary>, Status> {
+    let today = business_time::today(config.timezone);
+    let request = request.into_inner();
+    let year = request.year.unwrap_or_else(|| today.year());
+    let month = request.month.unwrap_or_else(|| today.month());
+    queue_monthly_mail(database, config, kind, year, month).await.map(Json)
+}
+
+// Envíos automáticos del mes: a partir del día configurado se encolan una vez por mes.
+// Si otro servidor ya los encoló, las llaves por cliente y mes evitan duplicados.
+#[derive(Default)]
+pub struct MonthlySchedule {
+    statements: Option<(i32, u32)>,
+    reminders: Option<(i32, u32)>,
+}
+
+impl MonthlySchedule {
+    pub async fn run(&mut self, database: &Surreal<Client>, config: &AppConfig, settings: &MailConfig) {
+        let today = business_time::today(config.timezone);
+        run_once(&mut self.statements, settings.statement_day, today, database, config, MailKind::Statement).await;
+        run_once(&mut self.reminders, settings.reminder_day, today, database, config, MailKind::Reminder).await;
+    }
+}
+
+async fn run_once(
+    done: &mut Option<(i32, u32)>,
+    day: Option<u32>,
+    today: NaiveDate,
+    database: &Surreal<Client>,
+    config: &AppConfig,
+    kind: MailKind,
+) {
+    let period = (today.year(), today.month());
+    let due = day.is_some_and(|day| today.day() >= day);
+    if !due || *done == Some(period) {
+        return;
+    }
+    match queue_monthly_mail(database, config, kind, period.0, period.1).await {
+        Ok(_) => *done = Some(period),
+        Err(status) => error!("No se encolaron los correos '{}' del mes: {}", kind.as_str(), status),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn month_statuses_reads_paid_months_through_the_given_month() {
+        let months = vec![HashMap::from([("Enero".to_string(), true), ("Febrero".to_string(), false)])];
+        assert_eq!(month_statuses(&months, 3), vec![("Enero", true), ("Febrero", false), ("Marzo", false)]);
+        assert!(month_statuses(&[], 12).iter().all(|(_, paid)| !paid));
+    }
+}
@@ -0,0 +1,310 @@
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::Surreal;
+use log::{info, warn, error};
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use crate::money::Money;
+use crate::business_time;
+use crate::config::AppConfig;
+
+// Tasa que se aplica cuando el artículo no indica otra
+pub const DEFAULT_TAX_RATE: &str = "iva";
+
+// Cómo se trata el impuesto en el precio de un artículo
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TaxTreatment {
+    // El impuesto se suma al precio
+    #[serde(rename = "taxable")]
+    Taxable,
+    // Sin impuesto; es lo que tienen los artículos registrados antes del IVA
+    #[default]
+    #[serde(rename = "exempt")]
+    Exempt,
+    // El precio ya incluye el impuesto
+    #[serde(rename = "included")]
+    Included,
+}
+
+// `rate` es un porcentaje, p. ej. 15 para el IVA general
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaxRate {
+    pub code: String,
+    pub name: String,
+    pub rate: f64,
+}
+
+// Base imponible, impuesto y monto cobrado de una línea
+#[derive(Debug, Clone, Copy)]
+pub struct LineTax {
+    pub taxable_base: Money,
+    pub tax: Money,
+    pub line_total: Money,
+}
+
+// `amount` es el importe de la línea ya descontado
+pub fn line_tax(treatment: TaxTreatment, percentage: Decimal, amount: Money) -> LineTax {
+    match treatment {
+        TaxTreatment::Exempt => LineTax {
+            taxable_base: amount,
+            tax: Money::ZERO,
+            line_total: amount,
+        },
+        TaxTreatment::Taxable => {
+            let tax = amount.percent(percentage);
+            LineTax {
+                taxable_base: amount,
+                tax,
+                line_total: amount + tax,
+            }
+        }
+        TaxTreatment::Included => {
+            let taxable_base = amount.scale(Decimal::ONE_HUNDRED / (Decimal::ONE_HUNDRED + percentage));
+            LineTax {
+                taxable_base,
+                tax: amount - taxable_base,
+                line_total: amount,
+            }
+        }
+    }
+}
+
+pub async fn load_tax_rates(database: &State<Surreal<Client>>) -> Result<Vec<TaxRate>, Status> {
+    match database.query("SELECT code, name, rate FROM tax_rates ORDER BY code;").await {
+        Ok(mut results) => results.take(0).map_err(|err| {
+            error!("Error al deserializar las tasas de impuesto: {:?}", err);
+            Status::InternalServerError
+        }),
+        Err(err) => {
+            error!("Error al consultar las tasas de impuesto: {:?}", err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// Porcentaje de la tasa `code`; un artículo gravado con una tasa que no existe no se puede cobrar
+pub fn tax_percentage(rates: &[TaxRate], code: &str) -> Result<Decimal, Status> {
+    let Some(rate) = rates.iter().find(|rate| rate.code == code) else {
+        warn!("La tasa de impuesto '{}' no está configurada", code);
+        return Err(Status::UnprocessableEntity);
+    };
+    Decimal::from_f64(rate.rate).ok_or_else(|| {
+        warn!("Porcentaje inválido en la tasa de impuesto '{}': {}", code, rate.rate);
+        Status::UnprocessableEntity
+    })
+}
+
+pub async fn get_tax_rates(database: &State<Surreal<Client>>) -> Result<Json<Vec<TaxRate>>, Status> {
+    load_tax_rates(database).await.map(Json)
+}
+
+// Crea o corrige una tasa; el código es el ID del registro
+pub async fn set_tax_rate(
+    database: &State<Surreal<Client>>,
+    new_rate: Json<TaxRate>,
+) -> Result<Status, Status> {
+    let mut rate = new_rate.into_inner();
+    rate.code = rate.code.trim().to_lowercase();
+
+    if rate.code.is_empty() || rate.name.trim().is_empty() {
+        warn!("Tasa de impuesto sin código o nombre");
+        return Err(Status::UnprocessableEntity);
+    }
+    if !rate.rate.is_finite() || !(0.0..=100.0).contains(&rate.rate) {
+        warn!("Porcentaje inválido para la tasa '{}': {}", rate.code, rate.rate);
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let result = database
+        .query("UPSERT type::thing('tax_rates', $code) CONTENT $rate;")
+        .bind(("code", rate.code.clone()))
+        .bind(("rate", rate.clone()))
+        .await;
+
+    match result.map(|response| response.check().err()) {
+        Ok(None) => {
+            info!("Tasa de impuesto '{}' fijada en {}%", rate.code, rate.rate);
+            Ok(Status::Ok)
+        }
+        Ok(Some(err)) | Err(err) => {
+            error!("Error al guardar la tasa de impuesto: {:?}", err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// Línea de venta o de devolución tal como se lee para el reporte
+#[derive(Deserialize, Debug)]
+struct TaxedLine {
+    #[serde(default)]
+    tax_treatment: TaxTreatment,
+    #[serde(default)]
+    tax_rate: String,
+    #[serde(default)]
+    tax_percentage: f64,
+    #[serde(default)]
+    taxable_base: Option<Money>,
+    #[serde(default)]
+    tax: Money,
+    // Ventas: `line_total`; devoluciones: `amount`
+    #[serde(default, alias = "amount")]
+    line_total: Money,
+}
+
+#[derive(Deserialize, Debug)]
+struct TaxedDocument {
+    #[serde(default)]
+    items: Vec<TaxedLine>,
+    exchange_rate: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TaxSummaryLine {
+    pub tax_treatment: TaxTreatment,
+    pub tax_rate: String,
+    pub tax_percentage: f64,
+    pub sales_base: Money,
+    pub sales_tax: Money,
+    pub refunded_base: Money,
+    pub refunded_tax: Money,
+    pub net_base: Money,
+    pub net_tax: Money,
+}
+
+// Resumen para el contador, consolidado en la moneda base
+#[derive(Serialize, Debug)]
+pub struct TaxSummary {
+    pub start_date: String,
+    pub end_date: String,
+    pub currency: String,
+    pub lines: Vec<TaxSummaryLine>,
+    pub total_tax: Money,
+}
+
+fn add_to_summary(lines: &mut Vec<TaxSummaryLine>, document: &TaxedDocument, refund: bool) {
+    let factor = document.exchange_rate.and_then(Decimal::from_f64).unwrap_or(Decimal::ONE);
+
+    for item in &document.items {
+        let taxable_base = item.taxable_base.unwrap_or(item.line_total).scale(factor);
+        let tax = item.tax.scale(factor);
+
+        let index = match lines.iter().position(|line| {
+            line.tax_treatment == item.tax_treatment && line.tax_rate == item.tax_rate && line.tax_percentage == item.tax_percentage
+        }) {
+            Some(index) => index,
+            None => {
+                lines.push(TaxSummaryLine {
+                    tax_treatment: item.tax_treatment,
+                    tax_rate: item.tax_rate.clone(),
+                    tax_percentage: item.tax_percentage,
+                    sales_base: Money::ZERO,
+                    sales_tax: Money::ZERO,
+                    refunded_base: Money::ZERO,
+                    refunded_tax: Money::ZERO,
+                    net_base: Money::ZERO,
+                    net_tax: Money::ZERO,
+                });
+                lines.len() - 1
+            }
+        };
+
+        let line = &mut lines[index];
+        if refund {
+            line.refunded_base += taxable_base;
+            line.refunded_tax += tax;
+            line.net_base -= taxable_base;
+            line.net_tax -= tax;
+        } else {
+            line.sales_base += taxable_base;
+            line.sales_tax += tax;
+            line.net_base += taxable_base;
+            line.net_tax += tax;
+        }
+    }
+}
+
+// Impuesto cobrado entre dos fechas (`dd-mm-YYYY`, ambas incluidas), menos lo devuelto
+pub async fn get_tax_summary(
+    database: &State<Surreal<Client>>,
+    config: &AppConfig,
+    start_date: String,
+    end_date: String,
+) -> Result<Json<TaxSummary>, Status> {
+    let (from, to) = business_time::day_range(
+        config.timezone,
+        business_time::parse_day(&start_date)?,
+        business_time::parse_day(&end_date)?,
+    )?;
+
+    let query = "SELECT items, exchange_rate FROM sales
+            WHERE date >= $from AND date < $to AND (status ?? 'completed') != 'voided';
+        SELECT items, sale.exchange_rate AS exchange_rate FROM refunds
+            WHERE created_at >= $from AND created_at < $to;";
+
+    let (sales, refunds) = match database
+        .query(query)
+        .bind(("from", from))
+        .bind(("to", to))
+        .await
+    {
+        Ok(mut results) => {
+            let sales = results.take::<Vec<TaxedDocument>>(0);
+            let refunds = results.take::<Vec<TaxedDocument>>(1);
+            match (sales, refunds) {
+                (Ok(sales), Ok(refunds)) => (sales, refunds),
+                (Err(err), _) | (_, Err(err)) => {
+                    error!("Error al deserializar las ventas del reporte de impuestos: {:?}", err);
+                    return Err(Status::InternalServerError);
+                }
+            }
+        }
+        Err(err) => {
+            error!("Error al consultar las ventas del reporte de impuestos: {:?}", err);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    let mut lines: Vec<TaxSummaryLine> = Vec::new();
+    for sale in &sales {
+        add_to_summary(&mut lines, sale, false);
+    }
+    for refund in &refunds {
+        add_to_summary(&mut lines, refund, true);
+    }
+
+    let total_tax = lines.iter().map(|line| line.net_tax).sum();
+    info!("Reporte de impuestos del {} al {} generado", start_date, end_date);
+
+    Ok(Json(TaxSummary {
+        start_date,
+        end_date,
+        currency: config.base_currency.clone(),
+        lines,
+        total_tax,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn taxable_adds_and_included_extracts_tax() {
+        let amount = Money::from_cents(11500);
+        let added = line_tax(TaxTreatment::Taxable, Decimal::from(15), Money::from_cents(10000));
+        assert_eq!((added.taxable_base, added.tax, added.line_total), (Money::from_cents(10000), Money::from_cents(1500), amount));
+
+        let included = line_tax(TaxTreatment::Included, Decimal::from(15), amount);
+        assert_eq!((included.taxable_base, included.tax, included.line_total), (Money::from_cents(10000), Money::from_cents(1500), amount));
+    }
+
+    #[test]
+    fn exempt_lines_carry_no_tax() {
+        let exempt = line_tax(TaxTreatment::Exempt, Decimal::from(15), Money::from_cents(999));
+        assert_eq!(exempt.tax, Money::ZERO);
+        assert_eq!(exempt.line_total, Money::from_cents(999));
+    }
+}